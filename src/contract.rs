@@ -1,22 +1,63 @@
-use cosmwasm_std::{entry_point, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult};
+use cosmwasm_std::{
+    entry_point, Binary, Deps, DepsMut, Env, MessageInfo, Reply, Response, StdResult,
+};
 
 use crate::error::ContractError;
+use crate::events;
 use crate::execute;
-use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
+use crate::migrations;
+use crate::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
 use crate::query;
-use crate::state::{FEE_PARAMS, SELL_ORDER_SEQ};
+use crate::reply::ReplyId;
+use crate::state::{Config, PurchaseAllowance, CONFIG, FEE_PARAMS, GENESIS_IMPORT, SELL_ORDER_SEQ};
+use crate::validation;
+
+pub const CONTRACT_NAME: &str = "crates.io:regen-marketplace";
+pub const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[entry_point]
 pub fn instantiate(
-    deps: DepsMut,
-    _env: Env,
-    _info: MessageInfo,
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
-    FEE_PARAMS.save(deps.storage, &msg.fee_params)?;
+    validation::validate_fee_params(&msg.fee_params)?;
+    validation::validate_crank_reward_share(msg.crank_reward_share)?;
+
+    cw2::set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    let authority = match msg.authority {
+        Some(authority) => deps.api.addr_validate(&authority)?,
+        None => info.sender,
+    };
+    CONFIG.save(
+        deps.storage,
+        &Config {
+            authority,
+            ecocredit: msg.ecocredit,
+            crank_reward_share: msg.crank_reward_share,
+        },
+    )?;
+    FEE_PARAMS.save(deps.storage, &msg.fee_params, env.block.height)?;
     SELL_ORDER_SEQ.save(deps.storage, &0u64)?;
+    if msg.import_genesis {
+        GENESIS_IMPORT.save(deps.storage, &true)?;
+    }
 
-    Ok(Response::new().add_attribute("method", "instantiate"))
+    let mut response = Response::new();
+    if let Some(dump) = msg.state {
+        validation::validate_state_version(dump.version)?;
+        for records in dump.records {
+            let event = execute::import_records(deps.branch(), &env, records, false)?;
+            response = response.add_event(event);
+        }
+        if !GENESIS_IMPORT.may_load(deps.storage)?.unwrap_or(false) {
+            let sell_orders = execute::check_imported_sell_orders(deps.storage)?;
+            response = response.add_event(events::genesis_import_finalized(sell_orders));
+        }
+    }
+
+    Ok(response.add_attribute("method", "instantiate"))
 }
 
 #[entry_point]
@@ -26,6 +67,23 @@ pub fn execute(
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+    validation::validate_execute_msg(deps.as_ref(), &env, &msg)?;
+    let importing = GENESIS_IMPORT.may_load(deps.storage)?.unwrap_or(false);
+    match msg {
+        ExecuteMsg::ImportBatch { .. }
+        | ExecuteMsg::ImportState { .. }
+        | ExecuteMsg::Finalize {}
+            if !importing =>
+        {
+            return Err(ContractError::ImportFinished {})
+        }
+        ExecuteMsg::ImportBatch { .. }
+        | ExecuteMsg::ImportState { .. }
+        | ExecuteMsg::Finalize {} => {}
+        _ if importing => return Err(ContractError::ImportInProgress {}),
+        _ => {}
+    }
+
     match msg {
         ExecuteMsg::Sell { orders } => execute::sell(deps, env, info, orders),
         ExecuteMsg::UpdateSellOrders { updates } => {
@@ -34,12 +92,16 @@ pub fn execute(
         ExecuteMsg::CancelSellOrder { sell_order_id } => {
             execute::cancel_sell_order(deps, env, info, sell_order_id)
         }
-        ExecuteMsg::BuyDirect { orders } => execute::buy_direct(deps, env, info, orders),
+        ExecuteMsg::BuyDirect {
+            orders,
+            use_balance,
+        } => execute::buy_direct(deps, env, info, orders, use_balance),
         ExecuteMsg::AddAllowedDenom {
             bank_denom,
             display_denom,
             exponent,
-        } => execute::add_allowed_denom(deps, env, info, bank_denom, display_denom, exponent),
+            kind,
+        } => execute::add_allowed_denom(deps, env, info, bank_denom, display_denom, exponent, kind),
         ExecuteMsg::RemoveAllowedDenom { denom } => {
             execute::remove_allowed_denom(deps, env, info, denom)
         }
@@ -47,6 +109,166 @@ pub fn execute(
         ExecuteMsg::GovSendFromFeePool { recipient, coins } => {
             execute::gov_send_from_fee_pool(deps, env, info, recipient, coins)
         }
+        ExecuteMsg::BuyWithSignedOrder {
+            order,
+            signature,
+            pubkey,
+            buy,
+        } => execute::buy_with_signed_order(deps, env, info, order, signature, pubkey, buy),
+        ExecuteMsg::CancelSignedOrders { nonces } => {
+            execute::cancel_signed_orders(deps, env, info, nonces)
+        }
+        ExecuteMsg::Pause { scope } => execute::pause(deps, env, info, scope),
+        ExecuteMsg::Unpause { scope } => execute::unpause(deps, env, info, scope),
+        ExecuteMsg::Deposit {} => execute::deposit(deps, env, info),
+        ExecuteMsg::Withdraw { coins } => execute::withdraw(deps, env, info, coins),
+        ExecuteMsg::GrantPurchaseAllowance {
+            spender,
+            denom,
+            limit,
+            expiration,
+            allowed_batches,
+        } => execute::grant_purchase_allowance(
+            deps,
+            env,
+            info,
+            spender,
+            PurchaseAllowance {
+                denom,
+                remaining: limit,
+                expiration,
+                allowed_batches,
+            },
+        ),
+        ExecuteMsg::RevokeAllowance { spender } => {
+            execute::revoke_allowance(deps, env, info, spender)
+        }
+        ExecuteMsg::MakeOffer {
+            sell_order_id,
+            quantity,
+            price,
+            expiration,
+            disable_auto_retire,
+            merkle_proof,
+            on_delivery_failure,
+        } => execute::make_offer(
+            deps,
+            env,
+            info,
+            sell_order_id,
+            quantity,
+            price,
+            expiration,
+            disable_auto_retire,
+            merkle_proof,
+            on_delivery_failure,
+        ),
+        ExecuteMsg::CounterOffer {
+            offer_id,
+            quantity,
+            price,
+            expiration,
+        } => execute::counter_offer(deps, env, info, offer_id, quantity, price, expiration),
+        ExecuteMsg::AcceptOffer { offer_id } => execute::accept_offer(deps, env, info, offer_id),
+        ExecuteMsg::RejectOffer { offer_id } => execute::reject_offer(deps, env, info, offer_id),
+        ExecuteMsg::PlaceCollectionBid {
+            criteria,
+            quantity,
+            price,
+            expiration,
+            disable_auto_retire,
+            retirement_jurisdiction,
+            retirement_reason,
+            on_delivery_failure,
+        } => execute::place_collection_bid(
+            deps,
+            env,
+            info,
+            criteria,
+            quantity,
+            price,
+            expiration,
+            disable_auto_retire,
+            retirement_jurisdiction,
+            retirement_reason,
+            on_delivery_failure,
+        ),
+        ExecuteMsg::CancelCollectionBid { bid_id } => {
+            execute::cancel_collection_bid(deps, env, info, bid_id)
+        }
+        ExecuteMsg::FillCollectionBid {
+            bid_id,
+            sell_order_id,
+            batch_denom,
+            quantity,
+        } => execute::fill_collection_bid(
+            deps,
+            env,
+            info,
+            bid_id,
+            sell_order_id,
+            batch_denom,
+            quantity,
+        ),
+        ExecuteMsg::SellBundle {
+            items,
+            price,
+            expiration,
+        } => execute::sell_bundle(deps, env, info, items, price, expiration),
+        ExecuteMsg::CancelBundle { bundle_id } => {
+            execute::cancel_bundle(deps, env, info, bundle_id)
+        }
+        ExecuteMsg::BuyBundle {
+            bundle_id,
+            max_fee_amount,
+            retirement_options,
+            beneficiary,
+            use_balance,
+        } => execute::buy_bundle(
+            deps,
+            env,
+            info,
+            bundle_id,
+            max_fee_amount,
+            retirement_options,
+            beneficiary,
+            use_balance,
+        ),
+        ExecuteMsg::Receive(receive) => execute::receive(deps, env, info, receive),
+        ExecuteMsg::BatchReceive(receive) => execute::batch_receive(deps, env, info, receive),
+        ExecuteMsg::BuyDirectFrom { owner, orders } => {
+            execute::buy_direct_from(deps, env, info, owner, orders)
+        }
+        ExecuteMsg::Marketplace(msg) => execute::marketplace(deps, env, info, msg),
+        ExecuteMsg::ImportBatch { genesis } => execute::import_batch(deps, env, info, genesis),
+        ExecuteMsg::ImportState {
+            records,
+            import_authority,
+            ..
+        } => execute::import_state(deps, env, info, records, import_authority),
+        ExecuteMsg::Finalize {} => execute::finalize_import(deps, env, info),
+        ExecuteMsg::Crank { limit } => execute::crank(deps, env, info, limit),
+        ExecuteMsg::GovSetCrankRewardShare { share } => {
+            execute::gov_set_crank_reward_share(deps, env, info, share)
+        }
+        ExecuteMsg::AddHook {
+            event,
+            contract,
+            required,
+        } => execute::add_hook(deps, env, info, event, contract, required),
+        ExecuteMsg::RemoveHook { event, contract } => {
+            execute::remove_hook(deps, env, info, event, contract)
+        }
+        ExecuteMsg::AddCreditToken {
+            batch_denom,
+            asset,
+            custodial,
+        } => execute::add_credit_token(deps, env, info, batch_denom, asset, custodial),
+        ExecuteMsg::RemoveCreditToken { asset } => {
+            execute::remove_credit_token(deps, env, info, asset)
+        }
+        ExecuteMsg::RetrySettlement { id } => execute::retry_settlement(deps, env, id),
+        ExecuteMsg::RefundSettlement { id } => execute::refund_settlement(deps, env, info, id),
     }
 }
 
@@ -54,19 +276,107 @@ pub fn execute(
 pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::SellOrder { sell_order_id } => query::sell_order(deps, sell_order_id),
-        QueryMsg::SellOrders { start_after, limit } => query::sell_orders(deps, start_after, limit),
+        QueryMsg::SellOrders {
+            start_after,
+            limit,
+            include_private,
+        } => query::sell_orders(deps, start_after, limit, include_private),
         QueryMsg::SellOrdersByBatch {
             batch_denom,
             start_after,
             limit,
-        } => query::sell_orders_by_batch(deps, batch_denom, start_after, limit),
+            include_private,
+        } => query::sell_orders_by_batch(deps, batch_denom, start_after, limit, include_private),
         QueryMsg::SellOrdersBySeller {
             seller,
             start_after,
             limit,
-        } => query::sell_orders_by_seller(deps, seller, start_after, limit),
+            include_private,
+        } => query::sell_orders_by_seller(deps, seller, start_after, limit, include_private),
         QueryMsg::AllowedDenoms { start_after, limit } => {
             query::allowed_denoms(deps, start_after, limit)
         }
+        QueryMsg::Config {} => query::config(deps),
+        QueryMsg::Pauses {} => query::pauses(deps),
+        QueryMsg::SignedOrder { seller, nonce } => query::signed_order(deps, seller, nonce),
+        QueryMsg::SignedOrderSignBytes { order } => query::signed_order_sign_bytes(order),
+        QueryMsg::Trade { trade_id } => query::trade(deps, trade_id),
+        QueryMsg::Trades { start_after, limit } => query::trades(deps, start_after, limit),
+        QueryMsg::TradesByBeneficiary {
+            beneficiary,
+            start_after,
+            limit,
+        } => query::trades_by_beneficiary(deps, beneficiary, start_after, limit),
+        QueryMsg::PurchaseAllowance { owner, spender } => {
+            query::purchase_allowance(deps, owner, spender)
+        }
+        QueryMsg::Offer { offer_id } => query::offer(deps, offer_id),
+        QueryMsg::OffersForOrder {
+            sell_order_id,
+            start_after,
+            limit,
+        } => query::offers_for_order(deps, sell_order_id, start_after, limit),
+        QueryMsg::OffersByBuyer {
+            buyer,
+            start_after,
+            limit,
+        } => query::offers_by_buyer(deps, buyer, start_after, limit),
+        QueryMsg::CollectionBid { bid_id } => query::collection_bid(deps, bid_id),
+        QueryMsg::CollectionBids { start_after, limit } => {
+            query::collection_bids(deps, start_after, limit)
+        }
+        QueryMsg::Bundle { bundle_id } => query::bundle(deps, bundle_id),
+        QueryMsg::Bundles { start_after, limit } => query::bundles(deps, start_after, limit),
+        QueryMsg::BundlesBySeller {
+            seller,
+            start_after,
+            limit,
+        } => query::bundles_by_seller(deps, seller, start_after, limit),
+        QueryMsg::Balance { address } => query::balance(deps, address),
+        QueryMsg::Balances { start_after, limit } => query::balances(deps, start_after, limit),
+        QueryMsg::SellOrderInfo { sell_order_id } => query::sell_order_info(deps, sell_order_id),
+        QueryMsg::SellOrderInfosByBatch {
+            batch_denom,
+            start_after,
+            limit,
+        } => query::sell_order_infos_by_batch(deps, batch_denom, start_after, limit),
+        QueryMsg::SellOrderAtHeight {
+            sell_order_id,
+            height,
+        } => query::sell_order_at_height(deps, sell_order_id, height),
+        QueryMsg::SellOrdersByBatchAtHeight {
+            batch_denom,
+            height,
+            start_after,
+            limit,
+        } => query::sell_orders_by_batch_at_height(deps, batch_denom, height, start_after, limit),
+        QueryMsg::FeeParamsAtHeight { height } => query::fee_params_at_height(deps, height),
+        QueryMsg::Hooks {} => query::hooks(deps),
+        QueryMsg::CreditTokens { start_after, limit } => {
+            query::credit_tokens(deps, start_after, limit)
+        }
+        QueryMsg::FailedSettlements { start_after, limit } => {
+            query::failed_settlements(deps, start_after, limit)
+        }
+        QueryMsg::ExportState {
+            section,
+            start_after,
+            limit,
+        } => query::export_state(deps, section, start_after, limit),
+    }
+}
+
+#[entry_point]
+pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match ReplyId::try_from(msg.id)? {
+        ReplyId::Hook { hook_id } => execute::hook_failed(deps, env, hook_id, msg.result),
+        ReplyId::Delivery { trade_id } | ReplyId::Retirement { trade_id } => {
+            execute::settlement_replied(deps, env, trade_id, msg.result)
+        }
     }
 }
+
+#[entry_point]
+pub fn migrate(deps: DepsMut, env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    migrations::migrate(deps, env, msg)
+}
@@ -0,0 +1,43 @@
+//! The subset of the cw1155 multi-token interface the marketplace uses to
+//! escrow, deliver and burn credits held as cw1155 tokens.
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Binary, Uint128};
+
+/// Sent by a cw1155 contract to the marketplace when tokens are transferred to
+/// it with a message.
+#[cw_serde]
+pub struct Cw1155ReceiveMsg {
+    /// Account that executed the transfer.
+    pub operator: String,
+    /// Previous owner of the tokens, unset when they were minted.
+    pub from: Option<String>,
+    pub token_id: String,
+    pub amount: Uint128,
+    pub msg: Binary,
+}
+
+/// Like `Cw1155ReceiveMsg`, for several token ids at once.
+#[cw_serde]
+pub struct Cw1155BatchReceiveMsg {
+    pub operator: String,
+    pub from: Option<String>,
+    pub batch: Vec<(String, Uint128)>,
+    pub msg: Binary,
+}
+
+#[cw_serde]
+pub enum Cw1155ExecuteMsg {
+    SendFrom {
+        from: String,
+        to: String,
+        token_id: String,
+        value: Uint128,
+        msg: Option<Binary>,
+    },
+    BurnFrom {
+        from: String,
+        token_id: String,
+        value: Uint128,
+    },
+}
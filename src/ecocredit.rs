@@ -0,0 +1,307 @@
+//! Adapter for the Regen `regen.ecocredit.v1` module. Credits are moved with
+//! Stargate messages and batches are looked up with Stargate queries, both
+//! protobuf encoded.
+//!
+//! The marketplace does not hold native credits: sellers grant it an authz
+//! authorization for `MsgSend`, and fills send the credits from the seller to
+//! the buyer with `MsgExec`.
+
+use std::str::FromStr;
+
+use cosmwasm_std::{
+    to_json_vec, Addr, Binary, ContractResult, CosmosMsg, Decimal, Empty, QuerierWrapper,
+    QueryRequest, StdError, StdResult, SystemResult, Uint128,
+};
+use prost::Message;
+
+use crate::state::{BatchMetadata, Retirement};
+
+pub const MSG_SEND_TYPE_URL: &str = "/regen.ecocredit.v1.MsgSend";
+pub const MSG_RETIRE_TYPE_URL: &str = "/regen.ecocredit.v1.MsgRetire";
+pub const MSG_EXEC_TYPE_URL: &str = "/cosmos.authz.v1beta1.MsgExec";
+pub const BATCH_QUERY_PATH: &str = "/regen.ecocredit.v1.Query/Batch";
+pub const BALANCE_QUERY_PATH: &str = "/regen.ecocredit.v1.Query/Balance";
+pub const PROJECT_QUERY_PATH: &str = "/regen.ecocredit.v1.Query/Project";
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Any {
+    #[prost(string, tag = "1")]
+    pub type_url: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct MsgExec {
+    #[prost(string, tag = "1")]
+    pub grantee: String,
+    #[prost(message, repeated, tag = "2")]
+    pub msgs: Vec<Any>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct MsgSend {
+    #[prost(string, tag = "1")]
+    pub sender: String,
+    #[prost(string, tag = "2")]
+    pub recipient: String,
+    #[prost(message, repeated, tag = "3")]
+    pub credits: Vec<SendCredits>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct SendCredits {
+    #[prost(string, tag = "1")]
+    pub batch_denom: String,
+    #[prost(string, tag = "2")]
+    pub tradable_amount: String,
+    #[prost(string, tag = "3")]
+    pub retired_amount: String,
+    #[prost(string, tag = "4")]
+    pub retirement_jurisdiction: String,
+    #[prost(string, tag = "5")]
+    pub retirement_reason: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct MsgRetire {
+    #[prost(string, tag = "1")]
+    pub owner: String,
+    #[prost(message, repeated, tag = "2")]
+    pub credits: Vec<Credits>,
+    #[prost(string, tag = "3")]
+    pub jurisdiction: String,
+    #[prost(string, tag = "4")]
+    pub reason: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Credits {
+    #[prost(string, tag = "1")]
+    pub batch_denom: String,
+    #[prost(string, tag = "2")]
+    pub amount: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct QueryBatchRequest {
+    #[prost(string, tag = "1")]
+    pub batch_denom: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct QueryBatchResponse {
+    #[prost(message, optional, tag = "1")]
+    pub batch: Option<BatchInfo>,
+}
+
+/// The fields of a batch the marketplace reads; dates are skipped.
+#[derive(Clone, PartialEq, Message)]
+pub struct BatchInfo {
+    #[prost(string, tag = "1")]
+    pub issuer: String,
+    #[prost(string, tag = "2")]
+    pub project_id: String,
+    #[prost(string, tag = "3")]
+    pub denom: String,
+    #[prost(string, tag = "4")]
+    pub metadata: String,
+    #[prost(bool, tag = "8")]
+    pub open: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct QueryProjectRequest {
+    #[prost(string, tag = "1")]
+    pub project_id: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct QueryProjectResponse {
+    #[prost(message, optional, tag = "1")]
+    pub project: Option<ProjectInfo>,
+}
+
+/// The fields of a project the marketplace reads.
+#[derive(Clone, PartialEq, Message)]
+pub struct ProjectInfo {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, tag = "4")]
+    pub jurisdiction: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct QueryBalanceRequest {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    #[prost(string, tag = "2")]
+    pub batch_denom: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct QueryBalanceResponse {
+    #[prost(message, optional, tag = "1")]
+    pub balance: Option<BatchBalanceInfo>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct BatchBalanceInfo {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    #[prost(string, tag = "2")]
+    pub batch_denom: String,
+    #[prost(string, tag = "3")]
+    pub tradable_amount: String,
+    #[prost(string, tag = "4")]
+    pub retired_amount: String,
+    #[prost(string, tag = "5")]
+    pub escrowed_amount: String,
+}
+
+/// `MsgSend` of `amount` credits, tradable or retired in the recipient's name.
+pub fn send(
+    sender: &Addr,
+    recipient: &Addr,
+    batch_denom: &str,
+    amount: Uint128,
+    retirement: Option<&Retirement>,
+) -> MsgSend {
+    let (tradable_amount, retired_amount) = match retirement {
+        Some(_) => (String::new(), amount.to_string()),
+        None => (amount.to_string(), String::new()),
+    };
+    MsgSend {
+        sender: sender.to_string(),
+        recipient: recipient.to_string(),
+        credits: vec![SendCredits {
+            batch_denom: batch_denom.to_string(),
+            tradable_amount,
+            retired_amount,
+            retirement_jurisdiction: retirement
+                .and_then(|retirement| retirement.jurisdiction.clone())
+                .unwrap_or_default(),
+            retirement_reason: retirement
+                .and_then(|retirement| retirement.reason.clone())
+                .unwrap_or_default(),
+        }],
+    }
+}
+
+/// `MsgRetire` of credits held by `owner`.
+pub fn retire(
+    owner: &Addr,
+    batch_denom: &str,
+    amount: Uint128,
+    retirement: &Retirement,
+) -> CosmosMsg {
+    let msg = MsgRetire {
+        owner: owner.to_string(),
+        credits: vec![Credits {
+            batch_denom: batch_denom.to_string(),
+            amount: amount.to_string(),
+        }],
+        jurisdiction: retirement.jurisdiction.clone().unwrap_or_default(),
+        reason: retirement.reason.clone().unwrap_or_default(),
+    };
+    stargate(MSG_RETIRE_TYPE_URL, &msg)
+}
+
+/// Runs `msg` with the authorization its sender granted to `grantee`.
+pub fn exec(grantee: &Addr, msg: MsgSend) -> CosmosMsg {
+    let msg = MsgExec {
+        grantee: grantee.to_string(),
+        msgs: vec![Any {
+            type_url: MSG_SEND_TYPE_URL.to_string(),
+            value: msg.encode_to_vec(),
+        }],
+    };
+    stargate(MSG_EXEC_TYPE_URL, &msg)
+}
+
+fn stargate(type_url: &str, msg: &impl Message) -> CosmosMsg {
+    CosmosMsg::Stargate {
+        type_url: type_url.to_string(),
+        value: Binary::from(msg.encode_to_vec()),
+    }
+}
+
+pub fn query_batch(querier: &QuerierWrapper, batch_denom: &str) -> StdResult<BatchInfo> {
+    let request = QueryBatchRequest {
+        batch_denom: batch_denom.to_string(),
+    };
+    let response: QueryBatchResponse = query(querier, BATCH_QUERY_PATH, &request)?;
+    response
+        .batch
+        .ok_or_else(|| StdError::not_found(format!("batch {}", batch_denom)))
+}
+
+pub fn query_project(querier: &QuerierWrapper, project_id: &str) -> StdResult<ProjectInfo> {
+    let request = QueryProjectRequest {
+        project_id: project_id.to_string(),
+    };
+    let response: QueryProjectResponse = query(querier, PROJECT_QUERY_PATH, &request)?;
+    response
+        .project
+        .ok_or_else(|| StdError::not_found(format!("project {}", project_id)))
+}
+
+/// Class, project and vintage year of a batch, read from its denom
+/// `<class>-<project seq>-<start date>-<end date>-<batch seq>` with dates as
+/// `YYYYMMDD`. The project jurisdiction is left empty.
+pub fn parse_batch_denom(batch_denom: &str) -> Option<BatchMetadata> {
+    let mut parts = batch_denom.rsplitn(4, '-');
+    let seq = parts.next()?;
+    let end_date = parts.next()?;
+    let start_date = parts.next()?;
+    let project_id = parts.next()?;
+    let (class_id, _) = project_id.rsplit_once('-')?;
+    let is_date = |date: &str| date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit());
+    if class_id.is_empty() || seq.is_empty() || !is_date(start_date) || !is_date(end_date) {
+        return None;
+    }
+    Some(BatchMetadata {
+        class_id: class_id.to_string(),
+        project_id: project_id.to_string(),
+        project_jurisdiction: String::new(),
+        vintage_year: start_date[..4].parse().ok()?,
+    })
+}
+
+/// Tradable credits of `batch_denom` held by `address`.
+pub fn query_tradable_balance(
+    querier: &QuerierWrapper,
+    address: &Addr,
+    batch_denom: &str,
+) -> StdResult<Decimal> {
+    let request = QueryBalanceRequest {
+        address: address.to_string(),
+        batch_denom: batch_denom.to_string(),
+    };
+    let response: QueryBalanceResponse = query(querier, BALANCE_QUERY_PATH, &request)?;
+    match response.balance {
+        Some(balance) if !balance.tradable_amount.is_empty() => {
+            Decimal::from_str(&balance.tradable_amount)
+        }
+        _ => Ok(Decimal::zero()),
+    }
+}
+
+fn query<T: Message + Default>(
+    querier: &QuerierWrapper,
+    path: &str,
+    request: &impl Message,
+) -> StdResult<T> {
+    let request: QueryRequest<Empty> = QueryRequest::Stargate {
+        path: path.to_string(),
+        data: Binary::from(request.encode_to_vec()),
+    };
+    let response = match querier.raw_query(&to_json_vec(&request)?) {
+        SystemResult::Ok(ContractResult::Ok(response)) => response,
+        SystemResult::Ok(ContractResult::Err(err)) => {
+            return Err(StdError::generic_err(format!("{}: {}", path, err)))
+        }
+        SystemResult::Err(err) => return Err(StdError::generic_err(format!("{}: {}", path, err))),
+    };
+    T::decode(response.as_slice()).map_err(|err| StdError::parse_err(path, err))
+}
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{Coin, StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -20,5 +20,174 @@ pub enum ContractError {
 
     #[error("Insufficient sell order quantity")]
     InsufficientSellOrderQuantity,
+
+    #[error("Denom {denom} is not allowed")]
+    DenomNotAllowed { denom: String },
+
+    #[error("Bid price denom {found} does not match sell order denom {expected}")]
+    BidDenomMismatch { expected: String, found: String },
+
+    #[error(
+        "Sell order {sell_order_id} changed: expected version {expected_version}, found {version}"
+    )]
+    SellOrderChanged {
+        sell_order_id: u64,
+        expected_version: u64,
+        version: u64,
+    },
+
+    #[error("Sell order {sell_order_id} has expired")]
+    SellOrderExpired { sell_order_id: u64 },
+
+    #[error("{buyer} is not a designated buyer of sell order {sell_order_id}")]
+    BuyerNotAllowed { sell_order_id: u64, buyer: String },
+
+    #[error("Invalid signed order: {reason}")]
+    InvalidSignedOrder { reason: String },
+
+    #[error("Signed order {nonce} has expired")]
+    SignedOrderExpired { nonce: u64 },
+
+    #[error("Signed order {nonce} was cancelled")]
+    SignedOrderCancelled { nonce: u64 },
+
+    #[error("{spender} has no purchase allowance from {owner}")]
+    NoPurchaseAllowance { owner: String, spender: String },
+
+    #[error("Purchase allowance from {owner} has expired")]
+    PurchaseAllowanceExpired { owner: String },
+
+    #[error("Purchase allowance exceeded: {remaining} left, {cost} required")]
+    PurchaseAllowanceExceeded { remaining: Coin, cost: Coin },
+
+    #[error("Batch {batch_denom} is not covered by the purchase allowance")]
+    BatchNotAllowed { batch_denom: String },
+
+    #[error("Attached funds {found} do not match the cost {expected}")]
+    InvalidFunds { expected: String, found: String },
+
+    #[error("Insufficient balance: {balance} available, {required} required")]
+    InsufficientBalance { balance: Coin, required: Coin },
+
+    #[error("Offer {offer_id} has expired")]
+    OfferExpired { offer_id: u64 },
+
+    #[error("Offer {offer_id} awaits the other party")]
+    OwnOfferTerms { offer_id: u64 },
+
+    #[error("Collection bid {bid_id} has expired")]
+    CollectionBidExpired { bid_id: u64 },
+
+    #[error("Collection bid {bid_id} wants only {remaining} more credits")]
+    CollectionBidQuantityExceeded { bid_id: u64, remaining: Uint128 },
+
+    #[error("Sell order {sell_order_id} does not match the criteria of collection bid {bid_id}")]
+    CollectionBidMismatch { bid_id: u64, sell_order_id: u64 },
+
+    #[error("Jurisdiction criteria need the Regen ecocredit adapter")]
+    JurisdictionsUnavailable {},
+
+    #[error("Bundle {bundle_id} has expired")]
+    BundleExpired { bundle_id: u64 },
+
+    #[error("Bundle {bundle_id} has no batch {batch_denom}")]
+    BatchNotInBundle { bundle_id: u64, batch_denom: String },
+
+    #[error("Sellers cannot buy their own listings")]
+    SelfTrade {},
+
+    #[error("Action cannot be paid for with cw20 tokens")]
+    NotPayable {},
+
+    #[error("Sell order {sell_order_id} holds escrowed credits; list more as a new order")]
+    EscrowedQuantityIncrease { sell_order_id: u64 },
+
+    #[error("Batch {batch_denom} does not exist")]
+    UnknownBatch { batch_denom: String },
+
+    #[error("Only {available} tradable credits of {batch_denom} are available")]
+    InsufficientCredits {
+        batch_denom: String,
+        available: String,
+    },
+
+    #[error("Trading opens once the genesis import is finalized")]
+    ImportInProgress {},
+
+    #[error("No genesis import is in progress")]
+    ImportFinished {},
+
+    #[error("Invalid genesis {record}: {reason}")]
+    InvalidGenesis { record: String, reason: String },
+
+    #[error("Unsupported state export version {version}")]
+    UnsupportedExportVersion { version: u32 },
+
+    #[error("{contract} is already hooked to {event}")]
+    HookExists { event: String, contract: String },
+
+    #[error("{contract} is not hooked to {event}")]
+    HookNotFound { event: String, contract: String },
+
+    #[error("{token} is not registered as holding credits of {batch_denom}")]
+    UnregisteredCreditToken { batch_denom: String, token: String },
+
+    #[error("{token} is not a registered credit token")]
+    CreditTokenNotFound { token: String },
+
+    #[error("Credits of trade {trade_id} could not be delivered: {error}")]
+    SettlementFailed { trade_id: u64, error: String },
+
+    #[error("Trade {trade_id} has no failed delivery to retry")]
+    NoFailedSettlement { trade_id: u64 },
+
+    #[error("Unknown reply id {id}")]
+    UnknownReplyId { id: u64 },
+
+    #[error("Migration requires an authority")]
+    MissingAuthority {},
+
+    #[error("Marketplace is paused: {scope}")]
+    Paused { scope: String },
+
+    #[error("{field} must not be empty")]
+    EmptyField { field: String },
+
+    #[error("Order {index}: {field} {reason}")]
+    InvalidOrderField {
+        index: usize,
+        field: String,
+        reason: String,
+    },
+
+    #[error("{field} {reason}")]
+    InvalidField { field: String, reason: String },
+
+    #[error("Order {index}: sell order {sell_order_id} is repeated")]
+    DuplicateSellOrder { index: usize, sell_order_id: u64 },
+
+    #[error("{field} must not exceed 100%")]
+    InvalidFee { field: String },
+
+    #[error("Invalid recipient {recipient}")]
+    InvalidRecipient { recipient: String },
+
+    #[error("Wrong contract: expected {expected}, found {found}")]
+    WrongContract { expected: String, found: String },
+
+    #[error("Cannot migrate from version {stored} to older version {current}")]
+    CannotDowngrade { stored: String, current: String },
+
+    #[error("Cannot migrate {record}: {reason}")]
+    MigrationFailed { record: String, reason: String },
+
+    #[error("Invalid contract version: {0}")]
+    SemVer(String),
     // Add any other custom errors you need
 }
+
+impl From<semver::Error> for ContractError {
+    fn from(err: semver::Error) -> Self {
+        Self::SemVer(err.to_string())
+    }
+}
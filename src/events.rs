@@ -0,0 +1,458 @@
+use cosmwasm_std::{Addr, Coin, Decimal, Event, Timestamp, Uint128};
+
+use crate::execute::PurchasePrice;
+use crate::msg::StateRecords;
+use crate::proto::MarketplaceGenesis;
+use crate::state::{
+    AllowedDenom, Bundle, CollectionBid, CreditAsset, CreditToken, DenomKind, FeeParams, Hook,
+    Offer, OfferParty, SellOrder, Trade,
+};
+
+/// Version of the event schema below. Bumped whenever an event is renamed or
+/// an attribute is removed or changes meaning; new attributes may be added
+/// without a bump. Every event carries it as `schema_version`.
+pub const EVENT_SCHEMA_VERSION: &str = "1";
+
+fn event(ty: &str) -> Event {
+    Event::new(ty).add_attribute("schema_version", EVENT_SCHEMA_VERSION)
+}
+
+/// Attribute values cannot be empty, so absent values are emitted as `none`.
+fn or_none(value: &str) -> &str {
+    if value.is_empty() {
+        "none"
+    } else {
+        value
+    }
+}
+
+fn expiration(expiration: Option<Timestamp>) -> String {
+    expiration
+        .map(|expiration| expiration.nanos().to_string())
+        .unwrap_or_else(|| "none".to_string())
+}
+
+/// Emitted as `wasm-sell_order_created` for every new sell order.
+pub fn sell_order_created(order: &SellOrder, batch_denom: &str, ask_denom: &str) -> Event {
+    event("sell_order_created")
+        .add_attribute("sell_order_id", order.id.to_string())
+        .add_attribute("seller", order.seller.as_str())
+        .add_attribute("batch_denom", or_none(batch_denom))
+        .add_attribute("quantity", order.quantity)
+        .add_attribute("market_id", order.market_id.to_string())
+        .add_attribute("ask_amount", order.ask_amount)
+        .add_attribute("ask_denom", or_none(ask_denom))
+        .add_attribute("disable_auto_retire", order.disable_auto_retire.to_string())
+        .add_attribute("expiration", expiration(order.expiration))
+        .add_attribute("version", order.version.to_string())
+        .add_attribute("private", order.allowed_buyers.is_some().to_string())
+        .add_attribute(
+            "asset",
+            match order.asset {
+                CreditAsset::Native => "native",
+                CreditAsset::Cw20 { .. } => "cw20",
+                CreditAsset::Cw1155 { .. } => "cw1155",
+            },
+        )
+}
+
+/// Emitted as `wasm-sell_order_updated` with the terms before and after the
+/// update, as `<field>_before` and `<field>_after` attributes.
+pub fn sell_order_updated(
+    before: &SellOrder,
+    after: &SellOrder,
+    ask_denom_before: &str,
+    ask_denom_after: &str,
+) -> Event {
+    event("sell_order_updated")
+        .add_attribute("sell_order_id", after.id.to_string())
+        .add_attribute("seller", after.seller.as_str())
+        .add_attribute("quantity_before", before.quantity)
+        .add_attribute("quantity_after", after.quantity)
+        .add_attribute("market_id_before", before.market_id.to_string())
+        .add_attribute("market_id_after", after.market_id.to_string())
+        .add_attribute("ask_amount_before", before.ask_amount)
+        .add_attribute("ask_amount_after", after.ask_amount)
+        .add_attribute("ask_denom_before", or_none(ask_denom_before))
+        .add_attribute("ask_denom_after", or_none(ask_denom_after))
+        .add_attribute(
+            "disable_auto_retire_before",
+            before.disable_auto_retire.to_string(),
+        )
+        .add_attribute(
+            "disable_auto_retire_after",
+            after.disable_auto_retire.to_string(),
+        )
+        .add_attribute("expiration_before", expiration(before.expiration))
+        .add_attribute("expiration_after", expiration(after.expiration))
+        .add_attribute("version_before", before.version.to_string())
+        .add_attribute("version_after", after.version.to_string())
+}
+
+/// Emitted as `wasm-sell_order_cancelled` with the quantity left unsold.
+pub fn sell_order_cancelled(order: &SellOrder, batch_denom: &str) -> Event {
+    event("sell_order_cancelled")
+        .add_attribute("sell_order_id", order.id.to_string())
+        .add_attribute("seller", order.seller.as_str())
+        .add_attribute("batch_denom", or_none(batch_denom))
+        .add_attribute("quantity", order.quantity)
+}
+
+/// Emitted as `wasm-sell_order_filled` for every purchase from an order.
+/// `remaining_quantity` is zero when the order was filled completely.
+pub fn sell_order_filled(order: &SellOrder, trade: &Trade, price: &PurchasePrice) -> Event {
+    fill_attributes(
+        event("sell_order_filled").add_attribute("sell_order_id", order.id.to_string()),
+        trade,
+        order.quantity,
+        price,
+    )
+}
+
+/// Emitted as `wasm-signed_order_filled` for every purchase from an
+/// off-chain signed order, which is identified by its seller and nonce.
+pub fn signed_order_filled(
+    nonce: u64,
+    trade: &Trade,
+    remaining_quantity: Uint128,
+    price: &PurchasePrice,
+) -> Event {
+    fill_attributes(
+        event("signed_order_filled").add_attribute("nonce", nonce.to_string()),
+        trade,
+        remaining_quantity,
+        price,
+    )
+}
+
+/// `buyer` is kept alongside `payer` for indexers of the first schema.
+fn fill_attributes(
+    event: Event,
+    trade: &Trade,
+    remaining_quantity: Uint128,
+    price: &PurchasePrice,
+) -> Event {
+    event
+        .add_attribute("trade_id", trade.id.to_string())
+        .add_attribute("seller", trade.seller.as_str())
+        .add_attribute("buyer", trade.payer.as_str())
+        .add_attribute("payer", trade.payer.as_str())
+        .add_attribute("beneficiary", trade.beneficiary.as_str())
+        .add_attribute("batch_denom", or_none(&trade.batch_denom))
+        .add_attribute("quantity", trade.quantity)
+        .add_attribute("remaining_quantity", remaining_quantity)
+        .add_attribute("ask_amount", price.ask_price.amount)
+        .add_attribute("ask_denom", &price.ask_price.denom)
+        .add_attribute("buyer_fee", price.buyer_fee.to_string())
+        .add_attribute("seller_fee", price.seller_fee.to_string())
+        .add_attribute("retired", trade.retirement.is_some().to_string())
+}
+
+/// Emitted as `wasm-offer_made` and `wasm-offer_countered` with the terms
+/// now on the table, and `proposer` set to `buyer` or `seller`.
+pub fn offer_proposed(ty: &str, offer: &Offer) -> Event {
+    offer_attributes(event(ty), offer)
+        .add_attribute(
+            "proposer",
+            match offer.proposer {
+                OfferParty::Buyer => "buyer",
+                OfferParty::Seller => "seller",
+            },
+        )
+        .add_attribute("escrow", offer.escrow.to_string())
+}
+
+/// Emitted as `wasm-offer_accepted`, alongside the `wasm-sell_order_filled`
+/// event of the trade.
+pub fn offer_accepted(offer: &Offer, trade: &Trade) -> Event {
+    offer_attributes(event("offer_accepted"), offer).add_attribute("trade_id", trade.id.to_string())
+}
+
+/// Emitted as `wasm-offer_rejected` with the account that rejected it.
+pub fn offer_rejected(offer: &Offer, rejected_by: &Addr) -> Event {
+    offer_attributes(event("offer_rejected"), offer)
+        .add_attribute("rejected_by", rejected_by.as_str())
+}
+
+fn offer_attributes(event: Event, offer: &Offer) -> Event {
+    event
+        .add_attribute("offer_id", offer.id.to_string())
+        .add_attribute("sell_order_id", offer.sell_order_id.to_string())
+        .add_attribute("buyer", offer.buyer.as_str())
+        .add_attribute("quantity", offer.quantity)
+        .add_attribute("price_amount", offer.price.amount)
+        .add_attribute("price_denom", &offer.price.denom)
+        .add_attribute("expiration", expiration(offer.expiration))
+}
+
+/// Emitted as `wasm-collection_bid_placed`.
+pub fn collection_bid_placed(bid: &CollectionBid) -> Event {
+    event("collection_bid_placed")
+        .add_attribute("bid_id", bid.id.to_string())
+        .add_attribute("buyer", bid.buyer.as_str())
+        .add_attribute("quantity", bid.quantity)
+        .add_attribute("price_amount", bid.price.amount)
+        .add_attribute("price_denom", &bid.price.denom)
+        .add_attribute("escrow", bid.escrow.to_string())
+        .add_attribute("expiration", expiration(bid.expiration))
+}
+
+/// Emitted as `wasm-collection_bid_filled` once per fill, alongside the
+/// `wasm-sell_order_filled` events of the trades.
+pub fn collection_bid_filled(bid: &CollectionBid, seller: &Addr, quantity: Uint128) -> Event {
+    event("collection_bid_filled")
+        .add_attribute("bid_id", bid.id.to_string())
+        .add_attribute("buyer", bid.buyer.as_str())
+        .add_attribute("seller", seller.as_str())
+        .add_attribute("quantity", quantity)
+        .add_attribute("remaining_quantity", bid.quantity)
+}
+
+/// Emitted as `wasm-collection_bid_cancelled` with the refunded escrow.
+pub fn collection_bid_cancelled(bid: &CollectionBid) -> Event {
+    event("collection_bid_cancelled")
+        .add_attribute("bid_id", bid.id.to_string())
+        .add_attribute("buyer", bid.buyer.as_str())
+        .add_attribute("remaining_quantity", bid.quantity)
+        .add_attribute("refund", bid.escrow.to_string())
+}
+
+/// Emitted as `wasm-bundle_created`, with `items` as comma separated
+/// `<quantity><batch_denom>` pairs.
+pub fn bundle_created(bundle: &Bundle) -> Event {
+    bundle_attributes(event("bundle_created"), bundle)
+        .add_attribute("price_amount", bundle.price.amount)
+        .add_attribute("price_denom", &bundle.price.denom)
+        .add_attribute("expiration", expiration(bundle.expiration))
+}
+
+/// Emitted as `wasm-bundle_cancelled`.
+pub fn bundle_cancelled(bundle: &Bundle) -> Event {
+    bundle_attributes(event("bundle_cancelled"), bundle)
+}
+
+/// Emitted as `wasm-bundle_sold` with the trades recorded for each batch.
+pub fn bundle_sold(bundle: &Bundle, trades: &[Trade], price: &PurchasePrice) -> Event {
+    let trade_ids: Vec<String> = trades.iter().map(|trade| trade.id.to_string()).collect();
+    let retired: Vec<&str> = trades
+        .iter()
+        .filter(|trade| trade.retirement.is_some())
+        .map(|trade| trade.batch_denom.as_str())
+        .collect();
+    let (payer, beneficiary) = trades
+        .first()
+        .map(|trade| (trade.payer.as_str(), trade.beneficiary.as_str()))
+        .unwrap_or_default();
+    bundle_attributes(event("bundle_sold"), bundle)
+        .add_attribute("trade_ids", trade_ids.join(","))
+        .add_attribute("payer", payer)
+        .add_attribute("beneficiary", beneficiary)
+        .add_attribute("price_amount", price.ask_price.amount)
+        .add_attribute("price_denom", &price.ask_price.denom)
+        .add_attribute("buyer_fee", price.buyer_fee.to_string())
+        .add_attribute("seller_fee", price.seller_fee.to_string())
+        .add_attribute("retired_batches", or_none(&retired.join(",")))
+}
+
+fn bundle_attributes(event: Event, bundle: &Bundle) -> Event {
+    let items: Vec<String> = bundle
+        .items
+        .iter()
+        .map(|item| format!("{}{}", item.quantity, item.batch_denom))
+        .collect();
+    event
+        .add_attribute("bundle_id", bundle.id.to_string())
+        .add_attribute("seller", bundle.seller.as_str())
+        .add_attribute("items", items.join(","))
+}
+
+/// Emitted as `wasm-denom_allowed`.
+pub fn denom_allowed(denom: &AllowedDenom) -> Event {
+    event("denom_allowed")
+        .add_attribute("bank_denom", &denom.bank_denom)
+        .add_attribute("display_denom", &denom.display_denom)
+        .add_attribute("exponent", denom.exponent.to_string())
+        .add_attribute(
+            "kind",
+            match denom.kind {
+                DenomKind::Native => "native",
+                DenomKind::Cw20 => "cw20",
+            },
+        )
+}
+
+/// Emitted as `wasm-denom_removed`.
+pub fn denom_removed(denom: &str) -> Event {
+    event("denom_removed").add_attribute("bank_denom", denom)
+}
+
+/// Emitted as `wasm-genesis_batch_imported` with the number of records of
+/// each table in the chunk.
+pub fn genesis_batch_imported(genesis: &MarketplaceGenesis) -> Event {
+    event("genesis_batch_imported")
+        .add_attribute("sell_orders", genesis.sell_orders.len().to_string())
+        .add_attribute("allowed_denoms", genesis.allowed_denoms.len().to_string())
+        .add_attribute("markets", genesis.markets.len().to_string())
+        .add_attribute("batches", genesis.batches.len().to_string())
+        .add_attribute("fee_params", genesis.fee_params.is_some().to_string())
+}
+
+/// Emitted as `wasm-state_imported` with the section and number of records of
+/// an `ImportState` page.
+pub fn state_imported(records: &StateRecords) -> Event {
+    let (section, count) = match records {
+        StateRecords::Config(_) => ("config", 1),
+        StateRecords::FeeParams(_) => ("fee_params", 1),
+        StateRecords::FeePool(coins) => ("fee_pool", coins.len()),
+        StateRecords::Sequences(_) => ("sequences", 1),
+        StateRecords::AllowedDenoms(denoms) => ("allowed_denoms", denoms.len()),
+        StateRecords::Batches(batches) => ("batches", batches.len()),
+        StateRecords::Markets(markets) => ("markets", markets.len()),
+        StateRecords::SellOrders(sell_orders) => ("sell_orders", sell_orders.len()),
+        StateRecords::Balances(balances) => ("balances", balances.len()),
+        StateRecords::Offers(offers) => ("offers", offers.len()),
+        StateRecords::CollectionBids(bids) => ("collection_bids", bids.len()),
+        StateRecords::Bundles(bundles) => ("bundles", bundles.len()),
+        StateRecords::Trades(trades) => ("trades", trades.len()),
+        StateRecords::PurchaseAllowances(allowances) => ("purchase_allowances", allowances.len()),
+        StateRecords::SignedOrders(signed_orders) => ("signed_orders", signed_orders.len()),
+        StateRecords::Pauses(pauses) => ("pauses", pauses.len()),
+        StateRecords::Hooks(hooks) => ("hooks", hooks.len()),
+        StateRecords::CreditTokens(tokens) => ("credit_tokens", tokens.len()),
+        StateRecords::Settlements(settlements) => ("settlements", settlements.len()),
+        StateRecords::CrankCursor(_) => ("crank_cursor", 1),
+        StateRecords::ContractVersion(_) => ("contract_version", 1),
+        StateRecords::GenesisImport(_) => ("genesis_import", 1),
+    };
+    event("state_imported")
+        .add_attribute("section", section)
+        .add_attribute("records", count.to_string())
+}
+
+/// Emitted as `wasm-genesis_import_finalized` when trading opens.
+pub fn genesis_import_finalized(sell_orders: usize) -> Event {
+    event("genesis_import_finalized").add_attribute("sell_orders", sell_orders.to_string())
+}
+
+/// Emitted as `wasm-fee_params_updated` with the fees before and after.
+pub fn fee_params_updated(before: &FeeParams, after: &FeeParams) -> Event {
+    event("fee_params_updated")
+        .add_attribute(
+            "buyer_percentage_fee_before",
+            before.buyer_percentage_fee.to_string(),
+        )
+        .add_attribute(
+            "buyer_percentage_fee_after",
+            after.buyer_percentage_fee.to_string(),
+        )
+        .add_attribute(
+            "seller_percentage_fee_before",
+            before.seller_percentage_fee.to_string(),
+        )
+        .add_attribute(
+            "seller_percentage_fee_after",
+            after.seller_percentage_fee.to_string(),
+        )
+}
+
+/// Emitted as `wasm-record_expired` for every record a crank removes, with
+/// `record` set to `sell_order`, `offer`, `collection_bid`, `bundle` or
+/// `purchase_allowance`. The id of a purchase allowance is its spender.
+pub fn record_expired(record: &str, id: &str, owner: &Addr) -> Event {
+    event("record_expired")
+        .add_attribute("record", record)
+        .add_attribute("id", id)
+        .add_attribute("owner", owner)
+}
+
+/// Emitted as `wasm-crank` with the records scanned and removed, and the
+/// reward paid to the keeper as a comma separated list.
+pub fn crank(keeper: &Addr, scanned: usize, expired: usize, reward: &[Coin]) -> Event {
+    event("crank")
+        .add_attribute("keeper", keeper)
+        .add_attribute("scanned", scanned.to_string())
+        .add_attribute("expired", expired.to_string())
+        .add_attribute(
+            "reward",
+            or_none(
+                &reward
+                    .iter()
+                    .map(|coin| coin.to_string())
+                    .collect::<Vec<String>>()
+                    .join(","),
+            ),
+        )
+}
+
+/// Emitted as `wasm-crank_reward_share_updated`.
+pub fn crank_reward_share_updated(before: Decimal, after: Decimal) -> Event {
+    event("crank_reward_share_updated")
+        .add_attribute("share_before", before.to_string())
+        .add_attribute("share_after", after.to_string())
+}
+
+/// Emitted as `wasm-hook_added` and `wasm-hook_removed`.
+pub fn hook_changed(ty: &str, hook: &Hook) -> Event {
+    event(ty)
+        .add_attribute("hook_id", hook.id.to_string())
+        .add_attribute("event", hook.event.as_str())
+        .add_attribute("contract", &hook.contract)
+        .add_attribute("required", hook.required.to_string())
+}
+
+/// Emitted as `wasm-hook_failed` when an optional hook call fails. The
+/// failed call is reverted and the rest of the transaction goes on.
+pub fn hook_failed(hook_id: u64, hook: Option<&Hook>, error: &str) -> Event {
+    let event = event("hook_failed").add_attribute("hook_id", hook_id.to_string());
+    let event = match hook {
+        Some(hook) => event
+            .add_attribute("event", hook.event.as_str())
+            .add_attribute("contract", &hook.contract),
+        None => event,
+    };
+    event.add_attribute("error", or_none(error))
+}
+
+/// Emitted as `wasm-credit_token_added` and `wasm-credit_token_removed`, with
+/// the token's escrow denom as `token`.
+pub fn credit_token_changed(ty: &str, token: &str, credit_token: &CreditToken) -> Event {
+    event(ty)
+        .add_attribute("token", token)
+        .add_attribute("batch_denom", &credit_token.batch_denom)
+        .add_attribute("custodial", credit_token.custodial.to_string())
+}
+
+/// Emitted as `wasm-settlement_failed` when the credits of a trade could not
+/// be delivered and the trade waits for `RetrySettlement`.
+pub fn settlement_failed(trade_id: u64, error: &str) -> Event {
+    event("settlement_failed")
+        .add_attribute("trade_id", trade_id.to_string())
+        .add_attribute("error", or_none(error))
+}
+
+/// Emitted as `wasm-settlement_refunded` when the payer of a failed delivery
+/// takes their payment back.
+pub fn settlement_refunded(trade_id: u64, refund: &Coin) -> Event {
+    event("settlement_refunded")
+        .add_attribute("trade_id", trade_id.to_string())
+        .add_attribute("refund", refund.to_string())
+}
+
+/// Emitted as `wasm-settlement_completed` when a retried delivery succeeds.
+pub fn settlement_completed(trade_id: u64) -> Event {
+    event("settlement_completed").add_attribute("trade_id", trade_id.to_string())
+}
+
+/// Emitted as `wasm-fee_pool_sent`, with `coins` as a comma separated list.
+pub fn fee_pool_sent(recipient: &str, coins: &[Coin]) -> Event {
+    event("fee_pool_sent")
+        .add_attribute("recipient", recipient)
+        .add_attribute(
+            "coins",
+            coins
+                .iter()
+                .map(|coin| coin.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+        )
+}
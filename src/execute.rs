@@ -1,21 +1,95 @@
-use cosmwasm_std::{Coin, DepsMut, Env, MessageInfo, Response, Uint128};
-use std::str::FromStr;
+use cosmwasm_std::{
+    from_json, to_json_binary, wasm_execute, Addr, Api, BankMsg, Binary, CanonicalAddr, Coin,
+    Coins, CosmosMsg, Decimal, DepsMut, Env, Event, HexBinary, MessageInfo, Order, QuerierWrapper,
+    Response, StdResult, Storage, SubMsg, SubMsgResult, Timestamp, Uint128, WasmMsg,
+};
+use cw20::Cw20ExecuteMsg;
+use cw_storage_plus::{Bound, Item};
+use semver::Version;
 
+use crate::contract;
+use crate::cw1155::{Cw1155BatchReceiveMsg, Cw1155ExecuteMsg};
+use crate::ecocredit;
 use crate::error::ContractError;
-use crate::msg::{BuyOrderMsg, SellOrderMsg, UpdateSellOrderMsg};
+use crate::events;
+use crate::msg::{
+    AllowedBuyersMsg, BundleRetirementMsg, BuyOrderMsg, CreditAssetMsg, ExecuteMsg, HookExecuteMsg,
+    HookMsg, ReceiveMsg, SellOrderMsg, SignedOrderBuyMsg, SignedSellOrder, StateRecords,
+    UpdateSellOrderMsg,
+};
+use crate::proto::{self, MarketplaceGenesis, MarketplaceMsg};
+use crate::reply::ReplyId;
+use crate::signing;
 use crate::state::{
-    AllowedDenom, FeeParams, SellOrder, ALLOWED_DENOMS, FEE_PARAMS, SELL_ORDERS, SELL_ORDER_SEQ,
+    cw1155_denom, cw20_denom, AllowedBuyers, AllowedDenom, Batch, BatchMetadata, Bundle,
+    BundleItem, CollectionBid, CollectionBidCriteria, Config, CrankCursor, CreditAsset,
+    CreditToken, DeliveryFailure, DenomKind, EcocreditAdapter, FeeParams, HeldPayment, Hook,
+    HookEvent, Market, Offer, OfferParty, PauseScope, PurchaseAllowance, Retirement, SellOrder,
+    Settlement, SignedOrderStatus, Trade, TradeSource, ALLOWED_DENOMS, BALANCES, BATCHES,
+    BATCH_KEYS, BATCH_SEQ, BUNDLES, BUNDLE_SEQ, COLLECTION_BIDS, COLLECTION_BID_SEQ, CONFIG,
+    CRANK_CURSOR, CREDIT_TOKENS, CW20_DENOM_PREFIX, FEE_PARAMS, FEE_POOL, GENESIS_IMPORT, HOOKS,
+    HOOK_SEQ, MARKETS, MARKET_IDS, MARKET_SEQ, OFFERS, OFFER_SEQ, PAUSES, PURCHASE_ALLOWANCES,
+    SELL_ORDERS, SELL_ORDER_SEQ, SETTLEMENTS, SIGNED_ORDERS, TRADES, TRADE_SEQ,
 };
+use crate::validation;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 
 pub fn sell(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     orders: Vec<SellOrderMsg>,
 ) -> Result<Response, ContractError> {
     let mut sell_order_ids = Vec::new();
+    let mut response = Response::new();
+    let mut escrow = Coins::default();
+    let config = CONFIG.load(deps.storage)?;
 
     for order in orders {
+        ensure_denom_allowed(deps.storage, &order.ask_price.denom)?;
+
+        let batch_key = batch_key_or_create(deps.storage, &order.batch_denom)?;
+        let market_id = market_id_or_create(deps.storage, &order.batch_denom, &order.ask_price)?;
+        ensure_not_paused(
+            deps.storage,
+            &PauseScope::Listings,
+            market_id,
+            &order.batch_denom,
+        )?;
+
+        let allowed_buyers = match order.allowed_buyers {
+            Some(AllowedBuyersMsg::Addresses { addresses }) => Some(AllowedBuyers::Addresses {
+                addresses: addresses
+                    .iter()
+                    .map(|address| deps.api.addr_validate(address))
+                    .collect::<StdResult<_>>()?,
+            }),
+            Some(AllowedBuyersMsg::MerkleRoot { root }) => Some(AllowedBuyers::MerkleRoot { root }),
+            None => None,
+        };
+
+        let asset = credit_asset(deps.api, order.asset.unwrap_or(CreditAssetMsg::Native))?;
+        match asset.escrow_denom() {
+            Some(denom) => {
+                let registered = CREDIT_TOKENS.may_load(deps.storage, denom.clone())?;
+                if registered.is_none_or(|token| token.batch_denom != order.batch_denom) {
+                    return Err(ContractError::UnregisteredCreditToken {
+                        batch_denom: order.batch_denom,
+                        token: denom,
+                    });
+                }
+                escrow.add(Coin::new(order.quantity.u128(), denom))?
+            }
+            None => ensure_credits_available(
+                &deps.querier,
+                &config.ecocredit,
+                &info.sender,
+                &order.batch_denom,
+                order.quantity,
+            )?,
+        }
+
         let id = SELL_ORDER_SEQ.update(deps.storage, |id| -> Result<_, ContractError> {
             Ok(id + 1)
         })?;
@@ -23,37 +97,54 @@ pub fn sell(
         let sell_order = SellOrder {
             id,
             seller: info.sender.clone(),
-            batch_key: 0, // This should be set properly based on the batch_denom
+            batch_key,
             quantity: order.quantity,
-            market_id: 0, // This should be set properly based on the ask_price denom
-            ask_amount: order.ask_price.amount.to_string(),
+            market_id,
+            ask_amount: order.ask_price.amount,
             disable_auto_retire: order.disable_auto_retire,
             expiration: order.expiration,
             maker: true,
+            version: 1,
+            allowed_buyers,
+            batch_metadata: batch_metadata(&deps.querier, &config.ecocredit, &order.batch_denom)?,
+            asset,
         };
 
-        SELL_ORDERS.save(deps.storage, id, &sell_order)?;
+        SELL_ORDERS.save(deps.storage, id, &sell_order, env.block.height)?;
         sell_order_ids.push(id);
+        response = response
+            .add_event(events::sell_order_created(
+                &sell_order,
+                &order.batch_denom,
+                &order.ask_price.denom,
+            ))
+            .add_submessages(hook_messages(
+                deps.storage,
+                HookMsg::OrderCreated { sell_order },
+            )?);
     }
 
-    Ok(Response::new()
-        .add_attribute("method", "sell")
-        .add_attribute(
-            "sell_order_ids",
-            sell_order_ids
-                .iter()
-                .map(|id| id.to_string())
-                .collect::<Vec<String>>()
-                .join(","),
-        ))
+    // cw20 and cw1155 credits arrive through the receive hooks.
+    ensure_funds(&info, &escrow)?;
+
+    Ok(response.add_attribute("method", "sell").add_attribute(
+        "sell_order_ids",
+        sell_order_ids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<String>>()
+            .join(","),
+    ))
 }
 
 pub fn update_sell_orders(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     updates: Vec<UpdateSellOrderMsg>,
 ) -> Result<Response, ContractError> {
+    let mut response = Response::new();
+
     for update in updates {
         let mut sell_order = SELL_ORDERS.load(deps.storage, update.sell_order_id)?;
 
@@ -61,13 +152,45 @@ pub fn update_sell_orders(
             return Err(ContractError::Unauthorized {});
         }
 
+        let before = sell_order.clone();
+        let ask_denom_before = ask_denom(deps.storage, before.market_id)?;
+
+        let batch_denom = batch_denom(deps.storage, sell_order.batch_key)?;
+        ensure_not_paused(
+            deps.storage,
+            &PauseScope::Listings,
+            sell_order.market_id,
+            &batch_denom,
+        )?;
+
         if let Some(new_quantity) = update.new_quantity {
+            if sell_order.asset != CreditAsset::Native {
+                // Escrowed credits can be withdrawn, but more must be listed
+                // as a new order.
+                if new_quantity > sell_order.quantity {
+                    return Err(ContractError::EscrowedQuantityIncrease {
+                        sell_order_id: sell_order.id,
+                    });
+                }
+                response = response.add_messages(transfer_credits(
+                    &sell_order.asset,
+                    &env,
+                    &sell_order.seller,
+                    sell_order.quantity - new_quantity,
+                )?);
+            }
             sell_order.quantity = new_quantity;
         }
 
         if let Some(new_ask_price) = update.new_ask_price {
-            sell_order.ask_amount = new_ask_price.amount.to_string();
-            // Update market_id if necessary
+            sell_order.ask_amount = new_ask_price.amount;
+            sell_order.market_id = market_id_or_create(deps.storage, &batch_denom, &new_ask_price)?;
+            ensure_not_paused(
+                deps.storage,
+                &PauseScope::Listings,
+                sell_order.market_id,
+                &batch_denom,
+            )?;
         }
 
         if let Some(disable_auto_retire) = update.disable_auto_retire {
@@ -78,15 +201,32 @@ pub fn update_sell_orders(
             sell_order.expiration = Some(new_expiration);
         }
 
-        SELL_ORDERS.save(deps.storage, update.sell_order_id, &sell_order)?;
+        sell_order.version += 1;
+
+        // Orders frozen by a removed denom can only be repriced or cancelled.
+        let market = MARKETS.load(deps.storage, sell_order.market_id)?;
+        ensure_denom_allowed(deps.storage, &market.bank_denom)?;
+
+        SELL_ORDERS.save(
+            deps.storage,
+            update.sell_order_id,
+            &sell_order,
+            env.block.height,
+        )?;
+        response = response.add_event(events::sell_order_updated(
+            &before,
+            &sell_order,
+            &ask_denom_before,
+            &market.bank_denom,
+        ));
     }
 
-    Ok(Response::new().add_attribute("method", "update_sell_orders"))
+    Ok(response.add_attribute("method", "update_sell_orders"))
 }
 
 pub fn cancel_sell_order(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     sell_order_id: u64,
 ) -> Result<Response, ContractError> {
@@ -96,56 +236,595 @@ pub fn cancel_sell_order(
         return Err(ContractError::Unauthorized {});
     }
 
-    SELL_ORDERS.remove(deps.storage, sell_order_id);
+    SELL_ORDERS.remove(deps.storage, sell_order_id, env.block.height)?;
 
+    let batch_denom = batch_denom(deps.storage, sell_order.batch_key)?;
+    let refund = transfer_credits(
+        &sell_order.asset,
+        &env,
+        &sell_order.seller,
+        sell_order.quantity,
+    )?;
     Ok(Response::new()
+        .add_event(events::sell_order_cancelled(&sell_order, &batch_denom))
+        .add_messages(refund)
+        .add_submessages(hook_messages(
+            deps.storage,
+            HookMsg::OrderCancelled { sell_order },
+        )?)
         .add_attribute("method", "cancel_sell_order")
         .add_attribute("sell_order_id", sell_order_id.to_string()))
 }
 
 pub fn buy_direct(
-    deps: DepsMut,
-    _env: Env,
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    orders: Vec<BuyOrderMsg>,
+    use_balance: bool,
+) -> Result<Response, ContractError> {
+    let (response, fills) = fill_sell_orders(deps.branch(), &env, &info.sender, None, orders)?;
+    if use_balance {
+        for (trade, _) in &fills {
+            hold_from_balance(deps.storage, trade)?;
+        }
+    }
+    let payments = fill_payments(deps.storage, &fills)?;
+    let messages = settle(deps.storage, &info, use_balance, &payments)?;
+
+    Ok(response
+        .add_messages(messages)
+        .add_attribute("method", "buy_direct"))
+}
+
+/// Pays for the purchase from the deposited balance of `owner`, within the
+/// allowance they granted the sender, and sends the proceeds to the sellers.
+pub fn buy_direct_from(
+    mut deps: DepsMut,
+    env: Env,
     info: MessageInfo,
+    owner: String,
     orders: Vec<BuyOrderMsg>,
 ) -> Result<Response, ContractError> {
+    // The owner's balance pays; funds attached by the spender would be kept.
+    if !info.funds.is_empty() {
+        return Err(ContractError::InvalidFunds {
+            expected: "none".to_string(),
+            found: coins_to_string(&info.funds),
+        });
+    }
+    let owner = deps.api.addr_validate(&owner)?;
+    let key = (owner.clone(), info.sender.clone());
+    let mut allowance = PURCHASE_ALLOWANCES
+        .may_load(deps.storage, key.clone())?
+        .ok_or_else(|| ContractError::NoPurchaseAllowance {
+            owner: owner.to_string(),
+            spender: info.sender.to_string(),
+        })?;
+    if matches!(allowance.expiration, Some(expiration) if expiration <= env.block.time) {
+        return Err(ContractError::PurchaseAllowanceExpired {
+            owner: owner.to_string(),
+        });
+    }
+
+    let (mut response, fills) =
+        fill_sell_orders(deps.branch(), &env, &owner, Some(&info.sender), orders)?;
+
+    let mut cost = Uint128::zero();
+    for (trade, price) in &fills {
+        if price.ask_price.denom != allowance.denom {
+            return Err(ContractError::BidDenomMismatch {
+                expected: allowance.denom,
+                found: price.ask_price.denom.clone(),
+            });
+        }
+        if let Some(allowed_batches) = &allowance.allowed_batches {
+            if !allowed_batches.contains(&trade.batch_denom) {
+                return Err(ContractError::BatchNotAllowed {
+                    batch_denom: trade.batch_denom.clone(),
+                });
+            }
+        }
+        cost += price.cost(trade.quantity);
+        hold_from_balance(deps.storage, trade)?;
+        if let Some(seller) = payee(deps.storage, trade)? {
+            response =
+                response.add_messages(pay_seller(deps.storage, seller, price, trade.quantity)?);
+        }
+    }
+
+    if cost > allowance.remaining {
+        return Err(ContractError::PurchaseAllowanceExceeded {
+            remaining: Coin::new(allowance.remaining.u128(), &allowance.denom),
+            cost: Coin::new(cost.u128(), &allowance.denom),
+        });
+    }
+    allowance.remaining -= cost;
+    PURCHASE_ALLOWANCES.save(deps.storage, key, &allowance)?;
+    debit_balance(
+        deps.storage,
+        &owner,
+        &Coin::new(cost.u128(), &allowance.denom),
+    )?;
+
+    Ok(response
+        .add_attribute("method", "buy_direct_from")
+        .add_attribute("owner", owner)
+        .add_attribute("spender", info.sender))
+}
+
+/// Fills each order for `payer` and records the trades. Payment is left to
+/// the caller.
+fn fill_sell_orders(
+    mut deps: DepsMut,
+    env: &Env,
+    payer: &Addr,
+    spender: Option<&Addr>,
+    orders: Vec<BuyOrderMsg>,
+) -> Result<(Response, Vec<(Trade, PurchasePrice)>), ContractError> {
     let fee_params = FEE_PARAMS.load(deps.storage)?;
+    let mut response = Response::new();
+    let mut fills = Vec::new();
 
     for order in orders {
-        let mut sell_order = SELL_ORDERS.load(deps.storage, order.sell_order_id)?;
+        let (event, trade, price, delivery) =
+            fill_sell_order(deps.branch(), env, &fee_params, payer, spender, order, None)?;
+        response = response
+            .add_event(event)
+            .add_submessages(delivery)
+            .add_submessages(trade_hooks(deps.storage, &trade)?);
+        fills.push((trade, price));
+    }
 
-        // Check if the bid price is sufficient
-        if order.bid_price.amount < Uint128::from_str(&sell_order.ask_amount)? {
-            return Err(ContractError::InsufficientBidPrice);
-        }
+    Ok((response, fills))
+}
 
-        // Calculate fees
-        let buyer_fee = calculate_fee(&order.bid_price, &fee_params.buyer_percentage_fee)?;
-        let seller_fee = calculate_fee(&order.bid_price, &fee_params.seller_percentage_fee)?;
+/// Fills one order at its ask price, or at `agreed_price` when the seller
+/// accepted a different one, and records the trade. The credits are delivered
+/// by the returned submessage.
+fn fill_sell_order(
+    deps: DepsMut,
+    env: &Env,
+    fee_params: &FeeParams,
+    payer: &Addr,
+    spender: Option<&Addr>,
+    order: BuyOrderMsg,
+    agreed_price: Option<Uint128>,
+) -> Result<(Event, Trade, PurchasePrice, Option<SubMsg>), ContractError> {
+    let mut sell_order = SELL_ORDERS.load(deps.storage, order.sell_order_id)?;
+    ensure_sell_order_live(env, &sell_order)?;
 
-        // Check if the max fee amount is sufficient
-        if buyer_fee.amount > order.max_fee_amount.amount {
-            return Err(ContractError::MaxFeeExceeded);
+    if let Some(expected_version) = order.expected_version {
+        if expected_version != sell_order.version {
+            return Err(ContractError::SellOrderChanged {
+                sell_order_id: sell_order.id,
+                expected_version,
+                version: sell_order.version,
+            });
         }
+    }
+
+    ensure_allowed_buyer(&sell_order, payer, order.merkle_proof.as_deref())?;
+
+    let batch_denom = batch_denom(deps.storage, sell_order.batch_key)?;
+    ensure_not_paused(
+        deps.storage,
+        &PauseScope::Purchases,
+        sell_order.market_id,
+        &batch_denom,
+    )?;
+
+    let price = price_purchase(
+        deps.storage,
+        fee_params,
+        sell_order.market_id,
+        agreed_price.unwrap_or(sell_order.ask_amount),
+        &order.bid_price,
+        &order.max_fee_amount,
+    )?;
+
+    // Process the trade
+    if order.quantity > sell_order.quantity {
+        return Err(ContractError::InsufficientSellOrderQuantity);
+    }
+
+    sell_order.quantity -= order.quantity;
+
+    if sell_order.quantity.is_zero() {
+        SELL_ORDERS.remove(deps.storage, order.sell_order_id, env.block.height)?;
+    } else {
+        SELL_ORDERS.save(
+            deps.storage,
+            order.sell_order_id,
+            &sell_order,
+            env.block.height,
+        )?;
+    }
+
+    let trade = Trade {
+        id: next_trade_id(deps.storage)?,
+        source: TradeSource::SellOrder {
+            sell_order_id: sell_order.id,
+        },
+        seller: sell_order.seller.clone(),
+        payer: payer.clone(),
+        spender: spender.cloned(),
+        beneficiary: beneficiary(deps.api, payer, order.beneficiary)?,
+        batch_denom,
+        quantity: order.quantity,
+        ask_price: price.ask_price.clone(),
+        buyer_fee: price.buyer_fee.clone(),
+        retirement: retirement(
+            sell_order.disable_auto_retire,
+            order.disable_auto_retire,
+            order.retirement_jurisdiction,
+            order.retirement_reason,
+        ),
+        timestamp: env.block.time,
+        refunded: false,
+    };
+    TRADES.save(deps.storage, trade.id, &trade)?;
+
+    let event = events::sell_order_filled(&sell_order, &trade, &price);
+    let delivery = settle_credits(
+        deps.storage,
+        env,
+        &sell_order.asset,
+        &trade,
+        &price,
+        order.on_delivery_failure,
+    )?;
+    Ok((event, trade, price, delivery))
+}
+
+/// Sends the credits of a trade as a submessage, recording a pending
+/// settlement that the reply clears or marks for retry. With `Retry` the
+/// payment is held in the settlement until the credits are delivered.
+fn settle_credits(
+    storage: &mut dyn Storage,
+    env: &Env,
+    asset: &CreditAsset,
+    trade: &Trade,
+    price: &PurchasePrice,
+    on_failure: DeliveryFailure,
+) -> Result<Option<SubMsg>, ContractError> {
+    let config = CONFIG.load(storage)?;
+    let Some(msg) = deliver_credits(&config.ecocredit, asset, env, trade)? else {
+        return Ok(None);
+    };
+    let held = (on_failure == DeliveryFailure::Retry).then(|| HeldPayment {
+        cost: Coin::new(price.cost(trade.quantity).u128(), &price.ask_price.denom),
+        proceeds: Coin::new(
+            price.proceeds(trade.quantity).u128(),
+            &price.ask_price.denom,
+        ),
+        from_balance: false,
+    });
+    let retire_custody = trade.retirement.is_some()
+        && config.ecocredit == EcocreditAdapter::Regen
+        && is_custodial(storage, asset)?;
+    SETTLEMENTS.save(
+        storage,
+        trade.id,
+        &Settlement {
+            trade_id: trade.id,
+            asset: asset.clone(),
+            on_failure,
+            error: None,
+            held,
+            retire_custody,
+        },
+    )?;
+    Ok(Some(SubMsg::reply_always(msg, settlement_reply_id(trade))))
+}
+
+/// The seller to pay for a trade, or None while its payment is held by a
+/// pending settlement.
+fn payee<'a>(storage: &dyn Storage, trade: &'a Trade) -> StdResult<Option<&'a Addr>> {
+    let held = SETTLEMENTS
+        .may_load(storage, trade.id)?
+        .is_some_and(|settlement| settlement.held.is_some());
+    Ok((!held).then_some(&trade.seller))
+}
+
+/// Pays the seller of a delivered trade from its held payment.
+fn release_payment(
+    storage: &mut dyn Storage,
+    trade_id: u64,
+    held: Option<HeldPayment>,
+) -> StdResult<Option<CosmosMsg>> {
+    let Some(held) = held else {
+        return Ok(None);
+    };
+    add_to_fee_pool(
+        storage,
+        &Coin::new(
+            (held.cost.amount - held.proceeds.amount).u128(),
+            &held.cost.denom,
+        ),
+    )?;
+    if held.proceeds.amount.is_zero() {
+        return Ok(None);
+    }
+    let trade = TRADES.load(storage, trade_id)?;
+    if held.from_balance {
+        credit_balance(storage, &trade.seller, &held.proceeds)?;
+        return Ok(None);
+    }
+    send_coin(&trade.seller, held.proceeds).map(Some)
+}
+
+/// Records that the payment held for `trade`, if any, came from the payer's
+/// deposited balance.
+fn hold_from_balance(storage: &mut dyn Storage, trade: &Trade) -> StdResult<()> {
+    let Some(mut settlement) = SETTLEMENTS.may_load(storage, trade.id)? else {
+        return Ok(());
+    };
+    if let Some(held) = &mut settlement.held {
+        held.from_balance = true;
+        SETTLEMENTS.save(storage, trade.id, &settlement)?;
+    }
+    Ok(())
+}
 
-        // Process the trade
-        let order_quantity = Uint128::from_str(&order.quantity)?;
-        let sell_order_quantity = Uint128::from_str(&sell_order.quantity)?;
+fn settlement_reply_id(trade: &Trade) -> u64 {
+    match trade.retirement {
+        Some(_) => ReplyId::Retirement { trade_id: trade.id },
+        None => ReplyId::Delivery { trade_id: trade.id },
+    }
+    .into()
+}
 
-        if order_quantity > sell_order_quantity {
-            return Err(ContractError::InsufficientSellOrderQuantity);
+/// Handles the reply to a credit delivery. A failure reverts the fill, or
+/// keeps the trade with the settlement marked for `RetrySettlement`.
+pub fn settlement_replied(
+    deps: DepsMut,
+    env: Env,
+    trade_id: u64,
+    result: SubMsgResult,
+) -> Result<Response, ContractError> {
+    let mut settlement = SETTLEMENTS.load(deps.storage, trade_id)?;
+    let error = match result {
+        SubMsgResult::Ok(_) => {
+            SETTLEMENTS.remove(deps.storage, trade_id);
+            let mut response = Response::new();
+            if settlement.retire_custody {
+                let trade = TRADES.load(deps.storage, trade_id)?;
+                if let Some(retirement) = &trade.retirement {
+                    response = response.add_message(ecocredit::retire(
+                        &env.contract.address,
+                        &trade.batch_denom,
+                        trade.quantity,
+                        retirement,
+                    ));
+                }
+            }
+            response =
+                response.add_messages(release_payment(deps.storage, trade_id, settlement.held)?);
+            if settlement.error.is_some() {
+                response = response.add_event(events::settlement_completed(trade_id));
+            }
+            return Ok(response);
         }
+        SubMsgResult::Err(error) => error,
+    };
+    if settlement.on_failure == DeliveryFailure::Revert {
+        return Err(ContractError::SettlementFailed { trade_id, error });
+    }
+    let event = events::settlement_failed(trade_id, &error);
+    settlement.error = Some(error);
+    SETTLEMENTS.save(deps.storage, trade_id, &settlement)?;
+    Ok(Response::new().add_event(event))
+}
 
-        sell_order.quantity = (sell_order_quantity - order_quantity).to_string();
+/// Sends the credits of a trade whose delivery failed again.
+pub fn retry_settlement(deps: DepsMut, env: Env, trade_id: u64) -> Result<Response, ContractError> {
+    let settlement = SETTLEMENTS
+        .may_load(deps.storage, trade_id)?
+        .filter(|settlement| settlement.error.is_some())
+        .ok_or(ContractError::NoFailedSettlement { trade_id })?;
+    let trade = TRADES.load(deps.storage, trade_id)?;
+    let config = CONFIG.load(deps.storage)?;
 
-        if sell_order.quantity == "0" {
-            SELL_ORDERS.remove(deps.storage, order.sell_order_id);
-        } else {
-            SELL_ORDERS.save(deps.storage, order.sell_order_id, &sell_order)?;
+    let response = Response::new()
+        .add_attribute("method", "retry_settlement")
+        .add_attribute("trade_id", trade_id.to_string());
+    match deliver_credits(&config.ecocredit, &settlement.asset, &env, &trade)? {
+        Some(msg) => {
+            Ok(response.add_submessage(SubMsg::reply_always(msg, settlement_reply_id(&trade))))
+        }
+        None => {
+            SETTLEMENTS.remove(deps.storage, trade_id);
+            Ok(response.add_messages(release_payment(deps.storage, trade_id, settlement.held)?))
         }
     }
+}
+
+/// Voids a trade whose delivery failed: the trade is kept but marked refunded,
+/// the payer gets the held payment back, to their deposited balance if it
+/// was paid from there, and escrowed credits return to the seller.
+pub fn refund_settlement(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    trade_id: u64,
+) -> Result<Response, ContractError> {
+    let settlement = SETTLEMENTS
+        .may_load(deps.storage, trade_id)?
+        .filter(|settlement| settlement.error.is_some())
+        .ok_or(ContractError::NoFailedSettlement { trade_id })?;
+    let trade = TRADES.load(deps.storage, trade_id)?;
+    if info.sender != trade.payer {
+        return Err(ContractError::Unauthorized {});
+    }
+    let held = settlement
+        .held
+        .ok_or(ContractError::NoFailedSettlement { trade_id })?;
+    SETTLEMENTS.remove(deps.storage, trade_id);
+    TRADES.save(
+        deps.storage,
+        trade_id,
+        &Trade {
+            refunded: true,
+            ..trade.clone()
+        },
+    )?;
+
+    let mut response = Response::new()
+        .add_event(events::settlement_refunded(trade_id, &held.cost))
+        .add_messages(transfer_credits(
+            &settlement.asset,
+            &env,
+            &trade.seller,
+            trade.quantity,
+        )?);
+    if held.from_balance {
+        credit_balance(deps.storage, &trade.payer, &held.cost)?;
+    } else if !held.cost.amount.is_zero() {
+        response = response.add_message(send_coin(&trade.payer, held.cost)?);
+    }
+
+    Ok(response
+        .add_attribute("method", "refund_settlement")
+        .add_attribute("trade_id", trade_id.to_string()))
+}
+
+pub fn buy_with_signed_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    order: Box<SignedSellOrder>,
+    signature: Binary,
+    pubkey: Binary,
+    buy: SignedOrderBuyMsg,
+) -> Result<Response, ContractError> {
+    if order.expiration <= env.block.time {
+        return Err(ContractError::SignedOrderExpired { nonce: order.nonce });
+    }
+    if order.marketplace != env.contract.address.as_str() || order.chain_id != env.block.chain_id {
+        return Err(ContractError::InvalidSignedOrder {
+            reason: "signed for another marketplace".to_string(),
+        });
+    }
+    let seller = signing::verify_signed_order(deps.api, &order, &signature, &pubkey)?;
+
+    let key = (seller.clone(), order.nonce);
+    let mut status = SIGNED_ORDERS
+        .may_load(deps.storage, key.clone())?
+        .unwrap_or_default();
+    if status.cancelled {
+        return Err(ContractError::SignedOrderCancelled { nonce: order.nonce });
+    }
+    if status.filled + buy.quantity > order.quantity {
+        return Err(ContractError::InsufficientSellOrderQuantity);
+    }
+
+    ensure_denom_allowed(deps.storage, &order.ask_price.denom)?;
+    batch_key_or_create(deps.storage, &order.batch_denom)?;
+    let market_id = market_id_or_create(deps.storage, &order.batch_denom, &order.ask_price)?;
+    ensure_not_paused(
+        deps.storage,
+        &PauseScope::Purchases,
+        market_id,
+        &order.batch_denom,
+    )?;
+
+    let fee_params = FEE_PARAMS.load(deps.storage)?;
+    let price = price_purchase(
+        deps.storage,
+        &fee_params,
+        market_id,
+        order.ask_price.amount,
+        &buy.bid_price,
+        &buy.max_fee_amount,
+    )?;
+
+    status.filled += buy.quantity;
+    SIGNED_ORDERS.save(deps.storage, key, &status)?;
+
+    let trade = Trade {
+        id: next_trade_id(deps.storage)?,
+        source: TradeSource::SignedOrder { nonce: order.nonce },
+        seller: seller.clone(),
+        payer: info.sender.clone(),
+        spender: None,
+        beneficiary: beneficiary(deps.api, &info.sender, buy.beneficiary)?,
+        batch_denom: order.batch_denom.clone(),
+        quantity: buy.quantity,
+        ask_price: price.ask_price.clone(),
+        buyer_fee: price.buyer_fee.clone(),
+        retirement: retirement(
+            order.disable_auto_retire,
+            buy.disable_auto_retire,
+            buy.retirement_jurisdiction,
+            buy.retirement_reason,
+        ),
+        timestamp: env.block.time,
+        refunded: false,
+    };
+    TRADES.save(deps.storage, trade.id, &trade)?;
+
+    let event =
+        events::signed_order_filled(order.nonce, &trade, order.quantity - status.filled, &price);
+    let delivery = settle_credits(
+        deps.storage,
+        &env,
+        &CreditAsset::Native,
+        &trade,
+        &price,
+        buy.on_delivery_failure,
+    )?;
+    if buy.use_balance {
+        hold_from_balance(deps.storage, &trade)?;
+    }
+    let seller_payee = payee(deps.storage, &trade)?;
+    let messages = settle(
+        deps.storage,
+        &info,
+        buy.use_balance,
+        &[(seller_payee, &price, trade.quantity)],
+    )?;
+
+    Ok(Response::new()
+        .add_event(event)
+        .add_submessages(delivery)
+        .add_messages(messages)
+        .add_submessages(trade_hooks(deps.storage, &trade)?)
+        .add_attribute("method", "buy_with_signed_order")
+        .add_attribute("seller", seller)
+        .add_attribute("nonce", order.nonce.to_string()))
+}
+
+/// Cancels signed orders that have not been filled completely. Cancelled
+/// nonces can never be filled again, even with a valid signature.
+pub fn cancel_signed_orders(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    nonces: Vec<u64>,
+) -> Result<Response, ContractError> {
+    for nonce in &nonces {
+        SIGNED_ORDERS.update(
+            deps.storage,
+            (info.sender.clone(), *nonce),
+            |status| -> StdResult<_> {
+                let mut status: SignedOrderStatus = status.unwrap_or_default();
+                status.cancelled = true;
+                Ok(status)
+            },
+        )?;
+    }
 
-    Ok(Response::new().add_attribute("method", "buy_direct"))
+    Ok(Response::new()
+        .add_attribute("method", "cancel_signed_orders")
+        .add_attribute(
+            "nonces",
+            nonces
+                .iter()
+                .map(|nonce| nonce.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+        ))
 }
 
 pub fn add_allowed_denom(
@@ -155,73 +834,2432 @@ pub fn add_allowed_denom(
     bank_denom: String,
     display_denom: String,
     exponent: u32,
+    kind: DenomKind,
 ) -> Result<Response, ContractError> {
-    // In a real implementation, you'd check if the sender has the authority to add denoms
+    ensure_authority(deps.storage, &info.sender)?;
 
+    let bank_denom = match kind {
+        DenomKind::Native => bank_denom,
+        DenomKind::Cw20 => cw20_denom(&deps.api.addr_validate(&bank_denom)?),
+    };
     let allowed_denom = AllowedDenom {
         bank_denom: bank_denom.clone(),
         display_denom,
         exponent,
+        kind,
     };
 
     ALLOWED_DENOMS.save(deps.storage, bank_denom.clone(), &allowed_denom)?;
 
     Ok(Response::new()
+        .add_event(events::denom_allowed(&allowed_denom))
         .add_attribute("method", "add_allowed_denom")
         .add_attribute("bank_denom", bank_denom))
 }
 
-pub fn remove_allowed_denom(
+/// Runs the action in the `msg` of tokens sent to the marketplace as if the
+/// tokens had been attached as funds by the account that sent them: cw20
+/// payments for a payable action, or cw20 and cw1155 credits escrowed by
+/// `Sell`.
+pub fn receive(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
-    denom: String,
+    receive: ReceiveMsg,
 ) -> Result<Response, ContractError> {
-    // In a real implementation, you'd check if the sender has the authority to remove denoms
+    let (sender, coin, msg) = match receive {
+        ReceiveMsg::Cw20(receive) => (
+            receive.sender,
+            Coin::new(receive.amount.u128(), cw20_denom(&info.sender)),
+            receive.msg,
+        ),
+        ReceiveMsg::Cw1155(receive) => (
+            receive.from.unwrap_or(receive.operator),
+            Coin::new(
+                receive.amount.u128(),
+                cw1155_denom(&info.sender, &receive.token_id),
+            ),
+            receive.msg,
+        ),
+    };
+    receive_funds(deps, env, &info.sender, sender, vec![coin], msg)
+}
 
-    ALLOWED_DENOMS.remove(deps.storage, denom.clone());
+pub fn batch_receive(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    receive: Cw1155BatchReceiveMsg,
+) -> Result<Response, ContractError> {
+    let funds = receive
+        .batch
+        .into_iter()
+        .map(|(token_id, amount)| Coin::new(amount.u128(), cw1155_denom(&info.sender, &token_id)))
+        .collect();
+    let sender = receive.from.unwrap_or(receive.operator);
+    receive_funds(deps, env, &info.sender, sender, funds, receive.msg)
+}
 
-    Ok(Response::new()
-        .add_attribute("method", "remove_allowed_denom")
-        .add_attribute("denom", denom))
+/// `token` is the contract that called the hook. Only it vouches for the
+/// sender, so a `Sell` run this way may only list credits held as its tokens.
+fn receive_funds(
+    deps: DepsMut,
+    env: Env,
+    token: &Addr,
+    sender: String,
+    funds: Vec<Coin>,
+    msg: Binary,
+) -> Result<Response, ContractError> {
+    let msg: ExecuteMsg = from_json(&msg)?;
+    match &msg {
+        // `sell` checks the tokens match the credits of the orders.
+        ExecuteMsg::Sell { orders } => {
+            if funds.is_empty() || funds.iter().any(|coin| coin.amount.is_zero()) {
+                return Err(ContractError::InvalidFunds {
+                    expected: "escrowed credits".to_string(),
+                    found: coins_to_string(&funds),
+                });
+            }
+            for order in orders {
+                match &order.asset {
+                    Some(CreditAssetMsg::Cw20 { contract })
+                    | Some(CreditAssetMsg::Cw1155 { contract, .. })
+                        if contract == token.as_str() => {}
+                    _ => return Err(ContractError::Unauthorized {}),
+                }
+            }
+        }
+        msg if msg.is_payable() => {
+            for coin in &funds {
+                let allowed = ALLOWED_DENOMS.may_load(deps.storage, coin.denom.clone())?;
+                if !matches!(
+                    allowed,
+                    Some(AllowedDenom {
+                        kind: DenomKind::Cw20,
+                        ..
+                    })
+                ) {
+                    return Err(ContractError::DenomNotAllowed {
+                        denom: coin.denom.clone(),
+                    });
+                }
+            }
+        }
+        _ => return Err(ContractError::NotPayable {}),
+    }
+
+    let info = MessageInfo {
+        sender: deps.api.addr_validate(&sender)?,
+        funds,
+    };
+    contract::execute(deps, env, info, msg)
 }
 
-pub fn gov_set_fee_params(
+/// Runs a native marketplace message as the equivalent `ExecuteMsg`.
+pub fn marketplace(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
-    fees: FeeParams,
+    msg: MarketplaceMsg,
+) -> Result<Response, ContractError> {
+    if msg.signer() != info.sender.as_str() {
+        return Err(ContractError::Unauthorized {});
+    }
+    contract::execute(deps, env, info, msg.into())
+}
+
+/// Seeds state from a chunk of a native marketplace genesis export. Ids are
+/// kept, and each sequence is moved past the largest id imported into it.
+pub fn import_batch(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    genesis: MarketplaceGenesis,
 ) -> Result<Response, ContractError> {
-    // In a real implementation, you'd check if the sender has the authority to set fees
+    ensure_authority(deps.storage, &info.sender)?;
+    let config = CONFIG.load(deps.storage)?;
+    let response = Response::new().add_event(events::genesis_batch_imported(&genesis));
 
-    FEE_PARAMS.save(deps.storage, &fees)?;
+    if let Some(fees) = genesis.fee_params {
+        let fees = FeeParams {
+            buyer_percentage_fee: fees.buyer_percentage_fee,
+            seller_percentage_fee: fees.seller_percentage_fee,
+        };
+        validation::validate_fee_params(&fees)?;
+        FEE_PARAMS.save(deps.storage, &fees, env.block.height)?;
+    }
+    for denom in genesis.allowed_denoms {
+        let allowed_denom = AllowedDenom {
+            bank_denom: denom.bank_denom,
+            display_denom: denom.display_denom,
+            exponent: denom.exponent,
+            kind: DenomKind::Native,
+        };
+        ALLOWED_DENOMS.save(
+            deps.storage,
+            allowed_denom.bank_denom.clone(),
+            &allowed_denom,
+        )?;
+    }
+    for batch in genesis.batches {
+        import_batch_record(
+            deps.storage,
+            Batch {
+                key: batch.key,
+                denom: batch.denom,
+            },
+        )?;
+    }
+    for market in genesis.markets {
+        import_market(
+            deps.storage,
+            Market {
+                id: market.id,
+                credit_type_abbrev: market.credit_type_abbrev,
+                bank_denom: market.bank_denom,
+                precision_modifier: market.precision_modifier,
+            },
+        )?;
+    }
+    for order in genesis.sell_orders {
+        let invalid = |reason: &str| ContractError::InvalidGenesis {
+            record: format!("sell order {}", order.id),
+            reason: reason.to_string(),
+        };
+        let seller = deps
+            .api
+            .addr_humanize(&CanonicalAddr::from(order.seller.clone()))
+            .map_err(|_| invalid("seller is not an address"))?;
+        let quantity = proto::parse_whole_amount(&order.quantity)
+            .map_err(|reason| invalid(&format!("quantity: {}", reason)))?;
+        let ask_amount = proto::parse_whole_amount(&order.ask_amount)
+            .map_err(|reason| invalid(&format!("ask_amount: {}", reason)))?;
+        let batch_metadata = match BATCHES.may_load(deps.storage, order.batch_key)? {
+            Some(batch) => batch_metadata(&deps.querier, &config.ecocredit, &batch.denom)?,
+            None => None,
+        };
+        let sell_order = SellOrder {
+            id: order.id,
+            seller,
+            batch_key: order.batch_key,
+            quantity,
+            market_id: order.market_id,
+            ask_amount,
+            disable_auto_retire: order.disable_auto_retire,
+            expiration: order.expiration,
+            maker: order.maker,
+            version: 1,
+            allowed_buyers: None,
+            batch_metadata,
+            asset: CreditAsset::Native,
+        };
+        import_sell_order(deps.storage, sell_order, env.block.height)?;
+    }
 
-    Ok(Response::new().add_attribute("method", "gov_set_fee_params"))
+    Ok(response.add_attribute("method", "import_batch"))
 }
 
-pub fn gov_send_from_fee_pool(
+/// Imports a page of an `ExportState` dump.
+pub fn import_state(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    records: StateRecords,
+    import_authority: bool,
+) -> Result<Response, ContractError> {
+    ensure_authority(deps.storage, &info.sender)?;
+    let event = import_records(deps, &env, records, import_authority)?;
+
+    Ok(Response::new()
+        .add_event(event)
+        .add_attribute("method", "import_state"))
+}
+
+/// Saves one page of an `ExportState` dump. Addresses are checked against
+/// this chain, so a dump can only be imported where its accounts are valid.
+/// An imported config keeps the current authority unless `import_authority`.
+pub fn import_records(
+    deps: DepsMut,
+    env: &Env,
+    records: StateRecords,
+    import_authority: bool,
+) -> Result<Event, ContractError> {
+    let event = events::state_imported(&records);
+
+    match records {
+        StateRecords::Config(config) => {
+            let authority = if import_authority {
+                deps.api.addr_validate(config.authority.as_str())?
+            } else {
+                CONFIG.load(deps.storage)?.authority
+            };
+            CONFIG.save(
+                deps.storage,
+                &Config {
+                    authority,
+                    ..config
+                },
+            )?;
+        }
+        StateRecords::FeeParams(fees) => {
+            validation::validate_fee_params(&fees)?;
+            FEE_PARAMS.save(deps.storage, &fees, env.block.height)?;
+        }
+        StateRecords::FeePool(coins) => {
+            for coin in coins {
+                FEE_POOL.save(deps.storage, coin.denom, &coin.amount)?;
+            }
+        }
+        StateRecords::Sequences(sequences) => {
+            advance_seq(deps.storage, &SELL_ORDER_SEQ, sequences.sell_order)?;
+            advance_seq(deps.storage, &BATCH_SEQ, sequences.batch)?;
+            advance_seq(deps.storage, &MARKET_SEQ, sequences.market)?;
+            advance_seq(deps.storage, &TRADE_SEQ, sequences.trade)?;
+            advance_seq(deps.storage, &OFFER_SEQ, sequences.offer)?;
+            advance_seq(deps.storage, &COLLECTION_BID_SEQ, sequences.collection_bid)?;
+            advance_seq(deps.storage, &BUNDLE_SEQ, sequences.bundle)?;
+            advance_seq(deps.storage, &HOOK_SEQ, sequences.hook)?;
+        }
+        StateRecords::AllowedDenoms(denoms) => {
+            for denom in denoms {
+                ALLOWED_DENOMS.save(deps.storage, denom.bank_denom.clone(), &denom)?;
+            }
+        }
+        StateRecords::Batches(batches) => {
+            for batch in batches {
+                import_batch_record(deps.storage, batch)?;
+            }
+        }
+        StateRecords::Markets(markets) => {
+            for market in markets {
+                import_market(deps.storage, market)?;
+            }
+        }
+        StateRecords::SellOrders(sell_orders) => {
+            for sell_order in sell_orders {
+                deps.api.addr_validate(sell_order.seller.as_str())?;
+                import_sell_order(deps.storage, sell_order, env.block.height)?;
+            }
+        }
+        StateRecords::Balances(balances) => {
+            for record in balances {
+                deps.api.addr_validate(record.account.as_str())?;
+                BALANCES.save(
+                    deps.storage,
+                    (record.account, record.balance.denom),
+                    &record.balance.amount,
+                )?;
+            }
+        }
+        StateRecords::Offers(offers) => {
+            for offer in offers {
+                deps.api.addr_validate(offer.buyer.as_str())?;
+                OFFERS.save(deps.storage, offer.id, &offer)?;
+                advance_seq(deps.storage, &OFFER_SEQ, offer.id)?;
+            }
+        }
+        StateRecords::CollectionBids(bids) => {
+            for bid in bids {
+                deps.api.addr_validate(bid.buyer.as_str())?;
+                COLLECTION_BIDS.save(deps.storage, bid.id, &bid)?;
+                advance_seq(deps.storage, &COLLECTION_BID_SEQ, bid.id)?;
+            }
+        }
+        StateRecords::Bundles(bundles) => {
+            for bundle in bundles {
+                deps.api.addr_validate(bundle.seller.as_str())?;
+                BUNDLES.save(deps.storage, bundle.id, &bundle)?;
+                advance_seq(deps.storage, &BUNDLE_SEQ, bundle.id)?;
+            }
+        }
+        StateRecords::Trades(trades) => {
+            for trade in trades {
+                deps.api.addr_validate(trade.seller.as_str())?;
+                deps.api.addr_validate(trade.payer.as_str())?;
+                TRADES.save(deps.storage, trade.id, &trade)?;
+                advance_seq(deps.storage, &TRADE_SEQ, trade.id)?;
+            }
+        }
+        StateRecords::PurchaseAllowances(allowances) => {
+            for record in allowances {
+                deps.api.addr_validate(record.owner.as_str())?;
+                deps.api.addr_validate(record.spender.as_str())?;
+                PURCHASE_ALLOWANCES.save(
+                    deps.storage,
+                    (record.owner, record.spender),
+                    &record.allowance,
+                )?;
+            }
+        }
+        StateRecords::SignedOrders(signed_orders) => {
+            for record in signed_orders {
+                deps.api.addr_validate(record.seller.as_str())?;
+                SIGNED_ORDERS.save(deps.storage, (record.seller, record.nonce), &record.status)?;
+            }
+        }
+        StateRecords::Pauses(pauses) => {
+            for scope in pauses {
+                PAUSES.save(deps.storage, scope.key(), &scope)?;
+            }
+        }
+        StateRecords::Hooks(hooks) => {
+            for hook in hooks {
+                deps.api.addr_validate(hook.contract.as_str())?;
+                HOOKS.save(deps.storage, hook.id, &hook)?;
+                advance_seq(deps.storage, &HOOK_SEQ, hook.id)?;
+            }
+        }
+        StateRecords::CreditTokens(tokens) => {
+            for token in tokens {
+                let Some(denom) = token.asset.escrow_denom() else {
+                    return Err(ContractError::InvalidGenesis {
+                        record: format!("credit token of {}", token.batch_denom),
+                        reason: "is not a cw20 or cw1155 token".to_string(),
+                    });
+                };
+                CREDIT_TOKENS.save(deps.storage, denom, &token)?;
+            }
+        }
+        StateRecords::Settlements(settlements) => {
+            for settlement in settlements {
+                SETTLEMENTS.save(deps.storage, settlement.trade_id, &settlement)?;
+            }
+        }
+        StateRecords::CrankCursor(cursor) => CRANK_CURSOR.save(deps.storage, &cursor)?,
+        StateRecords::ContractVersion(version) => {
+            if version.contract != contract::CONTRACT_NAME {
+                return Err(ContractError::WrongContract {
+                    expected: contract::CONTRACT_NAME.to_string(),
+                    found: version.contract,
+                });
+            }
+            let exported: Version = version.version.parse()?;
+            if exported > contract::CONTRACT_VERSION.parse::<Version>()? {
+                return Err(ContractError::CannotDowngrade {
+                    stored: version.version,
+                    current: contract::CONTRACT_VERSION.to_string(),
+                });
+            }
+        }
+        // A dump taken mid-import stays closed to trading until `Finalize`.
+        StateRecords::GenesisImport(importing) => {
+            if importing {
+                GENESIS_IMPORT.save(deps.storage, &true)?;
+            }
+        }
+    }
+
+    Ok(event)
+}
+
+fn import_batch_record(storage: &mut dyn Storage, batch: Batch) -> StdResult<()> {
+    BATCH_KEYS.save(storage, batch.denom.clone(), &batch.key)?;
+    BATCHES.save(storage, batch.key, &batch)?;
+    advance_seq(storage, &BATCH_SEQ, batch.key)
+}
+
+fn import_market(storage: &mut dyn Storage, market: Market) -> StdResult<()> {
+    MARKET_IDS.save(
+        storage,
+        (market.credit_type_abbrev.clone(), market.bank_denom.clone()),
+        &market.id,
+    )?;
+    MARKETS.save(storage, market.id, &market)?;
+    advance_seq(storage, &MARKET_SEQ, market.id)
+}
+
+fn import_sell_order(
+    storage: &mut dyn Storage,
+    sell_order: SellOrder,
+    height: u64,
+) -> Result<(), ContractError> {
+    if SELL_ORDERS.may_load(storage, sell_order.id)?.is_some() {
+        return Err(ContractError::InvalidGenesis {
+            record: format!("sell order {}", sell_order.id),
+            reason: "is imported twice".to_string(),
+        });
+    }
+    SELL_ORDERS.save(storage, sell_order.id, &sell_order, height)?;
+    advance_seq(storage, &SELL_ORDER_SEQ, sell_order.id)?;
+    Ok(())
+}
+
+/// Checks that every imported sell order refers to an imported batch and
+/// market, then opens trading.
+pub fn finalize_import(
     deps: DepsMut,
     _env: Env,
     info: MessageInfo,
-    recipient: String,
-    coins: Vec<Coin>,
 ) -> Result<Response, ContractError> {
-    // In a real implementation, you'd check if the sender has the authority to send from the fee pool
-    // You'd also need to implement the actual transfer of coins from the fee pool
+    ensure_authority(deps.storage, &info.sender)?;
+    let sell_orders = check_imported_sell_orders(deps.storage)?;
+    GENESIS_IMPORT.remove(deps.storage);
 
     Ok(Response::new()
-        .add_attribute("method", "gov_send_from_fee_pool")
-        .add_attribute("recipient", recipient))
+        .add_event(events::genesis_import_finalized(sell_orders))
+        .add_attribute("method", "finalize"))
 }
 
-fn calculate_fee(price: &Coin, fee_percentage: &str) -> Result<Coin, ContractError> {
-    let fee_percentage =
-        Uint128::from_str(fee_percentage).map_err(|_| ContractError::InvalidInput {})?;
-    let fee_amount = price
-        .amount
-        .multiply_ratio(fee_percentage, Uint128::new(10000)); // Assuming fee is in basis points
-    Ok(Coin {
-        denom: price.denom.clone(),
-        amount: fee_amount,
-    })
+/// Counts the sell orders, failing on any whose batch or market is missing.
+pub fn check_imported_sell_orders(storage: &dyn Storage) -> Result<usize, ContractError> {
+    let mut sell_orders = 0;
+    for item in SELL_ORDERS.range(storage, None, None, Order::Ascending) {
+        let (id, sell_order) = item?;
+        let missing = |what: String| ContractError::InvalidGenesis {
+            record: format!("sell order {}", id),
+            reason: format!("{} was not imported", what),
+        };
+        if !BATCHES.has(storage, sell_order.batch_key) {
+            return Err(missing(format!("batch {}", sell_order.batch_key)));
+        }
+        if !MARKETS.has(storage, sell_order.market_id) {
+            return Err(missing(format!("market {}", sell_order.market_id)));
+        }
+        sell_orders += 1;
+    }
+    Ok(sell_orders)
+}
+
+fn advance_seq(storage: &mut dyn Storage, seq: &Item<u64>, id: u64) -> StdResult<()> {
+    let current = seq.may_load(storage)?.unwrap_or_default();
+    seq.save(storage, &current.max(id))
+}
+
+/// Scans up to `limit` records, resuming where the last crank stopped, and
+/// removes the expired ones. Escrowed credits and funds go back to their
+/// owners, and the sender is paid out of the fee pool for each expired offer
+/// or collection bid that held funds (see `take_from_fee_pool`).
+pub fn crank(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    limit: u32,
+) -> Result<Response, ContractError> {
+    let expired = |expiration: &Option<Timestamp>| matches!(expiration, Some(expiration) if *expiration <= env.block.time);
+    let mut cursor = CRANK_CURSOR.may_load(deps.storage)?.unwrap_or_default();
+    let mut budget = limit as usize;
+    let mut scanned = 0;
+    let mut removed = 0;
+    let mut rewarded = BTreeMap::<String, u32>::new();
+    let mut response = Response::new();
+
+    // Each stage is visited at most once, so a crank over little state ends
+    // with budget to spare.
+    for _ in 0..CrankCursor::STAGES {
+        if budget == 0 {
+            break;
+        }
+        let (count, next) = match cursor {
+            CrankCursor::SellOrders { start_after } => {
+                let range = SELL_ORDERS.range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                );
+                let scan = Scan::run(range, budget, |order| expired(&order.expiration))?;
+                let next = scan.resume(
+                    |start_after| CrankCursor::SellOrders { start_after },
+                    CrankCursor::Offers { start_after: None },
+                );
+                for (id, sell_order) in scan.expired {
+                    SELL_ORDERS.remove(deps.storage, id, env.block.height)?;
+                    let refund = transfer_credits(
+                        &sell_order.asset,
+                        &env,
+                        &sell_order.seller,
+                        sell_order.quantity,
+                    )?;
+                    response = response
+                        .add_event(events::record_expired(
+                            "sell_order",
+                            &id.to_string(),
+                            &sell_order.seller,
+                        ))
+                        .add_messages(refund);
+                    removed += 1;
+                }
+                (scan.scanned, next)
+            }
+            CrankCursor::Offers { start_after } => {
+                let range = OFFERS.range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                );
+                let scan = Scan::run(range, budget, |offer| expired(&offer.expiration))?;
+                let next = scan.resume(
+                    |start_after| CrankCursor::Offers { start_after },
+                    CrankCursor::CollectionBids { start_after: None },
+                );
+                for (id, offer) in scan.expired {
+                    OFFERS.remove(deps.storage, id);
+                    response = response.add_event(events::record_expired(
+                        "offer",
+                        &id.to_string(),
+                        &offer.buyer,
+                    ));
+                    if !offer.escrow.amount.is_zero() {
+                        *rewarded.entry(offer.escrow.denom.clone()).or_default() += 1;
+                        response = response.add_message(send_coin(&offer.buyer, offer.escrow)?);
+                    }
+                    removed += 1;
+                }
+                (scan.scanned, next)
+            }
+            CrankCursor::CollectionBids { start_after } => {
+                let range = COLLECTION_BIDS.range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                );
+                let scan = Scan::run(range, budget, |bid| expired(&bid.expiration))?;
+                let next = scan.resume(
+                    |start_after| CrankCursor::CollectionBids { start_after },
+                    CrankCursor::Bundles { start_after: None },
+                );
+                for (id, bid) in scan.expired {
+                    COLLECTION_BIDS.remove(deps.storage, id);
+                    response = response.add_event(events::record_expired(
+                        "collection_bid",
+                        &id.to_string(),
+                        &bid.buyer,
+                    ));
+                    if !bid.escrow.amount.is_zero() {
+                        *rewarded.entry(bid.escrow.denom.clone()).or_default() += 1;
+                        response = response.add_message(send_coin(&bid.buyer, bid.escrow)?);
+                    }
+                    removed += 1;
+                }
+                (scan.scanned, next)
+            }
+            CrankCursor::Bundles { start_after } => {
+                let range = BUNDLES.range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                );
+                let scan = Scan::run(range, budget, |bundle| expired(&bundle.expiration))?;
+                let next = scan.resume(
+                    |start_after| CrankCursor::Bundles { start_after },
+                    CrankCursor::PurchaseAllowances { start_after: None },
+                );
+                for (id, bundle) in scan.expired {
+                    BUNDLES.remove(deps.storage, id);
+                    response = response.add_event(events::record_expired(
+                        "bundle",
+                        &id.to_string(),
+                        &bundle.seller,
+                    ));
+                    removed += 1;
+                }
+                (scan.scanned, next)
+            }
+            CrankCursor::PurchaseAllowances { start_after } => {
+                let range = PURCHASE_ALLOWANCES.range(
+                    deps.storage,
+                    start_after.map(Bound::exclusive),
+                    None,
+                    Order::Ascending,
+                );
+                let scan = Scan::run(range, budget, |allowance| expired(&allowance.expiration))?;
+                let next = scan.resume(
+                    |start_after| CrankCursor::PurchaseAllowances { start_after },
+                    CrankCursor::default(),
+                );
+                for ((owner, spender), _) in scan.expired {
+                    PURCHASE_ALLOWANCES.remove(deps.storage, (owner.clone(), spender.clone()));
+                    response = response.add_event(events::record_expired(
+                        "purchase_allowance",
+                        spender.as_str(),
+                        &owner,
+                    ));
+                    removed += 1;
+                }
+                (scan.scanned, next)
+            }
+        };
+        scanned += count;
+        budget -= count;
+        cursor = next;
+    }
+    CRANK_CURSOR.save(deps.storage, &cursor)?;
+
+    let share = CONFIG.load(deps.storage)?.crank_reward_share;
+    let reward = take_from_fee_pool(deps.storage, share, &rewarded)?;
+    for coin in &reward {
+        response = response.add_message(send_coin(&info.sender, coin.clone())?);
+    }
+
+    Ok(response
+        .add_event(events::crank(&info.sender, scanned, removed, &reward))
+        .add_attribute("method", "crank"))
+}
+
+/// One stage of a crank: the records scanned from a range, the expired ones
+/// among them, and whether the range ran out.
+struct Scan<K, T> {
+    scanned: usize,
+    expired: Vec<(K, T)>,
+    last: Option<K>,
+    exhausted: bool,
+}
+
+impl<K: Clone, T> Scan<K, T> {
+    fn run(
+        range: impl Iterator<Item = StdResult<(K, T)>>,
+        limit: usize,
+        is_expired: impl Fn(&T) -> bool,
+    ) -> StdResult<Self> {
+        let mut scan = Scan {
+            scanned: 0,
+            expired: vec![],
+            last: None,
+            exhausted: true,
+        };
+        for item in range {
+            if scan.scanned == limit {
+                scan.exhausted = false;
+                break;
+            }
+            let (key, record) = item?;
+            scan.scanned += 1;
+            scan.last = Some(key.clone());
+            if is_expired(&record) {
+                scan.expired.push((key, record));
+            }
+        }
+        Ok(scan)
+    }
+
+    /// Cursor of the next crank: this stage after the last key scanned, or
+    /// `next_stage` once the range ran out.
+    fn resume(
+        &self,
+        stage: impl FnOnce(Option<K>) -> CrankCursor,
+        next_stage: CrankCursor,
+    ) -> CrankCursor {
+        if self.exhausted {
+            next_stage
+        } else {
+            stage(self.last.clone())
+        }
+    }
+}
+
+/// Takes the crank reward from the fee pool: for each denom, `share` of the
+/// pool split into `MAX_CRANK_LIMIT` parts, one part per record rewarded. A
+/// crank therefore never takes more than `share` of the pool, however large
+/// the escrow it refunds.
+fn take_from_fee_pool(
+    storage: &mut dyn Storage,
+    share: Decimal,
+    rewarded: &BTreeMap<String, u32>,
+) -> StdResult<Vec<Coin>> {
+    let mut taken = vec![];
+    for (denom, records) in rewarded {
+        let pool = FEE_POOL
+            .may_load(storage, denom.clone())?
+            .unwrap_or_default();
+        let amount = pool
+            .mul_floor(share)
+            .multiply_ratio(*records, validation::MAX_CRANK_LIMIT);
+        if amount.is_zero() {
+            continue;
+        }
+        if amount == pool {
+            FEE_POOL.remove(storage, denom.clone());
+        } else {
+            FEE_POOL.save(storage, denom.clone(), &(pool - amount))?;
+        }
+        taken.push(Coin::new(amount.u128(), denom));
+    }
+    Ok(taken)
+}
+
+pub fn gov_set_crank_reward_share(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    share: Decimal,
+) -> Result<Response, ContractError> {
+    ensure_authority(deps.storage, &info.sender)?;
+    let mut config = CONFIG.load(deps.storage)?;
+    let before = config.crank_reward_share;
+    config.crank_reward_share = share;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_event(events::crank_reward_share_updated(before, share))
+        .add_attribute("method", "gov_set_crank_reward_share"))
+}
+
+pub fn add_hook(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    event: HookEvent,
+    contract: String,
+    required: bool,
+) -> Result<Response, ContractError> {
+    ensure_authority(deps.storage, &info.sender)?;
+    let contract = deps.api.addr_validate(&contract)?;
+    if find_hook(deps.storage, &event, &contract)?.is_some() {
+        return Err(ContractError::HookExists {
+            event: event.as_str().to_string(),
+            contract: contract.to_string(),
+        });
+    }
+
+    let id = HOOK_SEQ.may_load(deps.storage)?.unwrap_or_default() + 1;
+    HOOK_SEQ.save(deps.storage, &id)?;
+    let hook = Hook {
+        id,
+        event,
+        contract,
+        required,
+    };
+    HOOKS.save(deps.storage, id, &hook)?;
+
+    Ok(Response::new()
+        .add_event(events::hook_changed("hook_added", &hook))
+        .add_attribute("method", "add_hook")
+        .add_attribute("hook_id", id.to_string()))
+}
+
+pub fn add_credit_token(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    batch_denom: String,
+    asset: CreditAssetMsg,
+    custodial: bool,
+) -> Result<Response, ContractError> {
+    ensure_authority(deps.storage, &info.sender)?;
+    if custodial && CONFIG.load(deps.storage)?.ecocredit == EcocreditAdapter::Disabled {
+        return Err(ContractError::InvalidField {
+            field: "custodial".to_string(),
+            reason: "needs the regen ecocredit adapter".to_string(),
+        });
+    }
+    let asset = credit_asset(deps.api, asset)?;
+    let token = asset
+        .escrow_denom()
+        .ok_or_else(|| ContractError::InvalidField {
+            field: "asset".to_string(),
+            reason: "must be a cw20 or cw1155 token".to_string(),
+        })?;
+    let credit_token = CreditToken {
+        batch_denom,
+        asset,
+        custodial,
+    };
+    CREDIT_TOKENS.save(deps.storage, token.clone(), &credit_token)?;
+
+    Ok(Response::new()
+        .add_event(events::credit_token_changed(
+            "credit_token_added",
+            &token,
+            &credit_token,
+        ))
+        .add_attribute("method", "add_credit_token")
+        .add_attribute("token", token))
+}
+
+pub fn remove_credit_token(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    asset: CreditAssetMsg,
+) -> Result<Response, ContractError> {
+    ensure_authority(deps.storage, &info.sender)?;
+    let token = credit_asset(deps.api, asset)?
+        .escrow_denom()
+        .unwrap_or_default();
+    let credit_token = CREDIT_TOKENS
+        .may_load(deps.storage, token.clone())?
+        .ok_or_else(|| ContractError::CreditTokenNotFound {
+            token: token.clone(),
+        })?;
+    CREDIT_TOKENS.remove(deps.storage, token.clone());
+
+    Ok(Response::new()
+        .add_event(events::credit_token_changed(
+            "credit_token_removed",
+            &token,
+            &credit_token,
+        ))
+        .add_attribute("method", "remove_credit_token")
+        .add_attribute("token", token))
+}
+
+fn credit_asset(api: &dyn Api, asset: CreditAssetMsg) -> StdResult<CreditAsset> {
+    Ok(match asset {
+        CreditAssetMsg::Native => CreditAsset::Native,
+        CreditAssetMsg::Cw20 { contract } => CreditAsset::Cw20 {
+            contract: api.addr_validate(&contract)?,
+        },
+        CreditAssetMsg::Cw1155 { contract, token_id } => CreditAsset::Cw1155 {
+            contract: api.addr_validate(&contract)?,
+            token_id,
+        },
+    })
+}
+
+pub fn remove_hook(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    event: HookEvent,
+    contract: String,
+) -> Result<Response, ContractError> {
+    ensure_authority(deps.storage, &info.sender)?;
+    let contract = deps.api.addr_validate(&contract)?;
+    let hook =
+        find_hook(deps.storage, &event, &contract)?.ok_or_else(|| ContractError::HookNotFound {
+            event: event.as_str().to_string(),
+            contract: contract.to_string(),
+        })?;
+    HOOKS.remove(deps.storage, hook.id);
+
+    Ok(Response::new()
+        .add_event(events::hook_changed("hook_removed", &hook))
+        .add_attribute("method", "remove_hook")
+        .add_attribute("hook_id", hook.id.to_string()))
+}
+
+fn find_hook(storage: &dyn Storage, event: &HookEvent, contract: &Addr) -> StdResult<Option<Hook>> {
+    for item in HOOKS.range(storage, None, None, Order::Ascending) {
+        let (_, hook) = item?;
+        if hook.event == *event && hook.contract == *contract {
+            return Ok(Some(hook));
+        }
+    }
+    Ok(None)
+}
+
+/// Calls to the hooks subscribed to the event of `msg`. Optional hooks reply
+/// on error so their failure can be logged instead of failing the
+/// transaction.
+fn hook_messages(storage: &dyn Storage, msg: HookMsg) -> StdResult<Vec<SubMsg>> {
+    let event = msg.event();
+    let hooks = HOOKS
+        .range(storage, None, None, Order::Ascending)
+        .filter(|item| !matches!(item, Ok((_, hook)) if hook.event != event))
+        .collect::<StdResult<Vec<_>>>()?;
+    if hooks.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let msg = to_json_binary(&HookExecuteMsg::MarketplaceHook(msg))?;
+    Ok(hooks
+        .into_iter()
+        .map(|(id, hook)| {
+            let call = WasmMsg::Execute {
+                contract_addr: hook.contract.to_string(),
+                msg: msg.clone(),
+                funds: vec![],
+            };
+            if hook.required {
+                SubMsg::new(call)
+            } else {
+                SubMsg::reply_on_error(call, ReplyId::Hook { hook_id: id }.into())
+            }
+        })
+        .collect())
+}
+
+/// `OrderFilled` hooks for a trade, and `CreditsRetired` hooks when it
+/// retires the credits.
+fn trade_hooks(storage: &dyn Storage, trade: &Trade) -> StdResult<Vec<SubMsg>> {
+    let mut hooks = hook_messages(
+        storage,
+        HookMsg::OrderFilled {
+            trade: trade.clone(),
+        },
+    )?;
+    if trade.retirement.is_some() {
+        hooks.extend(hook_messages(
+            storage,
+            HookMsg::CreditsRetired {
+                trade: trade.clone(),
+            },
+        )?);
+    }
+    Ok(hooks)
+}
+
+/// Logs the failure of an optional hook. Its call was reverted on its own.
+pub fn hook_failed(
+    deps: DepsMut,
+    _env: Env,
+    hook_id: u64,
+    result: SubMsgResult,
+) -> Result<Response, ContractError> {
+    let SubMsgResult::Err(error) = result else {
+        return Ok(Response::new());
+    };
+    let hook = HOOKS.may_load(deps.storage, hook_id)?;
+    Ok(Response::new().add_event(events::hook_failed(hook_id, hook.as_ref(), &error)))
+}
+
+/// Open orders priced in a removed denom are frozen rather than cancelled:
+/// they cannot be bought or updated until the denom is allowed again, but
+/// sellers can still reprice them into an allowed denom or cancel them.
+pub fn remove_allowed_denom(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    denom: String,
+) -> Result<Response, ContractError> {
+    ensure_authority(deps.storage, &info.sender)?;
+
+    ALLOWED_DENOMS.remove(deps.storage, denom.clone());
+
+    Ok(Response::new()
+        .add_event(events::denom_removed(&denom))
+        .add_attribute("method", "remove_allowed_denom")
+        .add_attribute("denom", denom))
+}
+
+pub fn gov_set_fee_params(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    fees: FeeParams,
+) -> Result<Response, ContractError> {
+    ensure_authority(deps.storage, &info.sender)?;
+
+    let before = FEE_PARAMS.load(deps.storage)?;
+    FEE_PARAMS.save(deps.storage, &fees, env.block.height)?;
+
+    Ok(Response::new()
+        .add_event(events::fee_params_updated(&before, &fees))
+        .add_attribute("method", "gov_set_fee_params"))
+}
+
+/// Pays out collected fees. Cw20 fees are sent with a cw20 `Transfer`.
+pub fn gov_send_from_fee_pool(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    recipient: String,
+    coins: Vec<Coin>,
+) -> Result<Response, ContractError> {
+    ensure_authority(deps.storage, &info.sender)?;
+    let recipient = deps.api.addr_validate(&recipient)?;
+
+    let mut response = Response::new();
+    for coin in &coins {
+        let pool = FEE_POOL
+            .may_load(deps.storage, coin.denom.clone())?
+            .unwrap_or_default();
+        if pool < coin.amount {
+            return Err(ContractError::InsufficientBalance {
+                balance: Coin::new(pool.u128(), &coin.denom),
+                required: coin.clone(),
+            });
+        }
+        if pool == coin.amount {
+            FEE_POOL.remove(deps.storage, coin.denom.clone());
+        } else {
+            FEE_POOL.save(deps.storage, coin.denom.clone(), &(pool - coin.amount))?;
+        }
+        response = response.add_message(send_coin(&recipient, coin.clone())?);
+    }
+
+    Ok(response
+        .add_event(events::fee_pool_sent(recipient.as_str(), &coins))
+        .add_attribute("method", "gov_send_from_fee_pool")
+        .add_attribute("recipient", recipient))
+}
+
+pub fn deposit(deps: DepsMut, _env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+    if info.funds.is_empty() {
+        return Err(ContractError::EmptyField {
+            field: "funds".to_string(),
+        });
+    }
+
+    for coin in &info.funds {
+        credit_balance(deps.storage, &info.sender, coin)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("method", "deposit")
+        .add_attribute("account", info.sender))
+}
+
+pub fn withdraw(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    coins: Vec<Coin>,
+) -> Result<Response, ContractError> {
+    for coin in &coins {
+        debit_balance(deps.storage, &info.sender, coin)?;
+    }
+
+    let messages = coins
+        .into_iter()
+        .map(|coin| send_coin(&info.sender, coin))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_attribute("method", "withdraw")
+        .add_attribute("account", info.sender))
+}
+
+pub fn grant_purchase_allowance(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    spender: String,
+    allowance: PurchaseAllowance,
+) -> Result<Response, ContractError> {
+    let spender = deps.api.addr_validate(&spender)?;
+
+    PURCHASE_ALLOWANCES.save(
+        deps.storage,
+        (info.sender.clone(), spender.clone()),
+        &allowance,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("method", "grant_purchase_allowance")
+        .add_attribute("owner", info.sender)
+        .add_attribute("spender", spender)
+        .add_attribute("limit", allowance.remaining)
+        .add_attribute("denom", allowance.denom))
+}
+
+pub fn revoke_allowance(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    spender: String,
+) -> Result<Response, ContractError> {
+    let spender = deps.api.addr_validate(&spender)?;
+
+    PURCHASE_ALLOWANCES.remove(deps.storage, (info.sender.clone(), spender.clone()));
+
+    Ok(Response::new()
+        .add_attribute("method", "revoke_allowance")
+        .add_attribute("owner", info.sender)
+        .add_attribute("spender", spender))
+}
+
+/// Opens an offer with the attached funds in escrow.
+#[allow(clippy::too_many_arguments)]
+pub fn make_offer(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    sell_order_id: u64,
+    quantity: Uint128,
+    price: Coin,
+    expiration: Option<Timestamp>,
+    disable_auto_retire: bool,
+    merkle_proof: Option<Vec<HexBinary>>,
+    on_delivery_failure: DeliveryFailure,
+) -> Result<Response, ContractError> {
+    let sell_order = SELL_ORDERS.load(deps.storage, sell_order_id)?;
+    if sell_order.seller == info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    ensure_sell_order_live(&env, &sell_order)?;
+    ensure_allowed_buyer(&sell_order, &info.sender, merkle_proof.as_deref())?;
+    ensure_not_paused(
+        deps.storage,
+        &PauseScope::Purchases,
+        sell_order.market_id,
+        &batch_denom(deps.storage, sell_order.batch_key)?,
+    )?;
+
+    let cost = offer_cost(deps.storage, &sell_order, quantity, &price)?;
+    let escrow = offer_funds(&info, &price.denom)?;
+    if escrow != cost.amount {
+        return Err(ContractError::InvalidFunds {
+            expected: cost.to_string(),
+            found: coins_to_string(&info.funds),
+        });
+    }
+
+    let offer = Offer {
+        id: next_offer_id(deps.storage)?,
+        sell_order_id,
+        buyer: info.sender,
+        quantity,
+        price,
+        expiration,
+        proposer: OfferParty::Buyer,
+        escrow: cost,
+        disable_auto_retire,
+        merkle_proof,
+        on_delivery_failure,
+    };
+    OFFERS.save(deps.storage, offer.id, &offer)?;
+
+    Ok(Response::new()
+        .add_event(events::offer_proposed("offer_made", &offer))
+        .add_attribute("method", "make_offer")
+        .add_attribute("offer_id", offer.id.to_string()))
+}
+
+/// Replaces the terms of an offer with the sender's. The buyer's escrow must
+/// cover the new terms; any surplus is refunded when the offer settles.
+pub fn counter_offer(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    offer_id: u64,
+    quantity: Uint128,
+    price: Coin,
+    expiration: Option<Timestamp>,
+) -> Result<Response, ContractError> {
+    let mut offer = load_open_offer(deps.storage, &env, offer_id)?;
+    let sell_order = SELL_ORDERS.load(deps.storage, offer.sell_order_id)?;
+    let party = offer_party(&offer, &sell_order, &info.sender)?;
+
+    let cost = offer_cost(deps.storage, &sell_order, quantity, &price)?;
+    if price.denom != offer.escrow.denom {
+        return Err(ContractError::BidDenomMismatch {
+            expected: offer.escrow.denom,
+            found: price.denom,
+        });
+    }
+    add_offer_funds(&mut offer, &info, &party)?;
+    // The seller may ask for more than the escrow covers; the buyer tops it
+    // up on acceptance.
+    if party == OfferParty::Buyer {
+        ensure_escrow_covers(&offer, &cost)?;
+    }
+
+    offer.quantity = quantity;
+    offer.price = price;
+    offer.expiration = expiration;
+    offer.proposer = party;
+    OFFERS.save(deps.storage, offer_id, &offer)?;
+
+    Ok(Response::new()
+        .add_event(events::offer_proposed("offer_countered", &offer))
+        .add_attribute("method", "counter_offer")
+        .add_attribute("offer_id", offer_id.to_string()))
+}
+
+/// Settles an offer on the terms the other party proposed, with the same fees
+/// as `buy_direct`. The seller is paid and any surplus escrow is refunded.
+pub fn accept_offer(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    offer_id: u64,
+) -> Result<Response, ContractError> {
+    let mut offer = load_open_offer(deps.storage, &env, offer_id)?;
+    let sell_order = SELL_ORDERS.load(deps.storage, offer.sell_order_id)?;
+    let party = offer_party(&offer, &sell_order, &info.sender)?;
+    if party == offer.proposer {
+        return Err(ContractError::OwnOfferTerms { offer_id });
+    }
+
+    let cost = offer_cost(deps.storage, &sell_order, offer.quantity, &offer.price)?;
+    add_offer_funds(&mut offer, &info, &party)?;
+    ensure_escrow_covers(&offer, &cost)?;
+
+    let fee_params = FEE_PARAMS.load(deps.storage)?;
+    let order = BuyOrderMsg {
+        sell_order_id: offer.sell_order_id,
+        quantity: offer.quantity,
+        bid_price: offer.price.clone(),
+        disable_auto_retire: offer.disable_auto_retire,
+        retirement_jurisdiction: None,
+        retirement_reason: None,
+        max_fee_amount: offer.escrow.clone(),
+        beneficiary: None,
+        expected_version: None,
+        merkle_proof: offer.merkle_proof.clone(),
+        on_delivery_failure: offer.on_delivery_failure,
+    };
+    let (event, trade, price, delivery) = fill_sell_order(
+        deps.branch(),
+        &env,
+        &fee_params,
+        &offer.buyer,
+        None,
+        order,
+        Some(offer.price.amount),
+    )?;
+    OFFERS.remove(deps.storage, offer_id);
+
+    let refund = offer.escrow.amount - price.cost(trade.quantity);
+    let mut response = Response::new()
+        .add_event(event)
+        .add_event(events::offer_accepted(&offer, &trade))
+        .add_submessages(delivery)
+        .add_submessages(trade_hooks(deps.storage, &trade)?);
+    if let Some(seller) = payee(deps.storage, &trade)? {
+        response = response.add_messages(pay_seller(deps.storage, seller, &price, trade.quantity)?);
+    }
+    if !refund.is_zero() {
+        response = response.add_message(send_coin(
+            &offer.buyer,
+            Coin::new(refund.u128(), &offer.escrow.denom),
+        )?);
+    }
+
+    Ok(response
+        .add_attribute("method", "accept_offer")
+        .add_attribute("offer_id", offer_id.to_string()))
+}
+
+/// Lets either party end the negotiation. The escrow goes back to the buyer.
+pub fn reject_offer(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    offer_id: u64,
+) -> Result<Response, ContractError> {
+    let offer = OFFERS.load(deps.storage, offer_id)?;
+    // The sell order may already be filled or cancelled, in which case only
+    // the buyer can recover their escrow.
+    let is_seller = SELL_ORDERS
+        .may_load(deps.storage, offer.sell_order_id)?
+        .is_some_and(|sell_order| sell_order.seller == info.sender);
+    if offer.buyer != info.sender && !is_seller {
+        return Err(ContractError::Unauthorized {});
+    }
+    OFFERS.remove(deps.storage, offer_id);
+
+    let mut response = Response::new().add_event(events::offer_rejected(&offer, &info.sender));
+    if !offer.escrow.amount.is_zero() {
+        response = response.add_message(send_coin(&offer.buyer, offer.escrow.clone())?);
+    }
+
+    Ok(response
+        .add_attribute("method", "reject_offer")
+        .add_attribute("offer_id", offer_id.to_string()))
+}
+
+/// Escrows the attached funds for a bid on any batch matching `criteria`.
+#[allow(clippy::too_many_arguments)]
+pub fn place_collection_bid(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    criteria: CollectionBidCriteria,
+    quantity: Uint128,
+    price: Coin,
+    expiration: Option<Timestamp>,
+    disable_auto_retire: bool,
+    retirement_jurisdiction: Option<String>,
+    retirement_reason: Option<String>,
+    on_delivery_failure: DeliveryFailure,
+) -> Result<Response, ContractError> {
+    // Project jurisdictions are only known from the ecocredit module, so
+    // without it such a bid could never be filled.
+    if criteria.jurisdictions.is_some()
+        && CONFIG.load(deps.storage)?.ecocredit == EcocreditAdapter::Disabled
+    {
+        return Err(ContractError::JurisdictionsUnavailable {});
+    }
+    ensure_denom_allowed(deps.storage, &price.denom)?;
+    let fee_params = FEE_PARAMS.load(deps.storage)?;
+    let buyer_fee = calculate_fee(&price, fee_params.buyer_percentage_fee);
+    let cost = Coin::new(
+        ((price.amount + buyer_fee.amount) * quantity).u128(),
+        &price.denom,
+    );
+    if offer_funds(&info, &price.denom)? != cost.amount {
+        return Err(ContractError::InvalidFunds {
+            expected: cost.to_string(),
+            found: coins_to_string(&info.funds),
+        });
+    }
+
+    let id = COLLECTION_BID_SEQ
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        + 1;
+    COLLECTION_BID_SEQ.save(deps.storage, &id)?;
+    let bid = CollectionBid {
+        id,
+        buyer: info.sender,
+        criteria,
+        quantity,
+        price,
+        escrow: cost,
+        expiration,
+        disable_auto_retire,
+        retirement_jurisdiction,
+        retirement_reason,
+        on_delivery_failure,
+    };
+    COLLECTION_BIDS.save(deps.storage, id, &bid)?;
+
+    Ok(Response::new()
+        .add_event(events::collection_bid_placed(&bid))
+        .add_attribute("method", "place_collection_bid")
+        .add_attribute("bid_id", id.to_string()))
+}
+
+pub fn cancel_collection_bid(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    bid_id: u64,
+) -> Result<Response, ContractError> {
+    let bid = COLLECTION_BIDS.load(deps.storage, bid_id)?;
+    if bid.buyer != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    COLLECTION_BIDS.remove(deps.storage, bid_id);
+
+    let mut response = Response::new().add_event(events::collection_bid_cancelled(&bid));
+    if !bid.escrow.amount.is_zero() {
+        response = response.add_message(send_coin(&bid.buyer, bid.escrow)?);
+    }
+
+    Ok(response
+        .add_attribute("method", "cancel_collection_bid")
+        .add_attribute("bid_id", bid_id.to_string()))
+}
+
+/// Sells `quantity` credits into a collection bid at the bid price, paid from
+/// its escrow with the same fees as `buy_direct`. Each sell order used must
+/// belong to the sender and carry batch metadata matching the bid.
+pub fn fill_collection_bid(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    bid_id: u64,
+    sell_order_id: Option<u64>,
+    batch_denom: Option<String>,
+    quantity: Uint128,
+) -> Result<Response, ContractError> {
+    let mut bid = COLLECTION_BIDS.load(deps.storage, bid_id)?;
+    if matches!(bid.expiration, Some(expiration) if expiration <= env.block.time) {
+        return Err(ContractError::CollectionBidExpired { bid_id });
+    }
+    if quantity > bid.quantity {
+        return Err(ContractError::CollectionBidQuantityExceeded {
+            bid_id,
+            remaining: bid.quantity,
+        });
+    }
+
+    let sell_orders = match (sell_order_id, batch_denom) {
+        (Some(sell_order_id), _) => {
+            let sell_order = SELL_ORDERS.load(deps.storage, sell_order_id)?;
+            if sell_order.seller != info.sender {
+                return Err(ContractError::Unauthorized {});
+            }
+            if !matches_collection_bid(&bid, &sell_order) {
+                return Err(ContractError::CollectionBidMismatch {
+                    bid_id,
+                    sell_order_id,
+                });
+            }
+            vec![sell_order]
+        }
+        (None, Some(batch_denom)) => {
+            let batch_key = BATCH_KEYS.may_load(deps.storage, batch_denom)?;
+            SELL_ORDERS
+                .range(deps.storage, None, None, Order::Ascending)
+                .filter(|r| match r {
+                    Ok((_, sell_order)) => {
+                        sell_order.seller == info.sender
+                            && Some(sell_order.batch_key) == batch_key
+                            && matches_collection_bid(&bid, sell_order)
+                    }
+                    Err(_) => true,
+                })
+                .map(|item| item.map(|(_, sell_order)| sell_order))
+                .collect::<StdResult<Vec<_>>>()?
+        }
+        (None, None) => return Err(ContractError::InvalidInput {}),
+    };
+
+    let fee_params = FEE_PARAMS.load(deps.storage)?;
+    let mut response = Response::new();
+    let mut unfilled = quantity;
+    for sell_order in sell_orders {
+        if unfilled.is_zero() {
+            break;
+        }
+        let fill = unfilled.min(sell_order.quantity);
+        let order = BuyOrderMsg {
+            sell_order_id: sell_order.id,
+            quantity: fill,
+            bid_price: bid.price.clone(),
+            disable_auto_retire: bid.disable_auto_retire,
+            retirement_jurisdiction: bid.retirement_jurisdiction.clone(),
+            retirement_reason: bid.retirement_reason.clone(),
+            max_fee_amount: bid.escrow.clone(),
+            beneficiary: None,
+            expected_version: None,
+            merkle_proof: None,
+            on_delivery_failure: bid.on_delivery_failure,
+        };
+        let (event, trade, price, delivery) = fill_sell_order(
+            deps.branch(),
+            &env,
+            &fee_params,
+            &bid.buyer,
+            None,
+            order,
+            Some(bid.price.amount),
+        )?;
+
+        // Fees may have risen since the bid was placed.
+        let cost = price.cost(fill);
+        bid.escrow.amount =
+            bid.escrow
+                .amount
+                .checked_sub(cost)
+                .map_err(|_| ContractError::InvalidFunds {
+                    expected: Coin::new(cost.u128(), &bid.escrow.denom).to_string(),
+                    found: bid.escrow.to_string(),
+                })?;
+        bid.quantity -= fill;
+        unfilled -= fill;
+        response = response
+            .add_event(event)
+            .add_event(events::collection_bid_filled(&bid, &info.sender, fill))
+            .add_submessages(delivery)
+            .add_submessages(trade_hooks(deps.storage, &trade)?);
+        if let Some(seller) = payee(deps.storage, &trade)? {
+            response = response.add_messages(pay_seller(deps.storage, seller, &price, fill)?);
+        }
+    }
+    if !unfilled.is_zero() {
+        return Err(ContractError::InsufficientSellOrderQuantity);
+    }
+
+    if bid.quantity.is_zero() {
+        COLLECTION_BIDS.remove(deps.storage, bid_id);
+        if !bid.escrow.amount.is_zero() {
+            response = response.add_message(send_coin(&bid.buyer, bid.escrow.clone())?);
+        }
+    } else {
+        COLLECTION_BIDS.save(deps.storage, bid_id, &bid)?;
+    }
+
+    Ok(response
+        .add_attribute("method", "fill_collection_bid")
+        .add_attribute("bid_id", bid_id.to_string()))
+}
+
+pub fn sell_bundle(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    items: Vec<BundleItem>,
+    price: Coin,
+    expiration: Option<Timestamp>,
+) -> Result<Response, ContractError> {
+    ensure_denom_allowed(deps.storage, &price.denom)?;
+    let config = CONFIG.load(deps.storage)?;
+    for item in &items {
+        ensure_credits_available(
+            &deps.querier,
+            &config.ecocredit,
+            &info.sender,
+            &item.batch_denom,
+            item.quantity,
+        )?;
+        batch_key_or_create(deps.storage, &item.batch_denom)?;
+        let market_id = market_id_or_create(deps.storage, &item.batch_denom, &price)?;
+        ensure_not_paused(
+            deps.storage,
+            &PauseScope::Listings,
+            market_id,
+            &item.batch_denom,
+        )?;
+    }
+
+    let id = BUNDLE_SEQ.may_load(deps.storage)?.unwrap_or_default() + 1;
+    BUNDLE_SEQ.save(deps.storage, &id)?;
+    let bundle = Bundle {
+        id,
+        seller: info.sender,
+        items,
+        price,
+        expiration,
+    };
+    BUNDLES.save(deps.storage, id, &bundle)?;
+
+    Ok(Response::new()
+        .add_event(events::bundle_created(&bundle))
+        .add_attribute("method", "sell_bundle")
+        .add_attribute("bundle_id", id.to_string()))
+}
+
+pub fn cancel_bundle(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    bundle_id: u64,
+) -> Result<Response, ContractError> {
+    let bundle = BUNDLES.load(deps.storage, bundle_id)?;
+    if bundle.seller != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    BUNDLES.remove(deps.storage, bundle_id);
+
+    Ok(Response::new()
+        .add_event(events::bundle_cancelled(&bundle))
+        .add_attribute("method", "cancel_bundle")
+        .add_attribute("bundle_id", bundle_id.to_string()))
+}
+
+/// Buys every batch of a bundle at once, recording a trade per batch. Fees are
+/// charged on the bundle price like on the ask price of a sell order.
+#[allow(clippy::too_many_arguments)]
+pub fn buy_bundle(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    bundle_id: u64,
+    max_fee_amount: Coin,
+    retirement_options: Vec<BundleRetirementMsg>,
+    beneficiary: Option<String>,
+    use_balance: bool,
+) -> Result<Response, ContractError> {
+    let bundle = BUNDLES.load(deps.storage, bundle_id)?;
+    if bundle.seller == info.sender {
+        return Err(ContractError::SelfTrade {});
+    }
+    if matches!(bundle.expiration, Some(expiration) if expiration <= env.block.time) {
+        return Err(ContractError::BundleExpired { bundle_id });
+    }
+    for option in &retirement_options {
+        if !bundle
+            .items
+            .iter()
+            .any(|item| item.batch_denom == option.batch_denom)
+        {
+            return Err(ContractError::BatchNotInBundle {
+                bundle_id,
+                batch_denom: option.batch_denom.clone(),
+            });
+        }
+    }
+    for item in &bundle.items {
+        let market_id = market_id_or_create(deps.storage, &item.batch_denom, &bundle.price)?;
+        ensure_not_paused(
+            deps.storage,
+            &PauseScope::Purchases,
+            market_id,
+            &item.batch_denom,
+        )?;
+    }
+
+    ensure_denom_allowed(deps.storage, &bundle.price.denom)?;
+    if max_fee_amount.denom != bundle.price.denom {
+        return Err(ContractError::BidDenomMismatch {
+            expected: bundle.price.denom,
+            found: max_fee_amount.denom,
+        });
+    }
+    let fee_params = FEE_PARAMS.load(deps.storage)?;
+    let price = PurchasePrice {
+        ask_price: bundle.price.clone(),
+        buyer_fee: calculate_fee(&bundle.price, fee_params.buyer_percentage_fee),
+        seller_fee: calculate_fee(&bundle.price, fee_params.seller_percentage_fee),
+    };
+    if price.buyer_fee.amount > max_fee_amount.amount {
+        return Err(ContractError::MaxFeeExceeded);
+    }
+
+    let beneficiary = self::beneficiary(deps.api, &info.sender, beneficiary)?;
+    let mut trades = Vec::new();
+    for item in &bundle.items {
+        let option = retirement_options
+            .iter()
+            .find(|option| option.batch_denom == item.batch_denom);
+        let trade = Trade {
+            id: next_trade_id(deps.storage)?,
+            source: TradeSource::Bundle { bundle_id },
+            seller: bundle.seller.clone(),
+            payer: info.sender.clone(),
+            spender: None,
+            beneficiary: beneficiary.clone(),
+            batch_denom: item.batch_denom.clone(),
+            quantity: item.quantity,
+            ask_price: price.ask_price.clone(),
+            buyer_fee: price.buyer_fee.clone(),
+            retirement: retirement(
+                item.disable_auto_retire,
+                option.is_some_and(|option| option.disable_auto_retire),
+                option.and_then(|option| option.retirement_jurisdiction.clone()),
+                option.and_then(|option| option.retirement_reason.clone()),
+            ),
+            timestamp: env.block.time,
+            refunded: false,
+        };
+        TRADES.save(deps.storage, trade.id, &trade)?;
+        trades.push(trade);
+    }
+    BUNDLES.remove(deps.storage, bundle_id);
+
+    // The bundle is paid for as a single unit, so a failed delivery of any
+    // item reverts the whole purchase.
+    let messages = settle(
+        deps.storage,
+        &info,
+        use_balance,
+        &[(Some(&bundle.seller), &price, Uint128::one())],
+    )?;
+    let mut deliveries = vec![];
+    for trade in &trades {
+        deliveries.extend(settle_credits(
+            deps.storage,
+            &env,
+            &CreditAsset::Native,
+            trade,
+            &price,
+            DeliveryFailure::Revert,
+        )?);
+    }
+
+    let mut hooks = vec![];
+    for trade in &trades {
+        hooks.extend(trade_hooks(deps.storage, trade)?);
+    }
+
+    Ok(Response::new()
+        .add_event(events::bundle_sold(&bundle, &trades, &price))
+        .add_messages(messages)
+        .add_submessages(deliveries)
+        .add_submessages(hooks)
+        .add_attribute("method", "buy_bundle")
+        .add_attribute("bundle_id", bundle_id.to_string()))
+}
+
+pub fn pause(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    scope: PauseScope,
+) -> Result<Response, ContractError> {
+    ensure_authority(deps.storage, &info.sender)?;
+
+    PAUSES.save(deps.storage, scope.key(), &scope)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "pause")
+        .add_attribute("scope", scope.key()))
+}
+
+pub fn unpause(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    scope: PauseScope,
+) -> Result<Response, ContractError> {
+    ensure_authority(deps.storage, &info.sender)?;
+
+    PAUSES.remove(deps.storage, scope.key());
+
+    Ok(Response::new()
+        .add_attribute("method", "unpause")
+        .add_attribute("scope", scope.key()))
+}
+
+fn ensure_authority(storage: &dyn Storage, sender: &Addr) -> Result<(), ContractError> {
+    if CONFIG.load(storage)?.authority != *sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    Ok(())
+}
+
+/// Fails if `action` (listings or purchases) is halted globally, or if the
+/// market or batch of the order is halted.
+fn ensure_not_paused(
+    storage: &dyn Storage,
+    action: &PauseScope,
+    market_id: u64,
+    batch_denom: &str,
+) -> Result<(), ContractError> {
+    let scopes = [
+        PauseScope::All,
+        action.clone(),
+        PauseScope::Market { market_id },
+        PauseScope::Batch {
+            batch_denom: batch_denom.to_string(),
+        },
+    ];
+
+    for scope in scopes {
+        if PAUSES.has(storage, scope.key()) {
+            return Err(ContractError::Paused { scope: scope.key() });
+        }
+    }
+    Ok(())
+}
+
+/// Expired sell orders wait for a crank but can no longer be bought.
+fn ensure_sell_order_live(env: &Env, sell_order: &SellOrder) -> Result<(), ContractError> {
+    if matches!(sell_order.expiration, Some(expiration) if expiration <= env.block.time) {
+        return Err(ContractError::SellOrderExpired {
+            sell_order_id: sell_order.id,
+        });
+    }
+    Ok(())
+}
+
+/// Private orders can only be bought by their designated buyers, who pay for
+/// them.
+fn ensure_allowed_buyer(
+    sell_order: &SellOrder,
+    payer: &Addr,
+    merkle_proof: Option<&[HexBinary]>,
+) -> Result<(), ContractError> {
+    let allowed = match &sell_order.allowed_buyers {
+        None => true,
+        Some(AllowedBuyers::Addresses { addresses }) => addresses.contains(payer),
+        Some(AllowedBuyers::MerkleRoot { root }) => {
+            let leaf = Sha256::digest(payer.as_bytes()).to_vec();
+            let computed = merkle_proof
+                .unwrap_or_default()
+                .iter()
+                .fold(leaf, |node, sibling| {
+                    let (left, right) = if node.as_slice() <= sibling.as_slice() {
+                        (node.as_slice(), sibling.as_slice())
+                    } else {
+                        (sibling.as_slice(), node.as_slice())
+                    };
+                    Sha256::new()
+                        .chain_update(left)
+                        .chain_update(right)
+                        .finalize()
+                        .to_vec()
+                });
+            computed == root.as_slice()
+        }
+    };
+    if !allowed {
+        return Err(ContractError::BuyerNotAllowed {
+            sell_order_id: sell_order.id,
+            buyer: payer.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn ensure_denom_allowed(storage: &dyn Storage, denom: &str) -> Result<(), ContractError> {
+    if !ALLOWED_DENOMS.has(storage, denom.to_string()) {
+        return Err(ContractError::DenomNotAllowed {
+            denom: denom.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn batch_key_or_create(storage: &mut dyn Storage, batch_denom: &str) -> Result<u64, ContractError> {
+    if let Some(key) = BATCH_KEYS.may_load(storage, batch_denom.to_string())? {
+        return Ok(key);
+    }
+
+    let key = BATCH_SEQ.may_load(storage)?.unwrap_or_default() + 1;
+    BATCH_SEQ.save(storage, &key)?;
+    BATCHES.save(
+        storage,
+        key,
+        &Batch {
+            key,
+            denom: batch_denom.to_string(),
+        },
+    )?;
+    BATCH_KEYS.save(storage, batch_denom.to_string(), &key)?;
+    Ok(key)
+}
+
+/// Denom of the batch an order sells. Orders created before batches were
+/// tracked have no batch and resolve to an empty denom.
+fn batch_denom(storage: &dyn Storage, batch_key: u64) -> Result<String, ContractError> {
+    Ok(BATCHES
+        .may_load(storage, batch_key)?
+        .map(|batch| batch.denom)
+        .unwrap_or_default())
+}
+
+/// Bank denom of a market. Orders created before markets were tracked have
+/// no market and resolve to an empty denom.
+fn ask_denom(storage: &dyn Storage, market_id: u64) -> Result<String, ContractError> {
+    Ok(MARKETS
+        .may_load(storage, market_id)?
+        .map(|market| market.bank_denom)
+        .unwrap_or_default())
+}
+
+/// Markets pair a credit type with a bank denom, as in the Regen marketplace.
+fn market_id_or_create(
+    storage: &mut dyn Storage,
+    batch_denom: &str,
+    ask_price: &Coin,
+) -> Result<u64, ContractError> {
+    let credit_type_abbrev = credit_type_abbrev(batch_denom);
+    let market_key = (credit_type_abbrev.clone(), ask_price.denom.clone());
+    if let Some(id) = MARKET_IDS.may_load(storage, market_key.clone())? {
+        return Ok(id);
+    }
+
+    let id = MARKET_SEQ.may_load(storage)?.unwrap_or_default() + 1;
+    MARKET_SEQ.save(storage, &id)?;
+    MARKETS.save(
+        storage,
+        id,
+        &Market {
+            id,
+            credit_type_abbrev,
+            bank_denom: ask_price.denom.clone(),
+            precision_modifier: 0,
+        },
+    )?;
+    MARKET_IDS.save(storage, market_key, &id)?;
+    Ok(id)
+}
+
+/// Regen batch denoms start with their class id, e.g. `C01-001-20200101-20210101-001`,
+/// whose leading letters are the credit type abbreviation.
+fn credit_type_abbrev(batch_denom: &str) -> String {
+    batch_denom
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .collect()
+}
+
+/// Price and fees per credit of a purchase at an order's ask price.
+pub struct PurchasePrice {
+    pub ask_price: Coin,
+    pub buyer_fee: Coin,
+    pub seller_fee: Coin,
+}
+
+impl PurchasePrice {
+    /// Amount the buyer pays for `quantity` credits, including the buyer fee.
+    pub fn cost(&self, quantity: Uint128) -> Uint128 {
+        (self.ask_price.amount + self.buyer_fee.amount) * quantity
+    }
+
+    /// Amount the seller receives for `quantity` credits, after the seller
+    /// fee. Both fees stay in the contract as the fee pool.
+    pub fn proceeds(&self, quantity: Uint128) -> Uint128 {
+        (self.ask_price.amount - self.seller_fee.amount) * quantity
+    }
+}
+
+/// Checks a bid against the market and ask of an order and computes the fees
+/// owed on the purchase.
+fn price_purchase(
+    storage: &dyn Storage,
+    fee_params: &FeeParams,
+    market_id: u64,
+    ask_amount: Uint128,
+    bid_price: &Coin,
+    max_fee_amount: &Coin,
+) -> Result<PurchasePrice, ContractError> {
+    let market = MARKETS.load(storage, market_id)?;
+    if bid_price.denom != market.bank_denom {
+        return Err(ContractError::BidDenomMismatch {
+            expected: market.bank_denom,
+            found: bid_price.denom.clone(),
+        });
+    }
+    ensure_denom_allowed(storage, &market.bank_denom)?;
+
+    // Check if the bid price is sufficient
+    if bid_price.amount < ask_amount {
+        return Err(ContractError::InsufficientBidPrice);
+    }
+
+    // Calculate fees on the price actually paid
+    let ask_price = Coin {
+        denom: market.bank_denom,
+        amount: ask_amount,
+    };
+    let buyer_fee = calculate_fee(&ask_price, fee_params.buyer_percentage_fee);
+    let seller_fee = calculate_fee(&ask_price, fee_params.seller_percentage_fee);
+
+    // Check if the max fee amount is sufficient
+    if buyer_fee.amount > max_fee_amount.amount {
+        return Err(ContractError::MaxFeeExceeded);
+    }
+
+    Ok(PurchasePrice {
+        ask_price,
+        buyer_fee,
+        seller_fee,
+    })
+}
+
+/// Amount owed to a seller for `quantity` units at a purchase price. No
+/// seller is paid while the payment is held by a pending settlement.
+type Payment<'a> = (Option<&'a Addr>, &'a PurchasePrice, Uint128);
+
+fn fill_payments<'a>(
+    storage: &dyn Storage,
+    fills: &'a [(Trade, PurchasePrice)],
+) -> StdResult<Vec<Payment<'a>>> {
+    fills
+        .iter()
+        .map(|(trade, price)| Ok((payee(storage, trade)?, price, trade.quantity)))
+        .collect()
+}
+
+/// Collects the cost of `payments` from the sender and pays the sellers. With
+/// `use_balance` both sides settle against deposited balances and no funds may
+/// be attached; otherwise the attached funds must cover the cost exactly and
+/// proceeds are sent to the sellers.
+fn settle(
+    storage: &mut dyn Storage,
+    info: &MessageInfo,
+    use_balance: bool,
+    payments: &[Payment],
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    if use_balance {
+        if !info.funds.is_empty() {
+            return Err(ContractError::InvalidFunds {
+                expected: "none".to_string(),
+                found: coins_to_string(&info.funds),
+            });
+        }
+        for (seller, price, quantity) in payments {
+            let denom = &price.ask_price.denom;
+            debit_balance(
+                storage,
+                &info.sender,
+                &Coin::new(price.cost(*quantity).u128(), denom),
+            )?;
+            if let Some(seller) = seller {
+                credit_balance(
+                    storage,
+                    seller,
+                    &Coin::new(price.proceeds(*quantity).u128(), denom),
+                )?;
+                collect_fees(storage, price, *quantity)?;
+            }
+        }
+        return Ok(vec![]);
+    }
+
+    let mut cost = Coins::default();
+    for (_, price, quantity) in payments {
+        cost.add(Coin::new(
+            price.cost(*quantity).u128(),
+            &price.ask_price.denom,
+        ))?;
+    }
+    ensure_funds(info, &cost)?;
+
+    let mut messages = vec![];
+    for (seller, price, quantity) in payments {
+        if let Some(seller) = seller {
+            messages.extend(pay_seller(storage, seller, price, *quantity)?);
+        }
+    }
+    Ok(messages)
+}
+
+/// The attached funds must be exactly `expected`.
+fn ensure_funds(info: &MessageInfo, expected: &Coins) -> Result<(), ContractError> {
+    let funds = Coins::try_from(info.funds.clone()).map_err(|_| ContractError::InvalidFunds {
+        expected: expected.to_string(),
+        found: coins_to_string(&info.funds),
+    })?;
+    if funds != *expected {
+        return Err(ContractError::InvalidFunds {
+            expected: expected.to_string(),
+            found: funds.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn coins_to_string(coins: &[Coin]) -> String {
+    coins
+        .iter()
+        .map(|coin| coin.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Sends the proceeds of a purchase to the seller and keeps both fees in the
+/// fee pool.
+fn pay_seller(
+    storage: &mut dyn Storage,
+    seller: &Addr,
+    price: &PurchasePrice,
+    quantity: Uint128,
+) -> StdResult<Option<CosmosMsg>> {
+    collect_fees(storage, price, quantity)?;
+    let proceeds = price.proceeds(quantity);
+    if proceeds.is_zero() {
+        return Ok(None);
+    }
+    send_coin(seller, Coin::new(proceeds.u128(), &price.ask_price.denom)).map(Some)
+}
+
+fn collect_fees(
+    storage: &mut dyn Storage,
+    price: &PurchasePrice,
+    quantity: Uint128,
+) -> StdResult<()> {
+    let fees = price.cost(quantity) - price.proceeds(quantity);
+    add_to_fee_pool(storage, &Coin::new(fees.u128(), &price.ask_price.denom))
+}
+
+fn add_to_fee_pool(storage: &mut dyn Storage, fees: &Coin) -> StdResult<()> {
+    if fees.amount.is_zero() {
+        return Ok(());
+    }
+    FEE_POOL.update(storage, fees.denom.clone(), |pool| {
+        StdResult::Ok(pool.unwrap_or_default() + fees.amount)
+    })?;
+    Ok(())
+}
+
+/// Metadata collection bids are matched against, taken from the batch denom
+/// and, with the Regen adapter, the batch's project. None when the denom is
+/// not a Regen batch denom.
+fn batch_metadata(
+    querier: &QuerierWrapper,
+    adapter: &EcocreditAdapter,
+    batch_denom: &str,
+) -> StdResult<Option<BatchMetadata>> {
+    let Some(mut metadata) = ecocredit::parse_batch_denom(batch_denom) else {
+        return Ok(None);
+    };
+    if *adapter == EcocreditAdapter::Regen {
+        metadata.project_jurisdiction =
+            ecocredit::query_project(querier, &metadata.project_id)?.jurisdiction;
+    }
+    Ok(Some(metadata))
+}
+
+/// With the Regen adapter, the batch must exist and the seller must hold the
+/// credits they list.
+fn ensure_credits_available(
+    querier: &QuerierWrapper,
+    adapter: &EcocreditAdapter,
+    seller: &Addr,
+    batch_denom: &str,
+    quantity: Uint128,
+) -> Result<(), ContractError> {
+    if *adapter == EcocreditAdapter::Disabled {
+        return Ok(());
+    }
+    ecocredit::query_batch(querier, batch_denom).map_err(|_| ContractError::UnknownBatch {
+        batch_denom: batch_denom.to_string(),
+    })?;
+    let available = ecocredit::query_tradable_balance(querier, seller, batch_denom)?;
+    if available.to_uint_floor() < quantity {
+        return Err(ContractError::InsufficientCredits {
+            batch_denom: batch_denom.to_string(),
+            available: available.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Delivers the credits of a trade to its beneficiary, retired in their name
+/// when the trade retires them. Native credits are sent from the seller by
+/// the ecocredit adapter; escrowed tokens are transferred, or burned when
+/// retired.
+fn deliver_credits(
+    adapter: &EcocreditAdapter,
+    asset: &CreditAsset,
+    env: &Env,
+    trade: &Trade,
+) -> StdResult<Option<CosmosMsg>> {
+    if *asset == CreditAsset::Native {
+        return Ok(match adapter {
+            EcocreditAdapter::Disabled => None,
+            EcocreditAdapter::Regen => Some(ecocredit::exec(
+                &env.contract.address,
+                ecocredit::send(
+                    &trade.seller,
+                    &trade.beneficiary,
+                    &trade.batch_denom,
+                    trade.quantity,
+                    trade.retirement.as_ref(),
+                ),
+            )),
+        });
+    }
+    if trade.retirement.is_none() {
+        return transfer_credits(asset, env, &trade.beneficiary, trade.quantity);
+    }
+    let msg = match asset {
+        CreditAsset::Native => return Ok(None),
+        CreditAsset::Cw20 { contract } => wasm_execute(
+            contract,
+            &Cw20ExecuteMsg::Burn {
+                amount: trade.quantity,
+            },
+            vec![],
+        )?,
+        CreditAsset::Cw1155 { contract, token_id } => wasm_execute(
+            contract,
+            &Cw1155ExecuteMsg::BurnFrom {
+                from: env.contract.address.to_string(),
+                token_id: token_id.clone(),
+                value: trade.quantity,
+            },
+            vec![],
+        )?,
+    };
+    Ok(Some(msg.into()))
+}
+
+/// Whether `asset` is a registered token backed by native credits the
+/// marketplace holds.
+fn is_custodial(storage: &dyn Storage, asset: &CreditAsset) -> StdResult<bool> {
+    let Some(denom) = asset.escrow_denom() else {
+        return Ok(false);
+    };
+    Ok(CREDIT_TOKENS
+        .may_load(storage, denom)?
+        .is_some_and(|token| token.custodial))
+}
+
+/// Sends escrowed credits to `recipient`.
+fn transfer_credits(
+    asset: &CreditAsset,
+    env: &Env,
+    recipient: &Addr,
+    amount: Uint128,
+) -> StdResult<Option<CosmosMsg>> {
+    if amount.is_zero() {
+        return Ok(None);
+    }
+    let msg = match asset {
+        CreditAsset::Native => return Ok(None),
+        CreditAsset::Cw20 { contract } => wasm_execute(
+            contract,
+            &Cw20ExecuteMsg::Transfer {
+                recipient: recipient.to_string(),
+                amount,
+            },
+            vec![],
+        )?,
+        CreditAsset::Cw1155 { contract, token_id } => wasm_execute(
+            contract,
+            &Cw1155ExecuteMsg::SendFrom {
+                from: env.contract.address.to_string(),
+                to: recipient.to_string(),
+                token_id: token_id.clone(),
+                value: amount,
+                msg: None,
+            },
+            vec![],
+        )?,
+    };
+    Ok(Some(msg.into()))
+}
+
+/// Sends native coins with the bank module and cw20 denoms with a transfer on
+/// the token contract.
+fn send_coin(recipient: &Addr, coin: Coin) -> StdResult<CosmosMsg> {
+    Ok(match coin.denom.strip_prefix(CW20_DENOM_PREFIX) {
+        Some(contract_addr) => WasmMsg::Execute {
+            contract_addr: contract_addr.to_string(),
+            msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: recipient.to_string(),
+                amount: coin.amount,
+            })?,
+            funds: vec![],
+        }
+        .into(),
+        None => BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: vec![coin],
+        }
+        .into(),
+    })
+}
+
+fn debit_balance(
+    storage: &mut dyn Storage,
+    account: &Addr,
+    coin: &Coin,
+) -> Result<(), ContractError> {
+    let key = (account.clone(), coin.denom.clone());
+    let balance = BALANCES.may_load(storage, key.clone())?.unwrap_or_default();
+    if balance < coin.amount {
+        return Err(ContractError::InsufficientBalance {
+            balance: Coin::new(balance.u128(), &coin.denom),
+            required: coin.clone(),
+        });
+    }
+    if balance == coin.amount {
+        BALANCES.remove(storage, key);
+    } else {
+        BALANCES.save(storage, key, &(balance - coin.amount))?;
+    }
+    Ok(())
+}
+
+fn credit_balance(storage: &mut dyn Storage, account: &Addr, coin: &Coin) -> StdResult<()> {
+    if coin.amount.is_zero() {
+        return Ok(());
+    }
+    BALANCES.update(
+        storage,
+        (account.clone(), coin.denom.clone()),
+        |balance| -> StdResult<_> { Ok(balance.unwrap_or_default() + coin.amount) },
+    )?;
+    Ok(())
+}
+
+fn next_trade_id(storage: &mut dyn Storage) -> StdResult<u64> {
+    let id = TRADE_SEQ.may_load(storage)?.unwrap_or_default() + 1;
+    TRADE_SEQ.save(storage, &id)?;
+    Ok(id)
+}
+
+fn next_offer_id(storage: &mut dyn Storage) -> StdResult<u64> {
+    let id = OFFER_SEQ.may_load(storage)?.unwrap_or_default() + 1;
+    OFFER_SEQ.save(storage, &id)?;
+    Ok(id)
+}
+
+fn load_open_offer(
+    storage: &dyn Storage,
+    env: &Env,
+    offer_id: u64,
+) -> Result<Offer, ContractError> {
+    let offer = OFFERS.load(storage, offer_id)?;
+    if matches!(offer.expiration, Some(expiration) if expiration <= env.block.time) {
+        return Err(ContractError::OfferExpired { offer_id });
+    }
+    Ok(offer)
+}
+
+fn offer_party(
+    offer: &Offer,
+    sell_order: &SellOrder,
+    sender: &Addr,
+) -> Result<OfferParty, ContractError> {
+    if *sender == offer.buyer {
+        Ok(OfferParty::Buyer)
+    } else if *sender == sell_order.seller {
+        Ok(OfferParty::Seller)
+    } else {
+        Err(ContractError::Unauthorized {})
+    }
+}
+
+/// Price and buyer fee of `quantity` credits at the offered price, checked
+/// against the market of the sell order.
+fn offer_cost(
+    storage: &dyn Storage,
+    sell_order: &SellOrder,
+    quantity: Uint128,
+    price: &Coin,
+) -> Result<Coin, ContractError> {
+    if quantity > sell_order.quantity {
+        return Err(ContractError::InsufficientSellOrderQuantity);
+    }
+    let fee_params = FEE_PARAMS.load(storage)?;
+    // The buyer agrees to the fee by escrowing it, so it is not capped.
+    let purchase = price_purchase(
+        storage,
+        &fee_params,
+        sell_order.market_id,
+        price.amount,
+        price,
+        &Coin::new(u128::MAX, &price.denom),
+    )?;
+    Ok(Coin::new(
+        purchase.cost(quantity).u128(),
+        &purchase.ask_price.denom,
+    ))
+}
+
+/// Amount of `denom` attached to an offer message. No other coins are
+/// accepted.
+fn offer_funds(info: &MessageInfo, denom: &str) -> Result<Uint128, ContractError> {
+    match info.funds.as_slice() {
+        [] => Ok(Uint128::zero()),
+        [coin] if coin.denom == denom => Ok(coin.amount),
+        _ => Err(ContractError::InvalidFunds {
+            expected: denom.to_string(),
+            found: coins_to_string(&info.funds),
+        }),
+    }
+}
+
+/// Adds a buyer's attached funds to the escrow. The seller never attaches
+/// funds.
+fn add_offer_funds(
+    offer: &mut Offer,
+    info: &MessageInfo,
+    party: &OfferParty,
+) -> Result<(), ContractError> {
+    match party {
+        OfferParty::Buyer => offer.escrow.amount += offer_funds(info, &offer.escrow.denom)?,
+        OfferParty::Seller if !info.funds.is_empty() => {
+            return Err(ContractError::InvalidFunds {
+                expected: "none".to_string(),
+                found: coins_to_string(&info.funds),
+            });
+        }
+        OfferParty::Seller => {}
+    }
+    Ok(())
+}
+
+fn ensure_escrow_covers(offer: &Offer, cost: &Coin) -> Result<(), ContractError> {
+    if offer.escrow.amount < cost.amount {
+        return Err(ContractError::InvalidFunds {
+            expected: cost.to_string(),
+            found: offer.escrow.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn matches_collection_bid(bid: &CollectionBid, sell_order: &SellOrder) -> bool {
+    sell_order
+        .batch_metadata
+        .as_ref()
+        .is_some_and(|metadata| bid.criteria.matches(metadata))
+}
+
+/// The beneficiary of a purchase defaults to the account paying for it.
+fn beneficiary(api: &dyn Api, payer: &Addr, beneficiary: Option<String>) -> StdResult<Addr> {
+    match beneficiary {
+        Some(beneficiary) => api.addr_validate(&beneficiary),
+        None => Ok(payer.clone()),
+    }
+}
+
+/// Credits are retired on purchase unless both the seller and the buyer
+/// disabled auto-retirement.
+fn retirement(
+    seller_disable_auto_retire: bool,
+    buyer_disable_auto_retire: bool,
+    jurisdiction: Option<String>,
+    reason: Option<String>,
+) -> Option<Retirement> {
+    if seller_disable_auto_retire && buyer_disable_auto_retire {
+        return None;
+    }
+    Some(Retirement {
+        jurisdiction,
+        reason,
+    })
+}
+
+fn calculate_fee(price: &Coin, fee_percentage: Decimal) -> Coin {
+    Coin {
+        denom: price.denom.clone(),
+        amount: price.amount * fee_percentage,
+    }
 }
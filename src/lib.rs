@@ -1,9 +1,17 @@
 pub mod contract;
+pub mod cw1155;
+pub mod ecocredit;
 pub mod error;
+pub mod events;
 pub mod execute;
+pub mod migrations;
 pub mod msg;
+pub mod proto;
 pub mod query;
+pub mod reply;
+pub mod signing;
 pub mod state;
+pub mod validation;
 
 #[cfg(test)]
 mod test;
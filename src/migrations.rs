@@ -0,0 +1,371 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use cosmwasm_std::{Decimal, DepsMut, Env, Order, Response, StdResult, Storage, Uint128};
+use cw_storage_plus::{Item, KeyDeserialize, Map, PrimaryKey};
+use semver::Version;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::contract::{CONTRACT_NAME, CONTRACT_VERSION};
+use crate::error::ContractError;
+use crate::msg::MigrateMsg;
+use crate::state::{
+    AllowedDenom, Config, CreditAsset, DenomKind, EcocreditAdapter, FeeParams, SellOrder,
+    ALLOWED_DENOMS, CONFIG, FEE_PARAMS, SELL_ORDERS,
+};
+
+/// Version assumed for deployments instantiated before cw2 version info was
+/// recorded. Those contracts all share the 0.1.0 storage layout.
+pub const UNVERSIONED_CONTRACT_VERSION: &str = "0.1.0";
+
+/// Primary records of `SELL_ORDERS` and `FEE_PARAMS`. Releases before 0.4.0
+/// kept no changelog, so their records are read and removed through these,
+/// then saved again through the snapshots.
+const SELL_ORDER_RECORDS: Map<u64, SellOrder> = Map::new("sell_orders");
+const FEE_PARAMS_RECORD: Item<FeeParams> = Item::new("fee_params");
+
+/// A single storage upgrade. `run` rewrites every record whose layout changed
+/// in `version`, and is applied when migrating from any release older than it.
+pub struct MigrationStep {
+    pub version: &'static str,
+    pub run: fn(&mut dyn Storage, &Env) -> Result<(), ContractError>,
+}
+
+/// Storage upgrades in ascending version order.
+pub const MIGRATIONS: &[MigrationStep] = &[
+    MigrationStep {
+        version: "0.2.0",
+        run: migrate_v0_2,
+    },
+    MigrationStep {
+        version: "0.3.0",
+        run: migrate_v0_3,
+    },
+    MigrationStep {
+        version: "0.4.0",
+        run: migrate_v0_4,
+    },
+];
+
+pub fn migrate(deps: DepsMut, env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    let stored = stored_version(deps.storage)?;
+    let current: Version = CONTRACT_VERSION.parse()?;
+
+    if stored > current {
+        return Err(ContractError::CannotDowngrade {
+            stored: stored.to_string(),
+            current: current.to_string(),
+        });
+    }
+
+    for step in MIGRATIONS {
+        if stored < step.version.parse::<Version>()? {
+            (step.run)(deps.storage, &env)?;
+        }
+    }
+
+    let mut config = match (msg.authority, CONFIG.may_load(deps.storage)?) {
+        (Some(authority), Some(config)) => Config {
+            authority: deps.api.addr_validate(&authority)?,
+            ..config
+        },
+        (Some(authority), None) => Config {
+            authority: deps.api.addr_validate(&authority)?,
+            ecocredit: EcocreditAdapter::default(),
+            crank_reward_share: Decimal::zero(),
+        },
+        (None, Some(config)) => config,
+        (None, None) => return Err(ContractError::MissingAuthority {}),
+    };
+    if let Some(ecocredit) = msg.ecocredit {
+        config.ecocredit = ecocredit;
+    }
+    CONFIG.save(deps.storage, &config)?;
+
+    cw2::set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "migrate")
+        .add_attribute("from_version", stored.to_string())
+        .add_attribute("to_version", CONTRACT_VERSION))
+}
+
+/// Reads the cw2 version of the stored state, falling back to
+/// [`UNVERSIONED_CONTRACT_VERSION`] for marketplaces deployed before cw2.
+fn stored_version(storage: &dyn Storage) -> Result<Version, ContractError> {
+    match cw2::CONTRACT.may_load(storage)? {
+        Some(info) => {
+            if info.contract != CONTRACT_NAME {
+                return Err(ContractError::WrongContract {
+                    expected: CONTRACT_NAME.to_string(),
+                    found: info.contract,
+                });
+            }
+            Ok(info.version.parse()?)
+        }
+        // Every marketplace saves its fee params on instantiate, so their
+        // absence means this is not a marketplace at all.
+        None if FEE_PARAMS_RECORD.exists(storage) => Ok(UNVERSIONED_CONTRACT_VERSION.parse()?),
+        None => Err(ContractError::WrongContract {
+            expected: CONTRACT_NAME.to_string(),
+            found: "unknown".to_string(),
+        }),
+    }
+}
+
+/// Rewrites every record of `old` into `new` using `convert`. Both maps are
+/// expected to share a namespace, so the old records are replaced in place.
+pub fn rewrite_map<'a, K, O, N, F>(
+    storage: &mut dyn Storage,
+    old: &Map<'a, K, O>,
+    new: &Map<'a, K, N>,
+    mut convert: F,
+) -> Result<(), ContractError>
+where
+    K: PrimaryKey<'a> + KeyDeserialize<Output = K> + 'static,
+    O: Serialize + DeserializeOwned,
+    N: Serialize + DeserializeOwned,
+    F: FnMut(O) -> Result<N, ContractError>,
+{
+    let records = old
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    for (key, record) in records {
+        new.save(storage, key, &convert(record)?)?;
+    }
+
+    Ok(())
+}
+
+/// Rewrites the record of `old` into `new` using `convert`, if one is stored.
+pub fn rewrite_item<O, N, F>(
+    storage: &mut dyn Storage,
+    old: &Item<O>,
+    new: &Item<N>,
+    convert: F,
+) -> Result<(), ContractError>
+where
+    O: Serialize + DeserializeOwned,
+    N: Serialize + DeserializeOwned,
+    F: FnOnce(O) -> Result<N, ContractError>,
+{
+    if let Some(record) = old.may_load(storage)? {
+        new.save(storage, &convert(record)?)?;
+    }
+
+    Ok(())
+}
+
+/// 0.2.0 replaced the string quantities, prices and fees with typed amounts.
+/// Fees also changed unit, from basis points to fractions of the price.
+fn migrate_v0_2(storage: &mut dyn Storage, _env: &Env) -> Result<(), ContractError> {
+    rewrite_map(storage, &v0_1::SELL_ORDERS, &v0_2::SELL_ORDERS, |old| {
+        let record = format!("sell order {}", old.id);
+        Ok(v0_2::SellOrder {
+            id: old.id,
+            seller: old.seller,
+            batch_key: old.batch_key,
+            quantity: parse_legacy(&record, &old.quantity)?,
+            market_id: old.market_id,
+            ask_amount: parse_legacy(&record, &old.ask_amount)?,
+            disable_auto_retire: old.disable_auto_retire,
+            expiration: old.expiration,
+            maker: old.maker,
+        })
+    })?;
+
+    rewrite_item(storage, &v0_1::FEE_PARAMS, &FEE_PARAMS_RECORD, |old| {
+        Ok(FeeParams {
+            buyer_percentage_fee: parse_legacy_bps(&old.buyer_percentage_fee)?,
+            seller_percentage_fee: parse_legacy_bps(&old.seller_percentage_fee)?,
+        })
+    })
+}
+
+/// 0.3.0 added a version to sell orders, starting at 1.
+fn migrate_v0_3(storage: &mut dyn Storage, _env: &Env) -> Result<(), ContractError> {
+    rewrite_map(storage, &v0_2::SELL_ORDERS, &v0_3::SELL_ORDERS, |old| {
+        Ok(v0_3::SellOrder {
+            id: old.id,
+            seller: old.seller,
+            batch_key: old.batch_key,
+            quantity: old.quantity,
+            market_id: old.market_id,
+            ask_amount: old.ask_amount,
+            disable_auto_retire: old.disable_auto_retire,
+            expiration: old.expiration,
+            maker: old.maker,
+            version: 1,
+        })
+    })
+}
+
+/// 0.4.0 added the ecocredit adapter and crank reward to the config, private
+/// buyers, batch metadata and held assets to sell orders, and a kind to
+/// allowed denoms. Existing records keep the behaviour of 0.3.0: no adapter,
+/// no reward, public orders of native credits, and native denoms.
+///
+/// 0.4.0 also started the changelog of sell orders and fee params. They are
+/// saved again through their snapshots, so queries before the migration
+/// height find nothing instead of the migrated records.
+fn migrate_v0_4(storage: &mut dyn Storage, env: &Env) -> Result<(), ContractError> {
+    rewrite_item(storage, &v0_3::CONFIG, &CONFIG, |old| {
+        Ok(Config {
+            authority: old.authority,
+            ecocredit: EcocreditAdapter::Disabled,
+            crank_reward_share: Decimal::zero(),
+        })
+    })?;
+
+    let sell_orders = v0_3::SELL_ORDERS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (id, old) in sell_orders {
+        let sell_order = SellOrder {
+            id: old.id,
+            seller: old.seller,
+            batch_key: old.batch_key,
+            quantity: old.quantity,
+            market_id: old.market_id,
+            ask_amount: old.ask_amount,
+            disable_auto_retire: old.disable_auto_retire,
+            expiration: old.expiration,
+            maker: old.maker,
+            version: old.version,
+            allowed_buyers: None,
+            batch_metadata: None,
+            asset: CreditAsset::Native,
+        };
+        SELL_ORDER_RECORDS.remove(storage, id);
+        SELL_ORDERS.save(storage, id, &sell_order, env.block.height)?;
+    }
+    if let Some(fee_params) = FEE_PARAMS_RECORD.may_load(storage)? {
+        FEE_PARAMS_RECORD.remove(storage);
+        FEE_PARAMS.save(storage, &fee_params, env.block.height)?;
+    }
+
+    // Allowed denoms kept their 0.1.0 layout until now.
+    rewrite_map(storage, &v0_1::ALLOWED_DENOMS, &ALLOWED_DENOMS, |old| {
+        Ok(AllowedDenom {
+            bank_denom: old.bank_denom,
+            display_denom: old.display_denom,
+            exponent: old.exponent,
+            kind: DenomKind::Native,
+        })
+    })
+}
+
+/// Converts a 0.1.0 fee in basis points, such as `"100"`, to the fraction of
+/// the price it stands for, such as `0.01`.
+fn parse_legacy_bps(value: &str) -> Result<Decimal, ContractError> {
+    let bps: Uint128 = parse_legacy("fee params", value)?;
+    Decimal::checked_from_ratio(bps, 10_000u128).map_err(|err| ContractError::MigrationFailed {
+        record: "fee params".to_string(),
+        reason: format!("cannot convert {:?} basis points: {}", value, err),
+    })
+}
+
+fn parse_legacy<T>(record: &str, value: &str) -> Result<T, ContractError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|err: T::Err| ContractError::MigrationFailed {
+            record: record.to_string(),
+            reason: format!("cannot parse {:?}: {}", value, err),
+        })
+}
+
+/// Storage layouts of the 0.1.0 release, which predates cw2 versioning.
+pub mod v0_1 {
+    use cosmwasm_schema::cw_serde;
+    use cosmwasm_std::{Addr, Timestamp};
+    use cw_storage_plus::{Item, Map};
+
+    #[cw_serde]
+    pub struct SellOrder {
+        pub id: u64,
+        pub seller: Addr,
+        pub batch_key: u64,
+        pub quantity: String,
+        pub market_id: u64,
+        pub ask_amount: String,
+        pub disable_auto_retire: bool,
+        pub expiration: Option<Timestamp>,
+        pub maker: bool,
+    }
+
+    #[cw_serde]
+    pub struct AllowedDenom {
+        pub bank_denom: String,
+        pub display_denom: String,
+        pub exponent: u32,
+    }
+
+    /// Fees in basis points of the price.
+    #[cw_serde]
+    pub struct FeeParams {
+        pub buyer_percentage_fee: String,
+        pub seller_percentage_fee: String,
+    }
+
+    pub const SELL_ORDERS: Map<u64, SellOrder> = Map::new("sell_orders");
+    pub const ALLOWED_DENOMS: Map<String, AllowedDenom> = Map::new("allowed_denoms");
+    pub const FEE_PARAMS: Item<FeeParams> = Item::new("fee_params");
+}
+
+/// Storage layouts of the 0.2.0 release.
+pub mod v0_2 {
+    use cosmwasm_schema::cw_serde;
+    use cosmwasm_std::{Addr, Timestamp, Uint128};
+    use cw_storage_plus::Map;
+
+    #[cw_serde]
+    pub struct SellOrder {
+        pub id: u64,
+        pub seller: Addr,
+        pub batch_key: u64,
+        pub quantity: Uint128,
+        pub market_id: u64,
+        pub ask_amount: Uint128,
+        pub disable_auto_retire: bool,
+        pub expiration: Option<Timestamp>,
+        pub maker: bool,
+    }
+
+    pub const SELL_ORDERS: Map<u64, SellOrder> = Map::new("sell_orders");
+}
+
+/// Storage layouts of the 0.3.0 release. Allowed denoms still had the 0.1.0
+/// layout.
+pub mod v0_3 {
+    use cosmwasm_schema::cw_serde;
+    use cosmwasm_std::{Addr, Timestamp, Uint128};
+    use cw_storage_plus::{Item, Map};
+
+    #[cw_serde]
+    pub struct Config {
+        pub authority: Addr,
+    }
+
+    #[cw_serde]
+    pub struct SellOrder {
+        pub id: u64,
+        pub seller: Addr,
+        pub batch_key: u64,
+        pub quantity: Uint128,
+        pub market_id: u64,
+        pub ask_amount: Uint128,
+        pub disable_auto_retire: bool,
+        pub expiration: Option<Timestamp>,
+        pub maker: bool,
+        pub version: u64,
+    }
+
+    pub const CONFIG: Item<Config> = Item::new("config");
+    pub const SELL_ORDERS: Map<u64, SellOrder> = Map::new("sell_orders");
+}
@@ -1,11 +1,58 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Coin, Timestamp};
+use cosmwasm_std::{Addr, Binary, Coin, Decimal, HexBinary, Timestamp, Uint128};
+use cw20::Cw20ReceiveMsg;
 
-use crate::state::{AllowedDenom, FeeParams, SellOrder};
+use crate::cw1155::{Cw1155BatchReceiveMsg, Cw1155ReceiveMsg};
+use crate::proto::{
+    self, MarketplaceGenesis, MarketplaceMsg, QuerySellOrderResponse, QuerySellOrdersResponse,
+};
+
+use crate::state::{
+    AllowedDenom, Batch, Bundle, BundleItem, CollectionBid, CollectionBidCriteria, Config,
+    CrankCursor, CreditToken, DeliveryFailure, DenomKind, EcocreditAdapter, FeeParams, Hook,
+    HookEvent, Market, Offer, PauseScope, PurchaseAllowance, SellOrder, Settlement,
+    SignedOrderStatus, Trade,
+};
 
 #[cw_serde]
 pub struct InstantiateMsg {
     pub fee_params: FeeParams,
+    /// Defaults to the instantiating account.
+    pub authority: Option<String>,
+    #[serde(default)]
+    pub ecocredit: EcocreditAdapter,
+    /// Starts closed to trading until a native marketplace genesis export or
+    /// an `ExportState` dump is imported with `ImportBatch` or `ImportState`,
+    /// and `Finalize`.
+    #[serde(default)]
+    pub import_genesis: bool,
+    /// Share of the fee pool a `Crank` may pay its sender, one
+    /// `MAX_CRANK_LIMIT`th of it per expired offer or collection bid removed.
+    #[serde(default)]
+    pub crank_reward_share: Decimal,
+    /// An `ExportState` dump to start from, its pages' records in any order.
+    /// The authority above is kept; the adapter and crank reward share of the
+    /// dump's `config` replace the ones above. Trading opens at once unless
+    /// `import_genesis` is set or the dump was taken mid-import.
+    #[serde(default)]
+    pub state: Option<StateDump>,
+}
+
+/// The records of every page of an `ExportState` dump.
+#[cw_serde]
+pub struct StateDump {
+    pub version: u32,
+    pub records: Vec<StateRecords>,
+}
+
+#[cw_serde]
+pub struct MigrateMsg {
+    /// Replaces the marketplace authority. Required when migrating from a
+    /// release that did not store one.
+    pub authority: Option<String>,
+    /// Replaces the ecocredit adapter.
+    #[serde(default)]
+    pub ecocredit: Option<EcocreditAdapter>,
 }
 
 #[cw_serde]
@@ -17,15 +64,25 @@ pub enum ExecuteMsg {
         updates: Vec<UpdateSellOrderMsg>,
     },
     CancelSellOrder {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
         sell_order_id: u64,
     },
+    /// Buys from listed sell orders, paying with exactly the attached funds,
+    /// or from the sender's deposited balance when `use_balance` is set.
     BuyDirect {
         orders: Vec<BuyOrderMsg>,
+        #[serde(default)]
+        use_balance: bool,
     },
+    /// Allows a denom for prices. For `cw20` tokens, `bank_denom` is the token
+    /// contract address and prices use the denom `cw20:<address>`.
     AddAllowedDenom {
         bank_denom: String,
         display_denom: String,
         exponent: u32,
+        #[serde(default)]
+        kind: DenomKind,
     },
     RemoveAllowedDenom {
         denom: String,
@@ -37,64 +94,584 @@ pub enum ExecuteMsg {
         recipient: String,
         coins: Vec<Coin>,
     },
+    /// Buys from a sell order the seller signed off-chain instead of listing
+    /// it with `Sell`.
+    BuyWithSignedOrder {
+        order: Box<SignedSellOrder>,
+        /// 64 byte secp256k1 signature over the sha256 hash of the sign bytes
+        /// of `order`.
+        signature: Binary,
+        /// 33 byte compressed secp256k1 public key of the seller.
+        pubkey: Binary,
+        buy: SignedOrderBuyMsg,
+    },
+    /// Cancels the sender's signed orders with the given nonces.
+    CancelSignedOrders {
+        nonces: Vec<u64>,
+    },
+    Pause {
+        scope: PauseScope,
+    },
+    Unpause {
+        scope: PauseScope,
+    },
+    /// Adds the attached funds to the sender's deposited balance.
+    Deposit {},
+    /// Sends `coins` from the sender's deposited balance back to them.
+    Withdraw {
+        coins: Vec<Coin>,
+    },
+    /// Lets `spender` buy credits with the sender's deposited balance, up to
+    /// `limit` of `denom` including buyer fees. Replaces any earlier grant.
+    GrantPurchaseAllowance {
+        spender: String,
+        denom: String,
+        limit: Uint128,
+        expiration: Option<Timestamp>,
+        allowed_batches: Option<Vec<String>>,
+    },
+    RevokeAllowance {
+        spender: String,
+    },
+    /// Offers to buy from a sell order at `price` per credit. The attached
+    /// funds must cover the price and buyer fee of `quantity` exactly and are
+    /// held in escrow.
+    MakeOffer {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        sell_order_id: u64,
+        quantity: Uint128,
+        price: Coin,
+        expiration: Option<Timestamp>,
+        #[serde(default)]
+        disable_auto_retire: bool,
+        /// Required for private orders with a merkle root of buyers.
+        merkle_proof: Option<Vec<HexBinary>>,
+        /// Applies to the fill when the offer is accepted.
+        #[serde(default)]
+        on_delivery_failure: DeliveryFailure,
+    },
+    /// Proposes new terms for an offer. Either party may counter; a buyer
+    /// attaches any funds needed to cover the new terms.
+    CounterOffer {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        offer_id: u64,
+        quantity: Uint128,
+        price: Coin,
+        expiration: Option<Timestamp>,
+    },
+    /// Accepts the terms the other party proposed and settles the trade. A
+    /// buyer accepting a counter attaches any funds needed to cover it.
+    AcceptOffer {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        offer_id: u64,
+    },
+    /// Rejects or withdraws an offer and refunds the escrow to the buyer.
+    RejectOffer {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        offer_id: u64,
+    },
+    /// Bids for credits from any batch matching `criteria`. The attached funds
+    /// must cover the price and buyer fee of `quantity` exactly and are held
+    /// in escrow.
+    PlaceCollectionBid {
+        criteria: CollectionBidCriteria,
+        quantity: Uint128,
+        price: Coin,
+        expiration: Option<Timestamp>,
+        #[serde(default)]
+        disable_auto_retire: bool,
+        retirement_jurisdiction: Option<String>,
+        retirement_reason: Option<String>,
+        /// Applies to every fill of the bid.
+        #[serde(default)]
+        on_delivery_failure: DeliveryFailure,
+    },
+    /// Cancels the sender's collection bid and refunds the remaining escrow.
+    CancelCollectionBid {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        bid_id: u64,
+    },
+    /// Sells into a collection bid from one of the sender's sell orders, or
+    /// from their orders of `batch_denom` in id order. Exactly one of
+    /// `sell_order_id` and `batch_denom` must be set.
+    FillCollectionBid {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        bid_id: u64,
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
+        sell_order_id: Option<u64>,
+        batch_denom: Option<String>,
+        quantity: Uint128,
+    },
+    /// Lists credits from several batches for a single price, sold all or
+    /// nothing.
+    SellBundle {
+        items: Vec<BundleItem>,
+        price: Coin,
+        expiration: Option<Timestamp>,
+    },
+    CancelBundle {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        bundle_id: u64,
+    },
+    /// Buys a whole bundle, paying like `BuyDirect`. Batches without retirement
+    /// options are retired unless the seller disabled auto-retirement for them.
+    BuyBundle {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        bundle_id: u64,
+        max_fee_amount: Coin,
+        #[serde(default)]
+        retirement_options: Vec<BundleRetirementMsg>,
+        beneficiary: Option<String>,
+        #[serde(default)]
+        use_balance: bool,
+    },
+    /// Receive hook of cw20 and cw1155 token contracts. `msg` is a payable
+    /// action paid for with cw20 tokens, see `ExecuteMsg::is_payable`, or a
+    /// `Sell` of credits held as the received tokens.
+    Receive(ReceiveMsg),
+    /// Receive hook of cw1155 contracts for several token ids, see `Receive`.
+    BatchReceive(Cw1155BatchReceiveMsg),
+    /// Buys like `BuyDirect`, paying from the deposited balance of `owner`
+    /// under the allowance they granted the sender.
+    BuyDirectFrom {
+        owner: String,
+        orders: Vec<BuyOrderMsg>,
+    },
+    /// A native marketplace message in its proto JSON `Any` form. Its seller
+    /// or buyer must be the sender.
+    Marketplace(MarketplaceMsg),
+    /// Imports a chunk of a native marketplace genesis export, preserving
+    /// ids. Authority only, before `Finalize`.
+    ImportBatch {
+        genesis: MarketplaceGenesis,
+    },
+    /// Imports a page of an `ExportState` dump, preserving ids. Authority
+    /// only, before `Finalize`.
+    ImportState {
+        version: u32,
+        records: StateRecords,
+        /// Takes the authority of an imported `config` instead of keeping
+        /// this marketplace's.
+        #[serde(default)]
+        import_authority: bool,
+    },
+    /// Ends the genesis import and opens trading. Authority only.
+    Finalize {},
+    /// Scans up to `limit` records, removing expired sell orders, offers,
+    /// collection bids, bundles and purchase allowances and refunding their
+    /// escrow. Anyone may send it, and is paid a capped reward from the fee
+    /// pool for each expired offer or collection bid removed.
+    Crank {
+        limit: u32,
+    },
+    /// Sets the share of the fee pool a `Crank` may pay its sender.
+    /// Authority only.
+    GovSetCrankRewardShare {
+        share: Decimal,
+    },
+    /// Sends the credits of a trade whose delivery failed again. Anyone may
+    /// send it.
+    RetrySettlement {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        id: u64,
+    },
+    /// Voids a trade whose delivery failed: the trade is marked refunded, the
+    /// held payment goes back to where it was paid from, and escrowed credits
+    /// to the seller. Payer only.
+    RefundSettlement {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        id: u64,
+    },
+    /// Calls `contract` with a `MarketplaceHook` message on every `event`.
+    /// Authority only.
+    AddHook {
+        event: HookEvent,
+        contract: String,
+        #[serde(default)]
+        required: bool,
+    },
+    /// Authority only.
+    RemoveHook {
+        event: HookEvent,
+        contract: String,
+    },
+    /// Registers a cw20 or cw1155 token as holding credits of `batch_denom`,
+    /// so orders of that batch can list it. Authority only.
+    AddCreditToken {
+        batch_denom: String,
+        asset: CreditAssetMsg,
+        /// The marketplace holds native credits backing the token, to be
+        /// retired with the token. Needs the Regen adapter.
+        #[serde(default)]
+        custodial: bool,
+    },
+    /// Authority only. Orders already listing the token stay open.
+    RemoveCreditToken {
+        asset: CreditAssetMsg,
+    },
+}
+
+/// cw20 and cw1155 contracts both call the `receive` hook, with different
+/// payloads.
+#[cw_serde]
+#[serde(untagged)]
+pub enum ReceiveMsg {
+    Cw20(Cw20ReceiveMsg),
+    Cw1155(Cw1155ReceiveMsg),
+}
+
+impl ExecuteMsg {
+    /// Actions that take attached funds, and can therefore be paid for with
+    /// cw20 tokens through `Receive`.
+    pub fn is_payable(&self) -> bool {
+        matches!(
+            self,
+            ExecuteMsg::BuyDirect { .. }
+                | ExecuteMsg::BuyWithSignedOrder { .. }
+                | ExecuteMsg::Deposit {}
+                | ExecuteMsg::MakeOffer { .. }
+                | ExecuteMsg::CounterOffer { .. }
+                | ExecuteMsg::AcceptOffer { .. }
+                | ExecuteMsg::PlaceCollectionBid { .. }
+                | ExecuteMsg::BuyBundle { .. }
+        )
+    }
 }
 
 #[cw_serde]
 pub struct SellOrderMsg {
     pub batch_denom: String,
-    pub quantity: String,
+    pub quantity: Uint128,
     pub ask_price: Coin,
     pub disable_auto_retire: bool,
+    /// RFC 3339. Nanoseconds since the epoch are accepted too.
+    #[serde(default, with = "proto::timestamp_json")]
+    #[schemars(with = "Option<String>")]
     pub expiration: Option<Timestamp>,
+    /// Makes the order private to the designated buyers.
+    pub allowed_buyers: Option<AllowedBuyersMsg>,
+    /// Token holding the credits. Defaults to native credits. cw20 and cw1155
+    /// credits are sold by sending them to the marketplace with this order.
+    pub asset: Option<CreditAssetMsg>,
+}
+
+#[cw_serde]
+pub enum CreditAssetMsg {
+    Native,
+    Cw20 { contract: String },
+    Cw1155 { contract: String, token_id: String },
+}
+
+#[cw_serde]
+pub enum AllowedBuyersMsg {
+    Addresses {
+        addresses: Vec<String>,
+    },
+    /// See `AllowedBuyers::MerkleRoot`.
+    MerkleRoot {
+        root: HexBinary,
+    },
 }
 
 #[cw_serde]
 pub struct UpdateSellOrderMsg {
+    #[serde(with = "proto::u64_json")]
+    #[schemars(with = "String")]
     pub sell_order_id: u64,
-    pub new_quantity: Option<String>,
+    pub new_quantity: Option<Uint128>,
     pub new_ask_price: Option<Coin>,
     pub disable_auto_retire: Option<bool>,
+    #[serde(default, with = "proto::timestamp_json")]
+    #[schemars(with = "Option<String>")]
     pub new_expiration: Option<Timestamp>,
 }
 
 #[cw_serde]
 pub struct BuyOrderMsg {
+    #[serde(with = "proto::u64_json")]
+    #[schemars(with = "String")]
     pub sell_order_id: u64,
-    pub quantity: String,
+    pub quantity: Uint128,
     pub bid_price: Coin,
     pub disable_auto_retire: bool,
     pub retirement_jurisdiction: Option<String>,
     pub retirement_reason: Option<String>,
     pub max_fee_amount: Coin,
+    /// Receives the credits, or is named on their retirement. Defaults to the
+    /// account paying.
+    pub beneficiary: Option<String>,
+    /// Fails the purchase if the sell order was updated since this version.
+    pub expected_version: Option<u64>,
+    /// Proves the payer is a designated buyer of a private order with a
+    /// merkle root.
+    pub merkle_proof: Option<Vec<HexBinary>>,
+    /// Whether a failed credit delivery rolls back the purchase or waits for
+    /// `RetrySettlement`.
+    #[serde(default)]
+    pub on_delivery_failure: DeliveryFailure,
+}
+
+#[cw_serde]
+pub struct BundleRetirementMsg {
+    pub batch_denom: String,
+    pub disable_auto_retire: bool,
+    pub retirement_jurisdiction: Option<String>,
+    pub retirement_reason: Option<String>,
+}
+
+/// Sell order signed off-chain by the seller. The sign bytes are the
+/// canonical JSON of this struct, see `QueryMsg::SignedOrderSignBytes`.
+#[cw_serde]
+pub struct SignedSellOrder {
+    /// Address of the marketplace contract the order is valid on.
+    pub marketplace: String,
+    pub chain_id: String,
+    pub seller: String,
+    pub batch_denom: String,
+    pub quantity: Uint128,
+    pub ask_price: Coin,
+    pub disable_auto_retire: bool,
+    pub expiration: Timestamp,
+    /// Chosen by the seller, unique per order. Fills are tracked per seller
+    /// and nonce, so one signature can never sell more than `quantity`.
+    pub nonce: u64,
+}
+
+#[cw_serde]
+pub struct SignedOrderBuyMsg {
+    pub quantity: Uint128,
+    pub bid_price: Coin,
+    pub disable_auto_retire: bool,
+    pub retirement_jurisdiction: Option<String>,
+    pub retirement_reason: Option<String>,
+    pub max_fee_amount: Coin,
+    /// Receives the credits, or is named on their retirement. Defaults to the
+    /// sender, who always pays.
+    pub beneficiary: Option<String>,
+    /// Pays from the sender's deposited balance instead of attached funds.
+    #[serde(default)]
+    pub use_balance: bool,
+    /// Whether a failed credit delivery rolls back the purchase or waits for
+    /// `RetrySettlement`.
+    #[serde(default)]
+    pub on_delivery_failure: DeliveryFailure,
 }
 
 #[cw_serde]
 #[derive(QueryResponses)]
 pub enum QueryMsg {
     #[returns(SellOrderResponse)]
-    SellOrder { sell_order_id: u64 },
+    SellOrder {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        sell_order_id: u64,
+    },
+    /// Private orders are only listed with `include_private`.
     #[returns(SellOrdersResponse)]
     SellOrders {
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
         start_after: Option<u64>,
         limit: Option<u32>,
+        include_private: Option<bool>,
     },
     #[returns(SellOrdersResponse)]
     SellOrdersByBatch {
         batch_denom: String,
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
         start_after: Option<u64>,
         limit: Option<u32>,
+        include_private: Option<bool>,
     },
     #[returns(SellOrdersResponse)]
     SellOrdersBySeller {
         seller: String,
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
         start_after: Option<u64>,
         limit: Option<u32>,
+        include_private: Option<bool>,
     },
     #[returns(AllowedDenomsResponse)]
     AllowedDenoms {
         start_after: Option<String>,
         limit: Option<u32>,
     },
+    #[returns(ConfigResponse)]
+    Config {},
+    #[returns(PausesResponse)]
+    Pauses {},
+    #[returns(SignedOrderResponse)]
+    SignedOrder { seller: String, nonce: u64 },
+    #[returns(SignedOrderSignBytesResponse)]
+    SignedOrderSignBytes { order: SignedSellOrder },
+    #[returns(TradeResponse)]
+    Trade {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        trade_id: u64,
+    },
+    #[returns(TradesResponse)]
+    Trades {
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Trades whose credits went to, or were retired for, `beneficiary`.
+    #[returns(TradesResponse)]
+    TradesByBeneficiary {
+        beneficiary: String,
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(PurchaseAllowanceResponse)]
+    PurchaseAllowance { owner: String, spender: String },
+    #[returns(OfferResponse)]
+    Offer {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        offer_id: u64,
+    },
+    #[returns(OffersResponse)]
+    OffersForOrder {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        sell_order_id: u64,
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(OffersResponse)]
+    OffersByBuyer {
+        buyer: String,
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(CollectionBidResponse)]
+    CollectionBid {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        bid_id: u64,
+    },
+    #[returns(CollectionBidsResponse)]
+    CollectionBids {
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(BundleResponse)]
+    Bundle {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        bundle_id: u64,
+    },
+    #[returns(BundlesResponse)]
+    Bundles {
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(BundlesResponse)]
+    BundlesBySeller {
+        seller: String,
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Deposited balances of one account.
+    #[returns(BalanceResponse)]
+    Balance { address: String },
+    /// Deposited balances of all accounts, paginated by account.
+    #[returns(BalancesResponse)]
+    Balances {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// A sell order as the native module's `SellOrderInfo`.
+    #[returns(QuerySellOrderResponse)]
+    SellOrderInfo {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        sell_order_id: u64,
+    },
+    /// Public sell orders of a batch as `SellOrderInfo`s.
+    #[returns(QuerySellOrdersResponse)]
+    SellOrderInfosByBatch {
+        batch_denom: String,
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// A sell order as it was at the start of block `height`, before that
+    /// block's changes. `None` if it did not exist then.
+    #[returns(SellOrderAtHeightResponse)]
+    SellOrderAtHeight {
+        #[serde(with = "proto::u64_json")]
+        #[schemars(with = "String")]
+        sell_order_id: u64,
+        height: u64,
+    },
+    /// Sell orders of a batch, private ones included, as they were at the
+    /// start of block `height`. A page may hold fewer than `limit` orders;
+    /// the query continues from `next_start_after` until it is `None`.
+    #[returns(SellOrdersAtHeightResponse)]
+    SellOrdersByBatchAtHeight {
+        batch_denom: String,
+        height: u64,
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Fee params as they were at the start of block `height`.
+    #[returns(FeeParamsAtHeightResponse)]
+    FeeParamsAtHeight { height: u64 },
+    #[returns(HooksResponse)]
+    Hooks {},
+    /// Registered credit tokens by escrow denom.
+    #[returns(CreditTokensResponse)]
+    CreditTokens {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Deliveries that failed and await `RetrySettlement`, by trade id.
+    #[returns(SettlementsResponse)]
+    FailedSettlements {
+        #[serde(default, with = "proto::option_u64_json")]
+        #[schemars(with = "Option<String>")]
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// One page of a storage section, in a form `ImportState` takes back.
+    /// `start_after` is an id or a denom, as returned in `next`.
+    #[returns(ExportStateResponse)]
+    ExportState {
+        section: StateSection,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
 }
 
 #[cw_serde]
@@ -102,6 +679,24 @@ pub struct SellOrderResponse {
     pub sell_order: SellOrder,
 }
 
+#[cw_serde]
+pub struct SellOrderAtHeightResponse {
+    pub sell_order: Option<SellOrder>,
+}
+
+#[cw_serde]
+pub struct SellOrdersAtHeightResponse {
+    pub sell_orders: Vec<SellOrder>,
+    /// Last id looked up, to pass as `start_after` for the next page. `None`
+    /// once every id has been looked up.
+    pub next_start_after: Option<u64>,
+}
+
+#[cw_serde]
+pub struct FeeParamsAtHeightResponse {
+    pub fee_params: Option<FeeParams>,
+}
+
 #[cw_serde]
 pub struct SellOrdersResponse {
     pub sell_orders: Vec<SellOrder>,
@@ -111,3 +706,233 @@ pub struct SellOrdersResponse {
 pub struct AllowedDenomsResponse {
     pub allowed_denoms: Vec<AllowedDenom>,
 }
+
+#[cw_serde]
+pub struct ConfigResponse {
+    pub config: Config,
+}
+
+#[cw_serde]
+pub struct PausesResponse {
+    pub pauses: Vec<PauseScope>,
+}
+
+#[cw_serde]
+pub struct SignedOrderResponse {
+    pub status: SignedOrderStatus,
+}
+
+#[cw_serde]
+pub struct SignedOrderSignBytesResponse {
+    pub sign_bytes: Binary,
+}
+
+#[cw_serde]
+pub struct TradeResponse {
+    pub trade: Trade,
+}
+
+#[cw_serde]
+pub struct TradesResponse {
+    pub trades: Vec<Trade>,
+}
+
+#[cw_serde]
+pub struct PurchaseAllowanceResponse {
+    pub allowance: Option<PurchaseAllowance>,
+}
+
+#[cw_serde]
+pub struct BalanceResponse {
+    pub balances: Vec<Coin>,
+}
+
+#[cw_serde]
+pub struct AccountBalances {
+    pub address: Addr,
+    pub balances: Vec<Coin>,
+}
+
+#[cw_serde]
+pub struct BalancesResponse {
+    pub accounts: Vec<AccountBalances>,
+}
+
+#[cw_serde]
+pub struct OfferResponse {
+    pub offer: Offer,
+}
+
+#[cw_serde]
+pub struct OffersResponse {
+    pub offers: Vec<Offer>,
+}
+
+#[cw_serde]
+pub struct CollectionBidResponse {
+    pub bid: CollectionBid,
+}
+
+#[cw_serde]
+pub struct CollectionBidsResponse {
+    pub bids: Vec<CollectionBid>,
+}
+
+#[cw_serde]
+pub struct BundleResponse {
+    pub bundle: Bundle,
+}
+
+#[cw_serde]
+pub struct BundlesResponse {
+    pub bundles: Vec<Bundle>,
+}
+
+#[cw_serde]
+pub struct CreditTokensResponse {
+    pub tokens: Vec<CreditToken>,
+}
+
+#[cw_serde]
+pub struct SettlementsResponse {
+    pub settlements: Vec<Settlement>,
+}
+
+#[cw_serde]
+pub struct HooksResponse {
+    pub hooks: Vec<Hook>,
+}
+
+/// Message hooked contracts receive. Their execute message must accept it as
+/// a `marketplace_hook` variant.
+#[cw_serde]
+pub enum HookExecuteMsg {
+    MarketplaceHook(HookMsg),
+}
+
+#[cw_serde]
+pub enum HookMsg {
+    OrderCreated { sell_order: SellOrder },
+    OrderFilled { trade: Trade },
+    OrderCancelled { sell_order: SellOrder },
+    CreditsRetired { trade: Trade },
+}
+
+impl HookMsg {
+    pub fn event(&self) -> HookEvent {
+        match self {
+            HookMsg::OrderCreated { .. } => HookEvent::OrderCreated,
+            HookMsg::OrderFilled { .. } => HookEvent::OrderFilled,
+            HookMsg::OrderCancelled { .. } => HookEvent::OrderCancelled,
+            HookMsg::CreditsRetired { .. } => HookEvent::CreditsRetired,
+        }
+    }
+}
+
+/// Version of the `ExportState` format, bumped whenever a section changes
+/// shape or is added.
+pub const STATE_EXPORT_VERSION: u32 = 3;
+
+/// Oldest `ExportState` format still imported. Version 3 only added sections.
+pub const MIN_STATE_EXPORT_VERSION: u32 = 2;
+
+/// One storage namespace. Sections keyed by two values page with
+/// `start_after` set to both, joined by `/`.
+#[cw_serde]
+pub enum StateSection {
+    Config,
+    FeeParams,
+    FeePool,
+    Sequences,
+    AllowedDenoms,
+    Batches,
+    Markets,
+    SellOrders,
+    Balances,
+    Offers,
+    CollectionBids,
+    Bundles,
+    Trades,
+    PurchaseAllowances,
+    SignedOrders,
+    Pauses,
+    Hooks,
+    CreditTokens,
+    Settlements,
+    CrankCursor,
+    ContractVersion,
+    GenesisImport,
+}
+
+#[cw_serde]
+pub enum StateRecords {
+    Config(Config),
+    FeeParams(FeeParams),
+    FeePool(Vec<Coin>),
+    Sequences(Sequences),
+    AllowedDenoms(Vec<AllowedDenom>),
+    Batches(Vec<Batch>),
+    Markets(Vec<Market>),
+    SellOrders(Vec<SellOrder>),
+    Balances(Vec<BalanceRecord>),
+    Offers(Vec<Offer>),
+    CollectionBids(Vec<CollectionBid>),
+    Bundles(Vec<Bundle>),
+    Trades(Vec<Trade>),
+    PurchaseAllowances(Vec<PurchaseAllowanceRecord>),
+    SignedOrders(Vec<SignedOrderRecord>),
+    Pauses(Vec<PauseScope>),
+    Hooks(Vec<Hook>),
+    CreditTokens(Vec<CreditToken>),
+    Settlements(Vec<Settlement>),
+    CrankCursor(CrankCursor),
+    /// The cw2 name and version of the exporting contract.
+    ContractVersion(cw2::ContractVersion),
+    /// Whether the exporting marketplace was still importing its genesis.
+    GenesisImport(bool),
+}
+
+/// Deposited balance of one account in one denom.
+#[cw_serde]
+pub struct BalanceRecord {
+    pub account: Addr,
+    pub balance: Coin,
+}
+
+#[cw_serde]
+pub struct PurchaseAllowanceRecord {
+    pub owner: Addr,
+    pub spender: Addr,
+    pub allowance: PurchaseAllowance,
+}
+
+/// Fill and cancel status of a signed order, which keeps it from being
+/// replayed.
+#[cw_serde]
+pub struct SignedOrderRecord {
+    pub seller: Addr,
+    pub nonce: u64,
+    pub status: SignedOrderStatus,
+}
+
+/// Last id handed out by each sequence.
+#[cw_serde]
+#[derive(Default)]
+pub struct Sequences {
+    pub sell_order: u64,
+    pub batch: u64,
+    pub market: u64,
+    pub trade: u64,
+    pub offer: u64,
+    pub collection_bid: u64,
+    pub bundle: u64,
+    pub hook: u64,
+}
+
+#[cw_serde]
+pub struct ExportStateResponse {
+    pub version: u32,
+    pub records: StateRecords,
+    /// `start_after` of the next page, or `None` on the last one.
+    pub next: Option<String>,
+}
@@ -0,0 +1,545 @@
+//! Canonical proto JSON of the `regen.ecocredit.marketplace.v1` messages and
+//! sell orders, for clients written against the native marketplace module.
+//!
+//! Proto JSON encodes `uint64` as strings, timestamps as RFC 3339 and credit
+//! quantities as decimal strings. The types here serialize exactly that way,
+//! and the `msg.rs` types read and write the same forms for ids and
+//! timestamps. Field names are written in snake_case, as the proto files
+//! declare them, and the lowerCamelCase names of canonical proto JSON are
+//! accepted too.
+
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Binary, Coin, Decimal, Timestamp, Uint128};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::msg::{BuyOrderMsg, ExecuteMsg, SellOrderMsg, UpdateSellOrderMsg};
+use crate::state::{DeliveryFailure, SellOrder};
+
+/// A marketplace `Msg` in its `Any` JSON form, tagged with its type URL.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(tag = "@type")]
+pub enum MarketplaceMsg {
+    #[serde(rename = "/regen.ecocredit.marketplace.v1.MsgSell")]
+    Sell(MsgSell),
+    #[serde(rename = "/regen.ecocredit.marketplace.v1.MsgUpdateSellOrders")]
+    UpdateSellOrders(MsgUpdateSellOrders),
+    #[serde(rename = "/regen.ecocredit.marketplace.v1.MsgCancelSellOrder")]
+    CancelSellOrder(MsgCancelSellOrder),
+    #[serde(rename = "/regen.ecocredit.marketplace.v1.MsgBuyDirect")]
+    BuyDirect(MsgBuyDirect),
+}
+
+impl MarketplaceMsg {
+    /// Account that must send the message.
+    pub fn signer(&self) -> &str {
+        match self {
+            MarketplaceMsg::Sell(msg) => &msg.seller,
+            MarketplaceMsg::UpdateSellOrders(msg) => &msg.seller,
+            MarketplaceMsg::CancelSellOrder(msg) => &msg.seller,
+            MarketplaceMsg::BuyDirect(msg) => &msg.buyer,
+        }
+    }
+}
+
+impl From<MarketplaceMsg> for ExecuteMsg {
+    fn from(msg: MarketplaceMsg) -> Self {
+        match msg {
+            MarketplaceMsg::Sell(msg) => ExecuteMsg::Sell {
+                orders: msg.orders.into_iter().map(Into::into).collect(),
+            },
+            MarketplaceMsg::UpdateSellOrders(msg) => ExecuteMsg::UpdateSellOrders {
+                updates: msg.updates.into_iter().map(Into::into).collect(),
+            },
+            MarketplaceMsg::CancelSellOrder(msg) => ExecuteMsg::CancelSellOrder {
+                sell_order_id: msg.sell_order_id,
+            },
+            MarketplaceMsg::BuyDirect(msg) => ExecuteMsg::BuyDirect {
+                orders: msg.orders.into_iter().map(Into::into).collect(),
+                use_balance: false,
+            },
+        }
+    }
+}
+
+#[cw_serde]
+pub struct MsgSell {
+    pub seller: String,
+    pub orders: Vec<MsgSellOrder>,
+}
+
+#[cw_serde]
+pub struct MsgSellOrder {
+    #[serde(alias = "batchDenom")]
+    pub batch_denom: String,
+    #[serde(with = "whole_amount_json")]
+    #[schemars(with = "String")]
+    pub quantity: Uint128,
+    #[serde(alias = "askPrice")]
+    pub ask_price: Coin,
+    #[serde(default, alias = "disableAutoRetire")]
+    pub disable_auto_retire: bool,
+    #[serde(default, with = "timestamp_json")]
+    #[schemars(with = "Option<String>")]
+    pub expiration: Option<Timestamp>,
+}
+
+impl From<MsgSellOrder> for SellOrderMsg {
+    fn from(order: MsgSellOrder) -> Self {
+        SellOrderMsg {
+            batch_denom: order.batch_denom,
+            quantity: order.quantity,
+            ask_price: order.ask_price,
+            disable_auto_retire: order.disable_auto_retire,
+            expiration: order.expiration,
+            allowed_buyers: None,
+            asset: None,
+        }
+    }
+}
+
+#[cw_serde]
+pub struct MsgUpdateSellOrders {
+    pub seller: String,
+    pub updates: Vec<MsgUpdateSellOrder>,
+}
+
+#[cw_serde]
+pub struct MsgUpdateSellOrder {
+    #[serde(with = "u64_json", alias = "sellOrderId")]
+    #[schemars(with = "String")]
+    pub sell_order_id: u64,
+    #[serde(with = "whole_amount_json", alias = "newQuantity")]
+    #[schemars(with = "String")]
+    pub new_quantity: Uint128,
+    #[serde(default, alias = "newAskPrice")]
+    pub new_ask_price: Option<Coin>,
+    #[serde(default, alias = "disableAutoRetire")]
+    pub disable_auto_retire: bool,
+    #[serde(default, with = "timestamp_json", alias = "newExpiration")]
+    #[schemars(with = "Option<String>")]
+    pub new_expiration: Option<Timestamp>,
+}
+
+impl From<MsgUpdateSellOrder> for UpdateSellOrderMsg {
+    fn from(update: MsgUpdateSellOrder) -> Self {
+        UpdateSellOrderMsg {
+            sell_order_id: update.sell_order_id,
+            new_quantity: Some(update.new_quantity),
+            new_ask_price: update.new_ask_price,
+            disable_auto_retire: Some(update.disable_auto_retire),
+            new_expiration: update.new_expiration,
+        }
+    }
+}
+
+#[cw_serde]
+pub struct MsgCancelSellOrder {
+    pub seller: String,
+    #[serde(with = "u64_json", alias = "sellOrderId")]
+    #[schemars(with = "String")]
+    pub sell_order_id: u64,
+}
+
+#[cw_serde]
+pub struct MsgBuyDirect {
+    pub buyer: String,
+    pub orders: Vec<MsgBuyDirectOrder>,
+}
+
+#[cw_serde]
+pub struct MsgBuyDirectOrder {
+    #[serde(with = "u64_json", alias = "sellOrderId")]
+    #[schemars(with = "String")]
+    pub sell_order_id: u64,
+    #[serde(with = "whole_amount_json")]
+    #[schemars(with = "String")]
+    pub quantity: Uint128,
+    #[serde(alias = "bidPrice")]
+    pub bid_price: Coin,
+    #[serde(default, alias = "disableAutoRetire")]
+    pub disable_auto_retire: bool,
+    #[serde(default, alias = "retirementJurisdiction")]
+    pub retirement_jurisdiction: String,
+    #[serde(default, alias = "retirementReason")]
+    pub retirement_reason: String,
+    #[serde(alias = "maxFeeAmount")]
+    pub max_fee_amount: Coin,
+}
+
+impl From<MsgBuyDirectOrder> for BuyOrderMsg {
+    fn from(order: MsgBuyDirectOrder) -> Self {
+        let non_empty = |value: String| (!value.is_empty()).then_some(value);
+        BuyOrderMsg {
+            sell_order_id: order.sell_order_id,
+            quantity: order.quantity,
+            bid_price: order.bid_price,
+            disable_auto_retire: order.disable_auto_retire,
+            retirement_jurisdiction: non_empty(order.retirement_jurisdiction),
+            retirement_reason: non_empty(order.retirement_reason),
+            max_fee_amount: order.max_fee_amount,
+            beneficiary: None,
+            expected_version: None,
+            merkle_proof: None,
+            on_delivery_failure: DeliveryFailure::Revert,
+        }
+    }
+}
+
+/// `regen.ecocredit.marketplace.v1.SellOrderInfo`.
+#[cw_serde]
+pub struct SellOrderInfo {
+    #[serde(with = "u64_json")]
+    #[schemars(with = "String")]
+    pub id: u64,
+    pub seller: String,
+    #[serde(alias = "batchDenom")]
+    pub batch_denom: String,
+    #[serde(with = "whole_amount_json")]
+    #[schemars(with = "String")]
+    pub quantity: Uint128,
+    #[serde(alias = "askDenom")]
+    pub ask_denom: String,
+    #[serde(alias = "askAmount")]
+    pub ask_amount: Uint128,
+    #[serde(default, alias = "disableAutoRetire")]
+    pub disable_auto_retire: bool,
+    #[serde(default, with = "timestamp_json")]
+    #[schemars(with = "Option<String>")]
+    pub expiration: Option<Timestamp>,
+}
+
+impl SellOrderInfo {
+    /// Describes `order`, whose batch and ask denoms are stored by key.
+    pub fn new(order: &SellOrder, batch_denom: String, ask_denom: String) -> Self {
+        SellOrderInfo {
+            id: order.id,
+            seller: order.seller.to_string(),
+            batch_denom,
+            quantity: order.quantity,
+            ask_denom,
+            ask_amount: order.ask_amount,
+            disable_auto_retire: order.disable_auto_retire,
+            expiration: order.expiration,
+        }
+    }
+
+    /// The `Sell` order listing the same credits on the same terms.
+    pub fn to_sell_order_msg(&self) -> SellOrderMsg {
+        SellOrderMsg {
+            batch_denom: self.batch_denom.clone(),
+            quantity: self.quantity,
+            ask_price: Coin::new(self.ask_amount.u128(), &self.ask_denom),
+            disable_auto_retire: self.disable_auto_retire,
+            expiration: self.expiration,
+            allowed_buyers: None,
+            asset: None,
+        }
+    }
+}
+
+/// `regen.ecocredit.marketplace.v1.QuerySellOrderResponse`.
+#[cw_serde]
+pub struct QuerySellOrderResponse {
+    #[serde(alias = "sellOrder")]
+    pub sell_order: SellOrderInfo,
+}
+
+/// Response of the `SellOrders*` queries of the native module. Pagination is
+/// by `start_after` instead of page keys.
+#[cw_serde]
+pub struct QuerySellOrdersResponse {
+    #[serde(alias = "sellOrders")]
+    pub sell_orders: Vec<SellOrderInfo>,
+}
+
+/// A chunk of a `regen.ecocredit.marketplace.v1` genesis export, with the
+/// `regen.ecocredit.v1` batches its sell orders refer to by key. Tables are
+/// keyed by their full names, as in the export; other tables are ignored.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct MarketplaceGenesis {
+    #[serde(rename = "regen.ecocredit.marketplace.v1.SellOrder", default)]
+    pub sell_orders: Vec<GenesisSellOrder>,
+    #[serde(rename = "regen.ecocredit.marketplace.v1.AllowedDenom", default)]
+    pub allowed_denoms: Vec<GenesisAllowedDenom>,
+    #[serde(rename = "regen.ecocredit.marketplace.v1.Market", default)]
+    pub markets: Vec<GenesisMarket>,
+    #[serde(rename = "regen.ecocredit.marketplace.v1.FeeParams", default)]
+    pub fee_params: Option<GenesisFeeParams>,
+    #[serde(rename = "regen.ecocredit.v1.Batch", default)]
+    pub batches: Vec<GenesisBatch>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GenesisSellOrder {
+    #[serde(with = "u64_json")]
+    #[schemars(with = "String")]
+    pub id: u64,
+    /// Address bytes of the seller.
+    pub seller: Binary,
+    #[serde(with = "u64_json", alias = "batchKey")]
+    #[schemars(with = "String")]
+    pub batch_key: u64,
+    /// Decimal string; must be a whole number of credits.
+    pub quantity: String,
+    #[serde(with = "u64_json", alias = "marketId")]
+    #[schemars(with = "String")]
+    pub market_id: u64,
+    #[serde(alias = "askAmount")]
+    pub ask_amount: String,
+    #[serde(default, alias = "disableAutoRetire")]
+    pub disable_auto_retire: bool,
+    #[serde(default, with = "timestamp_json")]
+    #[schemars(with = "Option<String>")]
+    pub expiration: Option<Timestamp>,
+    #[serde(default)]
+    pub maker: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GenesisAllowedDenom {
+    #[serde(alias = "bankDenom")]
+    pub bank_denom: String,
+    #[serde(alias = "displayDenom")]
+    pub display_denom: String,
+    #[serde(default)]
+    pub exponent: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GenesisMarket {
+    #[serde(with = "u64_json")]
+    #[schemars(with = "String")]
+    pub id: u64,
+    #[serde(alias = "creditTypeAbbrev")]
+    pub credit_type_abbrev: String,
+    #[serde(alias = "bankDenom")]
+    pub bank_denom: String,
+    #[serde(default, alias = "precisionModifier")]
+    pub precision_modifier: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GenesisFeeParams {
+    #[serde(alias = "buyerPercentageFee")]
+    pub buyer_percentage_fee: Decimal,
+    #[serde(alias = "sellerPercentageFee")]
+    pub seller_percentage_fee: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GenesisBatch {
+    #[serde(with = "u64_json")]
+    #[schemars(with = "String")]
+    pub key: u64,
+    pub denom: String,
+}
+
+/// Parses a proto decimal string that must hold a whole number, such as
+/// `"10"` or `"10.000"`. Credits on Regen have up to 6 decimals, but the
+/// contract trades whole credits only, so `"10.5"` is rejected.
+pub fn parse_whole_amount(value: &str) -> Result<Uint128, String> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let whole = whole
+        .parse()
+        .ok()
+        .filter(|_| fraction.bytes().all(|b| b.is_ascii_digit()))
+        .ok_or_else(|| format!("{} is not a decimal number", value))?;
+    if fraction.bytes().any(|b| b != b'0') {
+        return Err(format!("fractional quantities are unsupported: {}", value));
+    }
+    Ok(whole)
+}
+
+/// Credit quantity as a proto decimal string, which must be a whole number of
+/// credits. Written without a fraction.
+pub mod whole_amount_json {
+    use cosmwasm_std::Uint128;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Uint128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Uint128, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        super::parse_whole_amount(&value).map_err(de::Error::custom)
+    }
+}
+
+/// `uint64` as a JSON string; numbers are accepted too.
+pub mod u64_json {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Number(u64),
+            String(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Number(value) => Ok(value),
+            Repr::String(value) => value.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+/// Optional `uint64` as a JSON string; numbers are accepted too.
+pub mod option_u64_json {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => super::u64_json::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(with = "super::u64_json")] u64);
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(value)| value))
+    }
+}
+
+/// Optional timestamp as RFC 3339. Nanoseconds since the epoch, the encoding
+/// of [`Timestamp`], are accepted too.
+pub mod timestamp_json {
+    use cosmwasm_std::Timestamp;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Timestamp>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_str(&super::format_rfc3339(*value)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Timestamp>, D::Error> {
+        let Some(value) = Option::<String>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+        if let Ok(nanos) = value.parse::<u64>() {
+            return Ok(Some(Timestamp::from_nanos(nanos)));
+        }
+        super::parse_rfc3339(&value)
+            .map(Some)
+            .ok_or_else(|| de::Error::custom(format!("invalid timestamp {}", value)))
+    }
+}
+
+/// Formats like protobuf's JSON encoding: UTC, with 0, 3, 6 or 9 fractional
+/// digits.
+pub fn format_rfc3339(timestamp: Timestamp) -> String {
+    let seconds = timestamp.seconds();
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let fraction = match timestamp.subsec_nanos() {
+        0 => String::new(),
+        n if n % 1_000_000 == 0 => format!(".{:03}", n / 1_000_000),
+        n if n % 1_000 == 0 => format!(".{:06}", n / 1_000),
+        n => format!(".{:09}", n),
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        seconds % 86_400 / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+        fraction
+    )
+}
+
+/// Parses `YYYY-MM-DDTHH:MM:SS[.fraction](Z|±HH:MM)` at or after the epoch.
+pub fn parse_rfc3339(value: &str) -> Option<Timestamp> {
+    let (date, time) = value.split_once(['T', 't'])?;
+    let (time, offset) = match time.strip_suffix(['Z', 'z']) {
+        Some(time) => (time, 0),
+        None => {
+            let split = time.len().checked_sub(6)?;
+            let (time, offset) = (time.get(..split)?, time.get(split..)?);
+            let sign = match offset.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let [hours, minutes] = fields(&offset[1..], ':', [2, 2])?;
+            (time, sign * (hours * 3600 + minutes * 60))
+        }
+    };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, "0"));
+    let [year, month, day] = fields(date, '-', [4, 2, 2])?;
+    let [hour, minute, second] = fields(time, ':', [2, 2, 2])?;
+    // Leap seconds are not representable.
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    // Rejects days past the end of the month.
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+
+    let nanos = match fraction.len() {
+        1..=9 if fraction.bytes().all(|b| b.is_ascii_digit()) => {
+            fraction.parse::<u64>().ok()? * 10u64.pow(9 - fraction.len() as u32)
+        }
+        _ => return None,
+    };
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    let seconds = u64::try_from(seconds).ok()?;
+    Some(Timestamp::from_nanos(
+        seconds.checked_mul(1_000_000_000)?.checked_add(nanos)?,
+    ))
+}
+
+/// Numeric fields of the given widths separated by `separator`.
+fn fields<const N: usize>(value: &str, separator: char, widths: [usize; N]) -> Option<[i64; N]> {
+    let mut fields = [0; N];
+    let mut parts = value.split(separator);
+    for (field, width) in fields.iter_mut().zip(widths) {
+        let part = parts.next()?;
+        if part.len() != width || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        *field = part.parse().ok()?;
+    }
+    parts.next().is_none().then_some(fields)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date, after Howard
+/// Hinnant's `days_from_civil`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, year_of_era) = (year.div_euclid(400), year.rem_euclid(400));
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let (era, day_of_era) = (days.div_euclid(146_097), days.rem_euclid(146_097));
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = (month_index + 2) % 12 + 1;
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+    (year, month, day)
+}
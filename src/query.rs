@@ -1,28 +1,58 @@
-use cosmwasm_std::{to_binary, Binary, Deps, Order, StdResult, Uint128};
-use cw_storage_plus::Bound;
+use cosmwasm_std::{to_json_binary, Addr, Binary, Coin, Deps, Order, StdError, StdResult};
+use cw_storage_plus::{Bound, Item, PrefixBound};
 
-use crate::msg::{AllowedDenomsResponse, SellOrderResponse, SellOrdersResponse};
-use crate::state::{AllowedDenom, SellOrder, ALLOWED_DENOMS, SELL_ORDERS};
+use crate::msg::{
+    AccountBalances, AllowedDenomsResponse, BalanceRecord, BalanceResponse, BalancesResponse,
+    BundleResponse, BundlesResponse, CollectionBidResponse, CollectionBidsResponse, ConfigResponse,
+    CreditTokensResponse, ExportStateResponse, FeeParamsAtHeightResponse, HooksResponse,
+    OfferResponse, OffersResponse, PausesResponse, PurchaseAllowanceRecord,
+    PurchaseAllowanceResponse, SellOrderAtHeightResponse, SellOrderResponse,
+    SellOrdersAtHeightResponse, SellOrdersResponse, Sequences, SettlementsResponse,
+    SignedOrderRecord, SignedOrderResponse, SignedOrderSignBytesResponse, SignedSellOrder,
+    StateRecords, StateSection, TradeResponse, TradesResponse, STATE_EXPORT_VERSION,
+};
+use crate::proto::{QuerySellOrderResponse, QuerySellOrdersResponse, SellOrderInfo};
+use crate::signing;
+use crate::state::{
+    AllowedDenom, Bundle, CollectionBid, CreditToken, Hook, Offer, PauseScope, SellOrder,
+    Settlement, Trade, ALLOWED_DENOMS, BALANCES, BATCHES, BATCH_KEYS, BATCH_SEQ, BUNDLES,
+    BUNDLE_SEQ, COLLECTION_BIDS, COLLECTION_BID_SEQ, CONFIG, CRANK_CURSOR, CREDIT_TOKENS,
+    FEE_PARAMS, FEE_POOL, GENESIS_IMPORT, HOOKS, HOOK_SEQ, MARKETS, MARKET_SEQ, OFFERS, OFFER_SEQ,
+    PAUSES, PURCHASE_ALLOWANCES, SELL_ORDERS, SELL_ORDER_SEQ, SETTLEMENTS, SIGNED_ORDERS, TRADES,
+    TRADE_SEQ,
+};
 
 const DEFAULT_LIMIT: u32 = 10;
 const MAX_LIMIT: u32 = 30;
+/// Most sell order ids `SellOrdersByBatchAtHeight` looks up per call.
+pub const MAX_HISTORY_SCAN: u64 = 100;
 
 pub fn sell_order(deps: Deps, sell_order_id: u64) -> StdResult<Binary> {
     let sell_order = SELL_ORDERS.load(deps.storage, sell_order_id)?;
-    to_binary(&SellOrderResponse { sell_order })
+    to_json_binary(&SellOrderResponse { sell_order })
 }
 
-pub fn sell_orders(deps: Deps, start_after: Option<u64>, limit: Option<u32>) -> StdResult<Binary> {
+pub fn sell_orders(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+    include_private: Option<bool>,
+) -> StdResult<Binary> {
     let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
     let start = start_after.map(Bound::exclusive);
+    let include_private = include_private.unwrap_or(false);
 
     let sell_orders: StdResult<Vec<SellOrder>> = SELL_ORDERS
         .range(deps.storage, start, None, Order::Ascending)
+        .filter(|r| match r {
+            Ok((_, sell_order)) => include_private || sell_order.allowed_buyers.is_none(),
+            Err(_) => true,
+        })
         .take(limit)
         .map(|item| item.map(|(_, sell_order)| sell_order))
         .collect();
 
-    to_binary(&SellOrdersResponse {
+    to_json_binary(&SellOrdersResponse {
         sell_orders: sell_orders?,
     })
 }
@@ -32,21 +62,31 @@ pub fn sell_orders_by_batch(
     batch_denom: String,
     start_after: Option<u64>,
     limit: Option<u32>,
+    include_private: Option<bool>,
 ) -> StdResult<Binary> {
     let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
     let start = start_after.map(Bound::exclusive);
+    let include_private = include_private.unwrap_or(false);
+    let Some(batch_key) = BATCH_KEYS.may_load(deps.storage, batch_denom)? else {
+        return to_json_binary(&SellOrdersResponse {
+            sell_orders: vec![],
+        });
+    };
 
     let sell_orders: StdResult<Vec<SellOrder>> = SELL_ORDERS
         .range(deps.storage, start, None, Order::Ascending)
         .filter(|r| match r {
-            Ok((_, sell_order)) => sell_order.batch_key.to_string() == batch_denom,
+            Ok((_, sell_order)) => {
+                sell_order.batch_key == batch_key
+                    && (include_private || sell_order.allowed_buyers.is_none())
+            }
             Err(_) => true,
         })
         .take(limit)
         .map(|item| item.map(|(_, sell_order)| sell_order))
         .collect();
 
-    to_binary(&SellOrdersResponse {
+    to_json_binary(&SellOrdersResponse {
         sell_orders: sell_orders?,
     })
 }
@@ -56,26 +96,77 @@ pub fn sell_orders_by_seller(
     seller: String,
     start_after: Option<u64>,
     limit: Option<u32>,
+    include_private: Option<bool>,
 ) -> StdResult<Binary> {
     let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
     let start = start_after.map(Bound::exclusive);
+    let include_private = include_private.unwrap_or(false);
     let seller_addr = deps.api.addr_validate(&seller)?;
 
     let sell_orders: StdResult<Vec<SellOrder>> = SELL_ORDERS
         .range(deps.storage, start, None, Order::Ascending)
         .filter(|r| match r {
-            Ok((_, sell_order)) => sell_order.seller == seller_addr,
+            Ok((_, sell_order)) => {
+                sell_order.seller == seller_addr
+                    && (include_private || sell_order.allowed_buyers.is_none())
+            }
             Err(_) => true,
         })
         .take(limit)
         .map(|item| item.map(|(_, sell_order)| sell_order))
         .collect();
 
-    to_binary(&SellOrdersResponse {
+    to_json_binary(&SellOrdersResponse {
         sell_orders: sell_orders?,
     })
 }
 
+pub fn sell_order_info(deps: Deps, sell_order_id: u64) -> StdResult<Binary> {
+    let sell_order = SELL_ORDERS.load(deps.storage, sell_order_id)?;
+    to_json_binary(&QuerySellOrderResponse {
+        sell_order: to_sell_order_info(deps, &sell_order)?,
+    })
+}
+
+pub fn sell_order_infos_by_batch(
+    deps: Deps,
+    batch_denom: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let Some(batch_key) = BATCH_KEYS.may_load(deps.storage, batch_denom)? else {
+        return to_json_binary(&QuerySellOrdersResponse {
+            sell_orders: vec![],
+        });
+    };
+
+    let sell_orders = SELL_ORDERS
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter(|r| match r {
+            Ok((_, sell_order)) => {
+                sell_order.batch_key == batch_key && sell_order.allowed_buyers.is_none()
+            }
+            Err(_) => true,
+        })
+        .take(limit)
+        .map(|item| to_sell_order_info(deps, &item?.1))
+        .collect::<StdResult<_>>()?;
+
+    to_json_binary(&QuerySellOrdersResponse { sell_orders })
+}
+
+fn to_sell_order_info(deps: Deps, sell_order: &SellOrder) -> StdResult<SellOrderInfo> {
+    let batch = BATCHES.load(deps.storage, sell_order.batch_key)?;
+    let market = MARKETS.load(deps.storage, sell_order.market_id)?;
+    Ok(SellOrderInfo::new(
+        sell_order,
+        batch.denom,
+        market.bank_denom,
+    ))
+}
+
 pub fn allowed_denoms(
     deps: Deps,
     start_after: Option<String>,
@@ -90,7 +181,561 @@ pub fn allowed_denoms(
         .map(|item| item.map(|(_, allowed_denom)| allowed_denom))
         .collect();
 
-    to_binary(&AllowedDenomsResponse {
+    to_json_binary(&AllowedDenomsResponse {
         allowed_denoms: allowed_denoms?,
     })
 }
+
+pub fn config(deps: Deps) -> StdResult<Binary> {
+    let config = CONFIG.load(deps.storage)?;
+    to_json_binary(&ConfigResponse { config })
+}
+
+pub fn pauses(deps: Deps) -> StdResult<Binary> {
+    let pauses: StdResult<Vec<PauseScope>> = PAUSES
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, scope)| scope))
+        .collect();
+
+    to_json_binary(&PausesResponse { pauses: pauses? })
+}
+
+pub fn signed_order(deps: Deps, seller: String, nonce: u64) -> StdResult<Binary> {
+    let seller = deps.api.addr_validate(&seller)?;
+    let status = SIGNED_ORDERS
+        .may_load(deps.storage, (seller, nonce))?
+        .unwrap_or_default();
+    to_json_binary(&SignedOrderResponse { status })
+}
+
+pub fn signed_order_sign_bytes(order: SignedSellOrder) -> StdResult<Binary> {
+    to_json_binary(&SignedOrderSignBytesResponse {
+        sign_bytes: signing::sign_bytes(&order)?.into(),
+    })
+}
+
+pub fn trade(deps: Deps, trade_id: u64) -> StdResult<Binary> {
+    let trade = TRADES.load(deps.storage, trade_id)?;
+    to_json_binary(&TradeResponse { trade })
+}
+
+pub fn trades(deps: Deps, start_after: Option<u64>, limit: Option<u32>) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let trades: StdResult<Vec<Trade>> = TRADES
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, trade)| trade))
+        .collect();
+
+    to_json_binary(&TradesResponse { trades: trades? })
+}
+
+pub fn trades_by_beneficiary(
+    deps: Deps,
+    beneficiary: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let beneficiary = deps.api.addr_validate(&beneficiary)?;
+
+    let trades: StdResult<Vec<Trade>> = TRADES
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter(|r| match r {
+            Ok((_, trade)) => trade.beneficiary == beneficiary,
+            Err(_) => true,
+        })
+        .take(limit)
+        .map(|item| item.map(|(_, trade)| trade))
+        .collect();
+
+    to_json_binary(&TradesResponse { trades: trades? })
+}
+
+pub fn purchase_allowance(deps: Deps, owner: String, spender: String) -> StdResult<Binary> {
+    let owner = deps.api.addr_validate(&owner)?;
+    let spender = deps.api.addr_validate(&spender)?;
+    let allowance = PURCHASE_ALLOWANCES.may_load(deps.storage, (owner, spender))?;
+    to_json_binary(&PurchaseAllowanceResponse { allowance })
+}
+
+pub fn balance(deps: Deps, address: String) -> StdResult<Binary> {
+    let address = deps.api.addr_validate(&address)?;
+
+    let balances: StdResult<Vec<Coin>> = BALANCES
+        .prefix(address)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| Coin { denom, amount }))
+        .collect();
+
+    to_json_binary(&BalanceResponse {
+        balances: balances?,
+    })
+}
+
+pub fn balances(deps: Deps, start_after: Option<String>, limit: Option<u32>) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .map(|address| deps.api.addr_validate(&address))
+        .transpose()?
+        .map(PrefixBound::exclusive);
+
+    let mut accounts: Vec<AccountBalances> = vec![];
+    for item in BALANCES.prefix_range(deps.storage, start, None, Order::Ascending) {
+        let ((address, denom), amount) = item?;
+        let coin = Coin { denom, amount };
+        if let Some(account) = accounts.last_mut() {
+            if account.address == address {
+                account.balances.push(coin);
+                continue;
+            }
+        }
+        if accounts.len() == limit {
+            break;
+        }
+        accounts.push(AccountBalances {
+            address,
+            balances: vec![coin],
+        });
+    }
+
+    to_json_binary(&BalancesResponse { accounts })
+}
+
+pub fn offer(deps: Deps, offer_id: u64) -> StdResult<Binary> {
+    let offer = OFFERS.load(deps.storage, offer_id)?;
+    to_json_binary(&OfferResponse { offer })
+}
+
+pub fn offers_for_order(
+    deps: Deps,
+    sell_order_id: u64,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    offers_where(deps, start_after, limit, |offer| {
+        offer.sell_order_id == sell_order_id
+    })
+}
+
+pub fn offers_by_buyer(
+    deps: Deps,
+    buyer: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let buyer = deps.api.addr_validate(&buyer)?;
+    offers_where(deps, start_after, limit, |offer| offer.buyer == buyer)
+}
+
+fn offers_where(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+    include: impl Fn(&Offer) -> bool,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let offers: StdResult<Vec<Offer>> = OFFERS
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter(|r| match r {
+            Ok((_, offer)) => include(offer),
+            Err(_) => true,
+        })
+        .take(limit)
+        .map(|item| item.map(|(_, offer)| offer))
+        .collect();
+
+    to_json_binary(&OffersResponse { offers: offers? })
+}
+
+pub fn collection_bid(deps: Deps, bid_id: u64) -> StdResult<Binary> {
+    let bid = COLLECTION_BIDS.load(deps.storage, bid_id)?;
+    to_json_binary(&CollectionBidResponse { bid })
+}
+
+pub fn collection_bids(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let bids: StdResult<Vec<CollectionBid>> = COLLECTION_BIDS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, bid)| bid))
+        .collect();
+
+    to_json_binary(&CollectionBidsResponse { bids: bids? })
+}
+
+pub fn bundle(deps: Deps, bundle_id: u64) -> StdResult<Binary> {
+    let bundle = BUNDLES.load(deps.storage, bundle_id)?;
+    to_json_binary(&BundleResponse { bundle })
+}
+
+pub fn bundles(deps: Deps, start_after: Option<u64>, limit: Option<u32>) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let bundles: StdResult<Vec<Bundle>> = BUNDLES
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, bundle)| bundle))
+        .collect();
+
+    to_json_binary(&BundlesResponse { bundles: bundles? })
+}
+
+pub fn bundles_by_seller(
+    deps: Deps,
+    seller: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let seller = deps.api.addr_validate(&seller)?;
+
+    let bundles: StdResult<Vec<Bundle>> = BUNDLES
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter(|r| match r {
+            Ok((_, bundle)) => bundle.seller == seller,
+            Err(_) => true,
+        })
+        .take(limit)
+        .map(|item| item.map(|(_, bundle)| bundle))
+        .collect();
+
+    to_json_binary(&BundlesResponse { bundles: bundles? })
+}
+
+pub fn sell_order_at_height(deps: Deps, sell_order_id: u64, height: u64) -> StdResult<Binary> {
+    let sell_order = SELL_ORDERS.may_load_at_height(deps.storage, sell_order_id, height)?;
+    to_json_binary(&SellOrderAtHeightResponse { sell_order })
+}
+
+/// Orders removed since `height` are only in the changelog, so ids are
+/// walked up to the current sequence rather than ranged over, at most
+/// `MAX_HISTORY_SCAN` of them per call.
+pub fn sell_orders_by_batch_at_height(
+    deps: Deps,
+    batch_denom: String,
+    height: u64,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let Some(batch_key) = BATCH_KEYS.may_load(deps.storage, batch_denom)? else {
+        return to_json_binary(&SellOrdersAtHeightResponse {
+            sell_orders: vec![],
+            next_start_after: None,
+        });
+    };
+    let last_id = SELL_ORDER_SEQ.may_load(deps.storage)?.unwrap_or_default();
+
+    let first = start_after.map_or(1, |id| id + 1);
+    let mut sell_orders = vec![];
+    let mut next_start_after = None;
+    for id in first..=last_id {
+        if sell_orders.len() == limit || id - first == MAX_HISTORY_SCAN {
+            next_start_after = Some(id - 1);
+            break;
+        }
+        match SELL_ORDERS.may_load_at_height(deps.storage, id, height)? {
+            Some(sell_order) if sell_order.batch_key == batch_key => sell_orders.push(sell_order),
+            _ => {}
+        }
+    }
+
+    to_json_binary(&SellOrdersAtHeightResponse {
+        sell_orders,
+        next_start_after,
+    })
+}
+
+pub fn fee_params_at_height(deps: Deps, height: u64) -> StdResult<Binary> {
+    let fee_params = FEE_PARAMS.may_load_at_height(deps.storage, height)?;
+    to_json_binary(&FeeParamsAtHeightResponse { fee_params })
+}
+
+pub fn hooks(deps: Deps) -> StdResult<Binary> {
+    let hooks: StdResult<Vec<Hook>> = HOOKS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, hook)| hook))
+        .collect();
+    to_json_binary(&HooksResponse { hooks: hooks? })
+}
+
+pub fn credit_tokens(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let tokens: StdResult<Vec<CreditToken>> = CREDIT_TOKENS
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| item.map(|(_, token)| token))
+        .collect();
+    to_json_binary(&CreditTokensResponse { tokens: tokens? })
+}
+
+pub fn failed_settlements(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let settlements: StdResult<Vec<Settlement>> = SETTLEMENTS
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive),
+            None,
+            Order::Ascending,
+        )
+        .filter(|item| !matches!(item, Ok((_, settlement)) if settlement.error.is_none()))
+        .take(limit)
+        .map(|item| item.map(|(_, settlement)| settlement))
+        .collect();
+    to_json_binary(&SettlementsResponse {
+        settlements: settlements?,
+    })
+}
+
+pub fn export_state(
+    deps: Deps,
+    section: StateSection,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Binary> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let id_start = || -> StdResult<Option<Bound<u64>>> {
+        start_after
+            .as_deref()
+            .map(|id| {
+                id.parse::<u64>()
+                    .map(Bound::exclusive)
+                    .map_err(|_| StdError::generic_err(format!("invalid start_after {}", id)))
+            })
+            .transpose()
+    };
+    let denom_start = || start_after.clone().map(Bound::exclusive);
+    // Both parts of a pair key. The first is an address, so it holds no `/`.
+    let pair_start = || -> StdResult<Option<(String, String)>> {
+        start_after
+            .as_deref()
+            .map(|key| {
+                key.split_once('/')
+                    .map(|(first, second)| (first.to_string(), second.to_string()))
+                    .ok_or_else(|| StdError::generic_err(format!("invalid start_after {}", key)))
+            })
+            .transpose()
+    };
+
+    let (records, next) = match section {
+        StateSection::Config => (StateRecords::Config(CONFIG.load(deps.storage)?), None),
+        StateSection::FeeParams => (
+            StateRecords::FeeParams(FEE_PARAMS.load(deps.storage)?),
+            None,
+        ),
+        StateSection::Sequences => {
+            let seq = |seq: &Item<u64>| seq.may_load(deps.storage).map(Option::unwrap_or_default);
+            let sequences = Sequences {
+                sell_order: seq(&SELL_ORDER_SEQ)?,
+                batch: seq(&BATCH_SEQ)?,
+                market: seq(&MARKET_SEQ)?,
+                trade: seq(&TRADE_SEQ)?,
+                offer: seq(&OFFER_SEQ)?,
+                collection_bid: seq(&COLLECTION_BID_SEQ)?,
+                bundle: seq(&BUNDLE_SEQ)?,
+                hook: seq(&HOOK_SEQ)?,
+            };
+            (StateRecords::Sequences(sequences), None)
+        }
+        StateSection::FeePool => {
+            let range = FEE_POOL.range(deps.storage, denom_start(), None, Order::Ascending);
+            let (coins, next) = export_page(range, limit)?;
+            let coins = coins
+                .into_iter()
+                .map(|(denom, amount)| Coin { denom, amount })
+                .collect();
+            (StateRecords::FeePool(coins), next)
+        }
+        StateSection::AllowedDenoms => {
+            let range = ALLOWED_DENOMS.range(deps.storage, denom_start(), None, Order::Ascending);
+            let (denoms, next) = export_page(range, limit)?;
+            (StateRecords::AllowedDenoms(values(denoms)), next)
+        }
+        StateSection::Batches => {
+            let range = BATCHES.range(deps.storage, id_start()?, None, Order::Ascending);
+            let (batches, next) = export_page(range, limit)?;
+            (StateRecords::Batches(values(batches)), next)
+        }
+        StateSection::Markets => {
+            let range = MARKETS.range(deps.storage, id_start()?, None, Order::Ascending);
+            let (markets, next) = export_page(range, limit)?;
+            (StateRecords::Markets(values(markets)), next)
+        }
+        StateSection::SellOrders => {
+            let range = SELL_ORDERS.range(deps.storage, id_start()?, None, Order::Ascending);
+            let (sell_orders, next) = export_page(range, limit)?;
+            (StateRecords::SellOrders(values(sell_orders)), next)
+        }
+        StateSection::Balances => {
+            let start = pair_start()?
+                .map(|(account, denom)| Bound::exclusive((Addr::unchecked(account), denom)));
+            let range = BALANCES
+                .range(deps.storage, start, None, Order::Ascending)
+                .map(|item| {
+                    item.map(|((account, denom), amount)| {
+                        (
+                            format!("{}/{}", account, denom),
+                            BalanceRecord {
+                                account,
+                                balance: Coin { denom, amount },
+                            },
+                        )
+                    })
+                });
+            let (balances, next) = export_page(range, limit)?;
+            (StateRecords::Balances(values(balances)), next)
+        }
+        StateSection::Offers => {
+            let range = OFFERS.range(deps.storage, id_start()?, None, Order::Ascending);
+            let (offers, next) = export_page(range, limit)?;
+            (StateRecords::Offers(values(offers)), next)
+        }
+        StateSection::CollectionBids => {
+            let range = COLLECTION_BIDS.range(deps.storage, id_start()?, None, Order::Ascending);
+            let (bids, next) = export_page(range, limit)?;
+            (StateRecords::CollectionBids(values(bids)), next)
+        }
+        StateSection::Bundles => {
+            let range = BUNDLES.range(deps.storage, id_start()?, None, Order::Ascending);
+            let (bundles, next) = export_page(range, limit)?;
+            (StateRecords::Bundles(values(bundles)), next)
+        }
+        StateSection::Trades => {
+            let range = TRADES.range(deps.storage, id_start()?, None, Order::Ascending);
+            let (trades, next) = export_page(range, limit)?;
+            (StateRecords::Trades(values(trades)), next)
+        }
+        StateSection::PurchaseAllowances => {
+            let start = pair_start()?.map(|(owner, spender)| {
+                Bound::exclusive((Addr::unchecked(owner), Addr::unchecked(spender)))
+            });
+            let range = PURCHASE_ALLOWANCES
+                .range(deps.storage, start, None, Order::Ascending)
+                .map(|item| {
+                    item.map(|((owner, spender), allowance)| {
+                        (
+                            format!("{}/{}", owner, spender),
+                            PurchaseAllowanceRecord {
+                                owner,
+                                spender,
+                                allowance,
+                            },
+                        )
+                    })
+                });
+            let (allowances, next) = export_page(range, limit)?;
+            (StateRecords::PurchaseAllowances(values(allowances)), next)
+        }
+        StateSection::SignedOrders => {
+            let start = pair_start()?
+                .map(|(seller, nonce)| {
+                    nonce
+                        .parse::<u64>()
+                        .map(|nonce| Bound::exclusive((Addr::unchecked(seller), nonce)))
+                        .map_err(|_| StdError::generic_err(format!("invalid nonce {}", nonce)))
+                })
+                .transpose()?;
+            let range = SIGNED_ORDERS
+                .range(deps.storage, start, None, Order::Ascending)
+                .map(|item| {
+                    item.map(|((seller, nonce), status)| {
+                        (
+                            format!("{}/{}", seller, nonce),
+                            SignedOrderRecord {
+                                seller,
+                                nonce,
+                                status,
+                            },
+                        )
+                    })
+                });
+            let (signed_orders, next) = export_page(range, limit)?;
+            (StateRecords::SignedOrders(values(signed_orders)), next)
+        }
+        StateSection::Pauses => {
+            let range = PAUSES.range(deps.storage, denom_start(), None, Order::Ascending);
+            let (pauses, next) = export_page(range, limit)?;
+            (StateRecords::Pauses(values(pauses)), next)
+        }
+        StateSection::Hooks => {
+            let range = HOOKS.range(deps.storage, id_start()?, None, Order::Ascending);
+            let (hooks, next) = export_page(range, limit)?;
+            (StateRecords::Hooks(values(hooks)), next)
+        }
+        StateSection::CreditTokens => {
+            let range = CREDIT_TOKENS.range(deps.storage, denom_start(), None, Order::Ascending);
+            let (tokens, next) = export_page(range, limit)?;
+            (StateRecords::CreditTokens(values(tokens)), next)
+        }
+        StateSection::Settlements => {
+            let range = SETTLEMENTS.range(deps.storage, id_start()?, None, Order::Ascending);
+            let (settlements, next) = export_page(range, limit)?;
+            (StateRecords::Settlements(values(settlements)), next)
+        }
+        StateSection::CrankCursor => (
+            StateRecords::CrankCursor(CRANK_CURSOR.may_load(deps.storage)?.unwrap_or_default()),
+            None,
+        ),
+        StateSection::ContractVersion => (
+            StateRecords::ContractVersion(cw2::get_contract_version(deps.storage)?),
+            None,
+        ),
+        StateSection::GenesisImport => (
+            StateRecords::GenesisImport(GENESIS_IMPORT.may_load(deps.storage)?.unwrap_or(false)),
+            None,
+        ),
+    };
+
+    to_json_binary(&ExportStateResponse {
+        version: STATE_EXPORT_VERSION,
+        records,
+        next,
+    })
+}
+
+/// Up to `limit` entries, and the key to continue from when more remain.
+type Page<K, T> = (Vec<(K, T)>, Option<String>);
+
+fn export_page<K: ToString, T>(
+    range: impl Iterator<Item = StdResult<(K, T)>>,
+    limit: usize,
+) -> StdResult<Page<K, T>> {
+    let mut entries = range.take(limit + 1).collect::<StdResult<Vec<_>>>()?;
+    if entries.len() <= limit {
+        return Ok((entries, None));
+    }
+    entries.truncate(limit);
+    let next = entries.last().map(|(key, _)| key.to_string());
+    Ok((entries, next))
+}
+
+fn values<K, T>(entries: Vec<(K, T)>) -> Vec<T> {
+    entries.into_iter().map(|(_, value)| value).collect()
+}
@@ -0,0 +1,41 @@
+//! Ids of the submessages the marketplace asks replies for. The low byte is
+//! the kind of call and the rest is the id of the record it concerns.
+
+use crate::error::ContractError;
+
+const HOOK: u64 = 1;
+const DELIVERY: u64 = 2;
+const RETIREMENT: u64 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplyId {
+    /// An optional hook call, by hook id.
+    Hook { hook_id: u64 },
+    /// Delivery of the tradable credits of a trade, by trade id.
+    Delivery { trade_id: u64 },
+    /// Delivery of credits retired in the buyer's name, by trade id.
+    Retirement { trade_id: u64 },
+}
+
+impl From<ReplyId> for u64 {
+    fn from(id: ReplyId) -> u64 {
+        match id {
+            ReplyId::Hook { hook_id } => (hook_id << 8) | HOOK,
+            ReplyId::Delivery { trade_id } => (trade_id << 8) | DELIVERY,
+            ReplyId::Retirement { trade_id } => (trade_id << 8) | RETIREMENT,
+        }
+    }
+}
+
+impl TryFrom<u64> for ReplyId {
+    type Error = ContractError;
+
+    fn try_from(id: u64) -> Result<Self, Self::Error> {
+        match id & 0xff {
+            HOOK => Ok(ReplyId::Hook { hook_id: id >> 8 }),
+            DELIVERY => Ok(ReplyId::Delivery { trade_id: id >> 8 }),
+            RETIREMENT => Ok(ReplyId::Retirement { trade_id: id >> 8 }),
+            _ => Err(ContractError::UnknownReplyId { id }),
+        }
+    }
+}
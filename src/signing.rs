@@ -0,0 +1,61 @@
+use bech32::{ToBase32, Variant};
+use cosmwasm_std::{to_json_vec, Addr, Api, Binary, StdResult};
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+
+use crate::error::ContractError;
+use crate::msg::SignedSellOrder;
+
+/// Bytes a seller signs for an off-chain order: the canonical JSON of the
+/// order, with fields in declaration order and no whitespace. The signature
+/// covers the sha256 hash of these bytes.
+pub fn sign_bytes(order: &SignedSellOrder) -> StdResult<Vec<u8>> {
+    to_json_vec(order)
+}
+
+/// Verifies that `signature` over `order` was made with `pubkey`, and that
+/// `pubkey` belongs to the seller named in the order. Returns the seller.
+pub fn verify_signed_order(
+    api: &dyn Api,
+    order: &SignedSellOrder,
+    signature: &Binary,
+    pubkey: &Binary,
+) -> Result<Addr, ContractError> {
+    let hash = Sha256::digest(sign_bytes(order)?);
+    let verified = api
+        .secp256k1_verify(&hash, signature, pubkey)
+        .map_err(|err| ContractError::InvalidSignedOrder {
+            reason: err.to_string(),
+        })?;
+    if !verified {
+        return Err(ContractError::InvalidSignedOrder {
+            reason: "signature does not match".to_string(),
+        });
+    }
+
+    let seller = api.addr_validate(&order.seller)?;
+    if derive_address(&order.seller, pubkey)? != order.seller {
+        return Err(ContractError::InvalidSignedOrder {
+            reason: "public key does not belong to the seller".to_string(),
+        });
+    }
+    Ok(seller)
+}
+
+/// Derives the Cosmos SDK account address of a compressed secp256k1 public
+/// key, `bech32(ripemd160(sha256(pubkey)))`, using the prefix of `like`.
+fn derive_address(like: &str, pubkey: &[u8]) -> Result<String, ContractError> {
+    if pubkey.len() != 33 {
+        return Err(ContractError::InvalidSignedOrder {
+            reason: "public key must be a 33 byte compressed secp256k1 key".to_string(),
+        });
+    }
+
+    let invalid_seller = |err: bech32::Error| ContractError::InvalidSignedOrder {
+        reason: format!("seller is not a bech32 address: {}", err),
+    };
+    let (prefix, _, _) = bech32::decode(like).map_err(invalid_seller)?;
+
+    let hash = Ripemd160::digest(Sha256::digest(pubkey));
+    bech32::encode(&prefix, hash.to_base32(), Variant::Bech32).map_err(invalid_seller)
+}
@@ -1,25 +1,133 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Timestamp};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Addr, Coin, Decimal, HexBinary, Timestamp, Uint128};
+use cw_storage_plus::{Item, Map, SnapshotItem, SnapshotMap, Strategy};
+
+#[cw_serde]
+pub struct Config {
+    /// Account allowed to pause and unpause the marketplace.
+    pub authority: Addr,
+    #[serde(default)]
+    pub ecocredit: EcocreditAdapter,
+    /// Share of the fee pool, in the denom of the funds refunded, that a
+    /// `Crank` may pay its sender. Each expired offer or collection bid it
+    /// removes earns one `MAX_CRANK_LIMIT`th of that share.
+    #[serde(default)]
+    pub crank_reward_share: Decimal,
+}
+
+/// How native credits change hands when orders are filled.
+#[cw_serde]
+#[derive(Default)]
+pub enum EcocreditAdapter {
+    /// Credits are settled outside the contract, from its trades.
+    #[default]
+    Disabled,
+    /// Credits are sent through the Regen ecocredit module.
+    Regen,
+}
 
 #[cw_serde]
 pub struct SellOrder {
     pub id: u64,
     pub seller: Addr,
     pub batch_key: u64,
-    pub quantity: String,
+    pub quantity: Uint128,
     pub market_id: u64,
-    pub ask_amount: String,
+    pub ask_amount: Uint128,
     pub disable_auto_retire: bool,
     pub expiration: Option<Timestamp>,
     pub maker: bool,
+    /// Starts at 1 and increments on every update, so buyers can pin the
+    /// terms they saw.
+    pub version: u64,
+    /// Restricts a private order to designated buyers. Anyone may buy when
+    /// unset.
+    pub allowed_buyers: Option<AllowedBuyers>,
+    /// Attributes of the batch, matched against collection bids. Derived
+    /// when the order is listed from the batch denom and, with the Regen
+    /// adapter, the ecocredit project. Orders without metadata never fill a
+    /// collection bid.
+    pub batch_metadata: Option<BatchMetadata>,
+    #[serde(default)]
+    pub asset: CreditAsset,
+}
+
+/// Where the credits of a sell order are held.
+#[cw_serde]
+#[derive(Default)]
+pub enum CreditAsset {
+    /// Credits of the ecocredit module, which delivers and retires them.
+    #[default]
+    Native,
+    /// cw20 tokens escrowed by the marketplace. Retired credits are burned.
+    Cw20 { contract: Addr },
+    /// cw1155 tokens escrowed by the marketplace. Retired credits are burned.
+    Cw1155 { contract: Addr, token_id: String },
+}
+
+impl CreditAsset {
+    /// Denom under which escrowed tokens of this asset are received, see
+    /// `cw20_denom` and `cw1155_denom`. None for native credits.
+    pub fn escrow_denom(&self) -> Option<String> {
+        match self {
+            CreditAsset::Native => None,
+            CreditAsset::Cw20 { contract } => Some(cw20_denom(contract)),
+            CreditAsset::Cw1155 { contract, token_id } => Some(cw1155_denom(contract, token_id)),
+        }
+    }
+}
+
+#[cw_serde]
+pub struct BatchMetadata {
+    pub class_id: String,
+    pub project_id: String,
+    pub project_jurisdiction: String,
+    /// Year the batch's crediting period starts.
+    pub vintage_year: u32,
+}
+
+#[cw_serde]
+pub enum AllowedBuyers {
+    Addresses {
+        addresses: Vec<Addr>,
+    },
+    /// Root of a merkle tree whose leaves are the sha256 hashes of the buyer
+    /// addresses. Pairs are hashed in sorted order, so proofs carry no
+    /// directions.
+    MerkleRoot {
+        root: HexBinary,
+    },
 }
 
 #[cw_serde]
 pub struct AllowedDenom {
+    /// Bank denom of a native coin, or `cw20:<contract>` for a cw20 token.
     pub bank_denom: String,
     pub display_denom: String,
     pub exponent: u32,
+    #[serde(default)]
+    pub kind: DenomKind,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub enum DenomKind {
+    #[default]
+    Native,
+    /// Paid through the `Receive` hook and paid out with cw20 transfers.
+    Cw20,
+}
+
+/// Prefix of the denoms standing for cw20 tokens in prices and balances.
+pub const CW20_DENOM_PREFIX: &str = "cw20:";
+
+pub fn cw20_denom(contract_addr: &Addr) -> String {
+    format!("{}{}", CW20_DENOM_PREFIX, contract_addr)
+}
+
+/// Denom standing for escrowed cw1155 tokens. Never a valid price denom.
+pub fn cw1155_denom(contract_addr: &Addr, token_id: &str) -> String {
+    format!("cw1155:{}/{}", contract_addr, token_id)
 }
 
 #[cw_serde]
@@ -30,14 +138,367 @@ pub struct Market {
     pub precision_modifier: u32,
 }
 
+#[cw_serde]
+pub struct Batch {
+    pub key: u64,
+    pub denom: String,
+}
+
+/// Part of the marketplace halted by the authority. Cancelling sell orders is
+/// never halted so sellers can always withdraw.
+#[cw_serde]
+pub enum PauseScope {
+    /// Halts listings, updates and purchases.
+    All,
+    /// Halts new listings and updates to existing ones.
+    Listings,
+    /// Halts purchases.
+    Purchases,
+    /// Halts listings and purchases in one market.
+    Market { market_id: u64 },
+    /// Halts listings and purchases of one credit batch.
+    Batch { batch_denom: String },
+}
+
+impl PauseScope {
+    pub fn key(&self) -> String {
+        match self {
+            PauseScope::All => "all".to_string(),
+            PauseScope::Listings => "listings".to_string(),
+            PauseScope::Purchases => "purchases".to_string(),
+            PauseScope::Market { market_id } => format!("market/{}", market_id),
+            PauseScope::Batch { batch_denom } => format!("batch/{}", batch_denom),
+        }
+    }
+}
+
+/// Fill state of an off-chain signed sell order.
+#[cw_serde]
+#[derive(Default)]
+pub struct SignedOrderStatus {
+    pub filled: Uint128,
+    pub cancelled: bool,
+}
+
+/// Record of a single purchase, kept for buyers, auditors and retirement
+/// reporting.
+#[cw_serde]
+pub struct Trade {
+    pub id: u64,
+    pub source: TradeSource,
+    pub seller: Addr,
+    /// Account that paid for the credits.
+    pub payer: Addr,
+    /// Account that bought on behalf of the payer under a purchase allowance.
+    pub spender: Option<Addr>,
+    /// Account that received the credits, or in whose name they were retired.
+    pub beneficiary: Addr,
+    pub batch_denom: String,
+    pub quantity: Uint128,
+    /// Price per credit paid to the seller, excluding fees. For bundles, the
+    /// price of the whole bundle, shared by the trades of all its batches.
+    pub ask_price: Coin,
+    pub buyer_fee: Coin,
+    /// Set when the credits were retired on purchase.
+    pub retirement: Option<Retirement>,
+    pub timestamp: Timestamp,
+    /// Set when the delivery failed and `RefundSettlement` voided the trade:
+    /// the payer got the payment back and the credits never changed hands.
+    #[serde(default)]
+    pub refunded: bool,
+}
+
+#[cw_serde]
+pub enum TradeSource {
+    SellOrder { sell_order_id: u64 },
+    SignedOrder { nonce: u64 },
+    Bundle { bundle_id: u64 },
+}
+
+#[cw_serde]
+pub struct Retirement {
+    pub jurisdiction: Option<String>,
+    pub reason: Option<String>,
+}
+
+/// Budget an owner grants a spender to buy credits with the owner's
+/// deposited balance.
+#[cw_serde]
+pub struct PurchaseAllowance {
+    pub denom: String,
+    /// Amount left to spend, including buyer fees. Decreases with every
+    /// purchase.
+    pub remaining: Uint128,
+    pub expiration: Option<Timestamp>,
+    /// Batches the spender may buy. Any batch when unset.
+    pub allowed_batches: Option<Vec<String>>,
+}
+
+/// Bid on a sell order at a price of the buyer's choosing. The buyer's funds
+/// are held in escrow until the offer is accepted or rejected.
+#[cw_serde]
+pub struct Offer {
+    pub id: u64,
+    pub sell_order_id: u64,
+    pub buyer: Addr,
+    pub quantity: Uint128,
+    /// Price per credit, excluding fees.
+    pub price: Coin,
+    pub expiration: Option<Timestamp>,
+    /// Party that proposed the current terms. Only the other party can
+    /// accept them.
+    pub proposer: OfferParty,
+    pub escrow: Coin,
+    pub disable_auto_retire: bool,
+    pub merkle_proof: Option<Vec<HexBinary>>,
+    #[serde(default)]
+    pub on_delivery_failure: DeliveryFailure,
+}
+
+#[cw_serde]
+pub enum OfferParty {
+    Buyer,
+    Seller,
+}
+
+/// Standing bid for credits from any batch matching `criteria`, with the
+/// buyer's funds held in escrow until it is filled or cancelled.
+#[cw_serde]
+pub struct CollectionBid {
+    pub id: u64,
+    pub buyer: Addr,
+    pub criteria: CollectionBidCriteria,
+    /// Quantity still wanted. Decreases with every fill.
+    pub quantity: Uint128,
+    /// Price per credit, excluding fees.
+    pub price: Coin,
+    pub escrow: Coin,
+    pub expiration: Option<Timestamp>,
+    pub disable_auto_retire: bool,
+    pub retirement_jurisdiction: Option<String>,
+    pub retirement_reason: Option<String>,
+    #[serde(default)]
+    pub on_delivery_failure: DeliveryFailure,
+}
+
+/// Filters over batch metadata. Unset filters match any batch.
+#[cw_serde]
+pub struct CollectionBidCriteria {
+    pub class_ids: Option<Vec<String>>,
+    pub project_ids: Option<Vec<String>>,
+    pub min_vintage_year: Option<u32>,
+    pub max_vintage_year: Option<u32>,
+    /// Jurisdiction prefixes, so `US` matches `US-WA 98225`. Only known with
+    /// the Regen adapter; bids setting them are rejected without it.
+    pub jurisdictions: Option<Vec<String>>,
+}
+
+impl CollectionBidCriteria {
+    pub fn matches(&self, metadata: &BatchMetadata) -> bool {
+        let listed = |list: &Option<Vec<String>>, value: &String| {
+            list.as_ref().is_none_or(|list| list.contains(value))
+        };
+        listed(&self.class_ids, &metadata.class_id)
+            && listed(&self.project_ids, &metadata.project_id)
+            && self
+                .min_vintage_year
+                .is_none_or(|min| metadata.vintage_year >= min)
+            && self
+                .max_vintage_year
+                .is_none_or(|max| metadata.vintage_year <= max)
+            && self.jurisdictions.as_ref().is_none_or(|jurisdictions| {
+                jurisdictions
+                    .iter()
+                    .any(|prefix| metadata.project_jurisdiction.starts_with(prefix.as_str()))
+            })
+    }
+}
+
+/// Credits from several batches sold together for a single price, all or
+/// nothing.
+#[cw_serde]
+pub struct Bundle {
+    pub id: u64,
+    pub seller: Addr,
+    pub items: Vec<BundleItem>,
+    /// Price of the whole bundle, excluding fees.
+    pub price: Coin,
+    pub expiration: Option<Timestamp>,
+}
+
+#[cw_serde]
+pub struct BundleItem {
+    pub batch_denom: String,
+    pub quantity: Uint128,
+    pub disable_auto_retire: bool,
+}
+
 #[cw_serde]
 pub struct FeeParams {
-    pub buyer_percentage_fee: String,
-    pub seller_percentage_fee: String,
+    pub buyer_percentage_fee: Decimal,
+    pub seller_percentage_fee: Decimal,
 }
 
+pub const CONFIG: Item<Config> = Item::new("config");
 pub const SELL_ORDER_SEQ: Item<u64> = Item::new("sell_order_seq");
-pub const SELL_ORDERS: Map<u64, SellOrder> = Map::new("sell_orders");
+/// Sell orders with a changelog of every change, for queries at past heights.
+pub const SELL_ORDERS: SnapshotMap<u64, SellOrder> = SnapshotMap::new(
+    "sell_orders",
+    "sell_orders__checkpoints",
+    "sell_orders__changelog",
+    Strategy::EveryBlock,
+);
 pub const ALLOWED_DENOMS: Map<String, AllowedDenom> = Map::new("allowed_denoms");
+pub const BATCH_SEQ: Item<u64> = Item::new("batch_seq");
+pub const BATCHES: Map<u64, Batch> = Map::new("batches");
+/// Batch key by batch denom.
+pub const BATCH_KEYS: Map<String, u64> = Map::new("batch_keys");
+pub const MARKET_SEQ: Item<u64> = Item::new("market_seq");
 pub const MARKETS: Map<u64, Market> = Map::new("markets");
-pub const FEE_PARAMS: Item<FeeParams> = Item::new("fee_params");
+/// Market id by credit type abbreviation and bank denom.
+pub const MARKET_IDS: Map<(String, String), u64> = Map::new("market_ids");
+pub const PAUSES: Map<String, PauseScope> = Map::new("pauses");
+/// Signed order status by seller and nonce.
+pub const SIGNED_ORDERS: Map<(Addr, u64), SignedOrderStatus> = Map::new("signed_orders");
+pub const TRADE_SEQ: Item<u64> = Item::new("trade_seq");
+pub const TRADES: Map<u64, Trade> = Map::new("trades");
+/// Deposited balance by account and denom.
+pub const BALANCES: Map<(Addr, String), Uint128> = Map::new("balances");
+/// Purchase allowance by owner and spender.
+pub const PURCHASE_ALLOWANCES: Map<(Addr, Addr), PurchaseAllowance> =
+    Map::new("purchase_allowances");
+pub const OFFER_SEQ: Item<u64> = Item::new("offer_seq");
+pub const OFFERS: Map<u64, Offer> = Map::new("offers");
+pub const COLLECTION_BID_SEQ: Item<u64> = Item::new("collection_bid_seq");
+pub const COLLECTION_BIDS: Map<u64, CollectionBid> = Map::new("collection_bids");
+pub const BUNDLE_SEQ: Item<u64> = Item::new("bundle_seq");
+pub const BUNDLES: Map<u64, Bundle> = Map::new("bundles");
+/// Fee params with a changelog of every change, for queries at past heights.
+pub const FEE_PARAMS: SnapshotItem<FeeParams> = SnapshotItem::new(
+    "fee_params",
+    "fee_params__checkpoints",
+    "fee_params__changelog",
+    Strategy::EveryBlock,
+);
+/// Buyer and seller fees collected, by denom.
+pub const FEE_POOL: Map<String, Uint128> = Map::new("fee_pool");
+/// Set while a genesis export is being imported; trading is closed until the
+/// import is finalized.
+pub const GENESIS_IMPORT: Item<bool> = Item::new("genesis_import");
+
+/// Where the next `Crank` resumes its scan. Stages run in the order below and
+/// wrap around.
+#[cw_serde]
+pub enum CrankCursor {
+    SellOrders { start_after: Option<u64> },
+    Offers { start_after: Option<u64> },
+    CollectionBids { start_after: Option<u64> },
+    Bundles { start_after: Option<u64> },
+    PurchaseAllowances { start_after: Option<(Addr, Addr)> },
+}
+
+impl CrankCursor {
+    pub const STAGES: usize = 5;
+}
+
+impl Default for CrankCursor {
+    fn default() -> Self {
+        CrankCursor::SellOrders { start_after: None }
+    }
+}
+
+pub const CRANK_CURSOR: Item<CrankCursor> = Item::new("crank_cursor");
+
+/// Marketplace event an external contract can subscribe to.
+#[cw_serde]
+pub enum HookEvent {
+    OrderCreated,
+    OrderFilled,
+    OrderCancelled,
+    CreditsRetired,
+}
+
+impl HookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HookEvent::OrderCreated => "order_created",
+            HookEvent::OrderFilled => "order_filled",
+            HookEvent::OrderCancelled => "order_cancelled",
+            HookEvent::CreditsRetired => "credits_retired",
+        }
+    }
+}
+
+/// Contract called on every `event`. A failing call is logged and ignored,
+/// unless the hook is `required`, in which case it fails the transaction.
+#[cw_serde]
+pub struct Hook {
+    pub id: u64,
+    pub event: HookEvent,
+    pub contract: Addr,
+    pub required: bool,
+}
+
+/// cw20 or cw1155 token the authority registered as holding credits of a
+/// batch. Only registered tokens can be listed.
+#[cw_serde]
+pub struct CreditToken {
+    pub batch_denom: String,
+    pub asset: CreditAsset,
+    /// The marketplace holds native credits of the batch backing the token
+    /// one to one, and retires them with `MsgRetire` when it burns retired
+    /// tokens.
+    #[serde(default)]
+    pub custodial: bool,
+}
+
+/// Registered credit tokens by escrow denom, see `CreditAsset::escrow_denom`.
+pub const CREDIT_TOKENS: Map<String, CreditToken> = Map::new("credit_tokens");
+
+pub const HOOK_SEQ: Item<u64> = Item::new("hook_seq");
+pub const HOOKS: Map<u64, Hook> = Map::new("hooks");
+
+/// What happens when the credits of a fill cannot be delivered.
+#[cw_serde]
+#[derive(Copy, Default)]
+pub enum DeliveryFailure {
+    /// The fill is rolled back with the rest of the transaction.
+    #[default]
+    Revert,
+    /// The trade stands and the delivery waits for `RetrySettlement`, or for
+    /// the payer to take their payment back with `RefundSettlement`.
+    Retry,
+}
+
+/// Credit delivery of a trade sent as a submessage. Removed once delivered;
+/// `error` is set while a failed delivery awaits a retry.
+#[cw_serde]
+pub struct Settlement {
+    pub trade_id: u64,
+    pub asset: CreditAsset,
+    pub on_failure: DeliveryFailure,
+    pub error: Option<String>,
+    /// Payment of a `Retry` trade, held until the credits are delivered.
+    #[serde(default)]
+    pub held: Option<HeldPayment>,
+    /// Retires the native credits backing a custodial token once its burn
+    /// succeeded.
+    #[serde(default)]
+    pub retire_custody: bool,
+}
+
+/// Buyer's payment for a trade awaiting delivery. Once the credits are
+/// delivered `proceeds` go to the seller and the rest to the fee pool; a
+/// refund returns all of `cost` to the payer.
+#[cw_serde]
+pub struct HeldPayment {
+    pub cost: Coin,
+    pub proceeds: Coin,
+    /// Paid from the payer's deposited balance. Proceeds and refunds are then
+    /// credited to balances instead of sent.
+    #[serde(default)]
+    pub from_balance: bool,
+}
+
+/// Settlements by trade id.
+pub const SETTLEMENTS: Map<u64, Settlement> = Map::new("settlements");
@@ -1,29 +1,423 @@
-use cosmwasm_std::{Addr, Coin, Uint128};
-use cw_multi_test::{App, ContractWrapper, Executor};
+use cosmwasm_std::testing::{mock_dependencies, mock_env, MockApi, MockStorage};
+use cosmwasm_std::{
+    from_json, to_json_binary, to_json_string, Addr, Api, Binary, Coin, Decimal, Empty, Event,
+    Timestamp, Uint128,
+};
+use cw2::ContractVersion;
+use cw20::Cw20Coin;
+use cw_multi_test::{
+    no_init, AppBuilder, AppResponse, BankKeeper, ContractWrapper, DistributionKeeper, Executor,
+    FailingModule, GovFailingModule, IbcFailingModule, StakeKeeper, WasmKeeper,
+};
 
-use crate::contract::{execute, instantiate, query};
+use crate::contract::{
+    execute, instantiate, migrate, query, reply, CONTRACT_NAME, CONTRACT_VERSION,
+};
+use crate::cw1155::{Cw1155BatchReceiveMsg, Cw1155ExecuteMsg};
+use crate::error::ContractError;
+use crate::migrations::{self, v0_1, v0_3};
 use crate::msg::{
-    ExecuteMsg, InstantiateMsg, QueryMsg, SellOrderMsg, SellOrderResponse, SellOrdersResponse,
+    AllowedBuyersMsg, BalanceRecord, BalanceResponse, BalancesResponse, BundleResponse,
+    BundleRetirementMsg, BundlesResponse, BuyOrderMsg, CollectionBidResponse,
+    CollectionBidsResponse, CreditAssetMsg, CreditTokensResponse, ExecuteMsg, ExportStateResponse,
+    FeeParamsAtHeightResponse, HooksResponse, InstantiateMsg, MigrateMsg, OfferResponse,
+    OffersResponse, PausesResponse, PurchaseAllowanceResponse, QueryMsg, SellOrderAtHeightResponse,
+    SellOrderMsg, SellOrderResponse, SellOrdersAtHeightResponse, SellOrdersResponse,
+    SettlementsResponse, SignedOrderBuyMsg, SignedOrderResponse, SignedSellOrder, StateDump,
+    StateRecords, StateSection, TradeResponse, TradesResponse, STATE_EXPORT_VERSION,
+};
+use crate::proto::{
+    format_rfc3339, parse_rfc3339, MarketplaceMsg, MsgSellOrder, QuerySellOrderResponse,
+    QuerySellOrdersResponse,
 };
-use crate::state::FeeParams;
+use crate::query::MAX_HISTORY_SCAN;
+use crate::state::{
+    BatchMetadata, BundleItem, CollectionBidCriteria, Config, CreditAsset, DeliveryFailure,
+    DenomKind, EcocreditAdapter, FeeParams, HeldPayment, HookEvent, PauseScope, Retirement,
+    TradeSource, ALLOWED_DENOMS, CONFIG, FEE_PARAMS, SELL_ORDERS,
+};
+
+/// Every test app runs with [`mock_ecocredit::MockEcocredit`] standing in for
+/// the Regen chain.
+type App = cw_multi_test::App<
+    BankKeeper,
+    MockApi,
+    MockStorage,
+    FailingModule<Empty, Empty, Empty>,
+    WasmKeeper<Empty, Empty>,
+    StakeKeeper,
+    DistributionKeeper,
+    IbcFailingModule,
+    GovFailingModule,
+    mock_ecocredit::MockEcocredit,
+>;
+
+/// Stargate keeper implementing the parts of `regen.ecocredit.v1` and
+/// `cosmos.authz.v1beta1` the marketplace uses.
+mod mock_ecocredit {
+    use cosmwasm_std::{Addr, Api, Binary, BlockInfo, CustomQuery, Querier, Storage, Uint128};
+    use cw_multi_test::error::{bail, AnyResult};
+    use cw_multi_test::{AppResponse, CosmosRouter, Stargate};
+    use cw_storage_plus::Map;
+    use prost::Message;
+    use schemars::JsonSchema;
+    use serde::de::DeserializeOwned;
+
+    use crate::ecocredit::{
+        BatchBalanceInfo, BatchInfo, MsgExec, MsgRetire, MsgSend, ProjectInfo, QueryBalanceRequest,
+        QueryBalanceResponse, QueryBatchRequest, QueryBatchResponse, QueryProjectRequest,
+        QueryProjectResponse, BALANCE_QUERY_PATH, BATCH_QUERY_PATH, MSG_EXEC_TYPE_URL,
+        MSG_RETIRE_TYPE_URL, MSG_SEND_TYPE_URL, PROJECT_QUERY_PATH,
+    };
+
+    pub const BATCHES: Map<&str, ()> = Map::new("regen_batches");
+    /// Tradable and retired credits per owner and batch.
+    pub const BALANCES: Map<(&str, &str), (Uint128, Uint128)> = Map::new("regen_balances");
+    /// Jurisdiction per project. Unlisted projects have none.
+    pub const PROJECTS: Map<&str, String> = Map::new("regen_projects");
+    /// `MsgSend` authorizations per granter and grantee.
+    pub const GRANTS: Map<(&str, &str), ()> = Map::new("regen_grants");
+
+    pub struct MockEcocredit;
+
+    fn amount(amount: &str) -> AnyResult<Uint128> {
+        if amount.is_empty() {
+            return Ok(Uint128::zero());
+        }
+        Ok(amount.parse()?)
+    }
+
+    fn send(storage: &mut dyn Storage, msg: MsgSend) -> AnyResult<()> {
+        for credits in msg.credits {
+            let tradable = amount(&credits.tradable_amount)?;
+            let retired = amount(&credits.retired_amount)?;
+            if !retired.is_zero() && credits.retirement_jurisdiction.is_empty() {
+                bail!("retirement jurisdiction required");
+            }
+            let key = (msg.sender.as_str(), credits.batch_denom.as_str());
+            let (held, held_retired) = BALANCES.may_load(storage, key)?.unwrap_or_default();
+            BALANCES.save(
+                storage,
+                key,
+                &(held.checked_sub(tradable + retired)?, held_retired),
+            )?;
+            let key = (msg.recipient.as_str(), credits.batch_denom.as_str());
+            let (held, held_retired) = BALANCES.may_load(storage, key)?.unwrap_or_default();
+            BALANCES.save(storage, key, &(held + tradable, held_retired + retired))?;
+        }
+        Ok(())
+    }
+
+    impl Stargate for MockEcocredit {
+        fn execute<ExecC, QueryC>(
+            &self,
+            _api: &dyn Api,
+            storage: &mut dyn Storage,
+            _router: &dyn CosmosRouter<ExecC = ExecC, QueryC = QueryC>,
+            _block: &BlockInfo,
+            sender: Addr,
+            type_url: String,
+            value: Binary,
+        ) -> AnyResult<AppResponse>
+        where
+            ExecC: std::fmt::Debug + Clone + PartialEq + JsonSchema + DeserializeOwned + 'static,
+            QueryC: CustomQuery + DeserializeOwned + 'static,
+        {
+            match type_url.as_str() {
+                MSG_SEND_TYPE_URL => {
+                    let msg = MsgSend::decode(value.as_slice())?;
+                    if msg.sender != sender.as_str() {
+                        bail!("unauthorized");
+                    }
+                    send(storage, msg)?;
+                }
+                MSG_EXEC_TYPE_URL => {
+                    let exec = MsgExec::decode(value.as_slice())?;
+                    if exec.grantee != sender.as_str() {
+                        bail!("unauthorized");
+                    }
+                    for any in exec.msgs {
+                        if any.type_url != MSG_SEND_TYPE_URL {
+                            bail!("unsupported authz message {}", any.type_url);
+                        }
+                        let msg = MsgSend::decode(any.value.as_slice())?;
+                        if !GRANTS.has(storage, (&msg.sender, &exec.grantee)) {
+                            bail!("authorization not found");
+                        }
+                        send(storage, msg)?;
+                    }
+                }
+                MSG_RETIRE_TYPE_URL => {
+                    let msg = MsgRetire::decode(value.as_slice())?;
+                    for credits in msg.credits {
+                        let retired = amount(&credits.amount)?;
+                        let key = (sender.as_str(), credits.batch_denom.as_str());
+                        let (held, held_retired) =
+                            BALANCES.may_load(storage, key)?.unwrap_or_default();
+                        BALANCES.save(
+                            storage,
+                            key,
+                            &(held.checked_sub(retired)?, held_retired + retired),
+                        )?;
+                    }
+                }
+                _ => bail!("unexpected stargate message {}", type_url),
+            }
+            Ok(AppResponse::default())
+        }
+
+        fn query(
+            &self,
+            _api: &dyn Api,
+            storage: &dyn Storage,
+            _querier: &dyn Querier,
+            _block: &BlockInfo,
+            path: String,
+            data: Binary,
+        ) -> AnyResult<Binary> {
+            let response = match path.as_str() {
+                BATCH_QUERY_PATH => {
+                    let request = QueryBatchRequest::decode(data.as_slice())?;
+                    if !BATCHES.has(storage, &request.batch_denom) {
+                        bail!("batch {} not found", request.batch_denom);
+                    }
+                    QueryBatchResponse {
+                        batch: Some(BatchInfo {
+                            denom: request.batch_denom,
+                            open: true,
+                            ..Default::default()
+                        }),
+                    }
+                    .encode_to_vec()
+                }
+                BALANCE_QUERY_PATH => {
+                    let request = QueryBalanceRequest::decode(data.as_slice())?;
+                    let (tradable, retired) = BALANCES
+                        .may_load(storage, (&request.address, &request.batch_denom))?
+                        .unwrap_or_default();
+                    QueryBalanceResponse {
+                        balance: Some(BatchBalanceInfo {
+                            address: request.address,
+                            batch_denom: request.batch_denom,
+                            tradable_amount: tradable.to_string(),
+                            retired_amount: retired.to_string(),
+                            escrowed_amount: "0".to_string(),
+                        }),
+                    }
+                    .encode_to_vec()
+                }
+                PROJECT_QUERY_PATH => {
+                    let request = QueryProjectRequest::decode(data.as_slice())?;
+                    QueryProjectResponse {
+                        project: Some(ProjectInfo {
+                            jurisdiction: PROJECTS
+                                .may_load(storage, &request.project_id)?
+                                .unwrap_or_default(),
+                            id: request.project_id,
+                        }),
+                    }
+                    .encode_to_vec()
+                }
+                _ => bail!("unexpected stargate query {}", path),
+            };
+            Ok(response.into())
+        }
+    }
+}
 
 fn mock_app() -> App {
-    App::default()
+    AppBuilder::default()
+        .with_stargate(mock_ecocredit::MockEcocredit)
+        .build(no_init)
+}
+
+fn fund(app: &mut App, addr: &Addr, amount: u128) {
+    app.init_modules(|router, _, storage| {
+        router
+            .bank
+            .init_balance(storage, addr, vec![Coin::new(amount, "uusd")])
+            .unwrap()
+    });
 }
 
 fn instantiate_contract(app: &mut App, sender: Addr) -> Addr {
-    let code = ContractWrapper::new(execute, instantiate, query);
+    instantiate_contract_with(app, sender, EcocreditAdapter::Disabled)
+}
+
+fn instantiate_contract_with(app: &mut App, sender: Addr, ecocredit: EcocreditAdapter) -> Addr {
+    let code = ContractWrapper::new(execute, instantiate, query)
+        .with_reply(reply)
+        .with_migrate(migrate);
     let code_id = app.store_code(Box::new(code));
 
     let msg = InstantiateMsg {
         fee_params: FeeParams {
-            buyer_percentage_fee: "0.01".to_string(),
-            seller_percentage_fee: "0.01".to_string(),
+            buyer_percentage_fee: Decimal::percent(1),
+            seller_percentage_fee: Decimal::percent(1),
         },
+        authority: None,
+        ecocredit,
+        import_genesis: false,
+        crank_reward_share: Decimal::zero(),
+        state: None,
     };
 
-    app.instantiate_contract(code_id, sender, &msg, &[], "Regen Marketplace", None)
-        .unwrap()
+    let contract_addr = app
+        .instantiate_contract(
+            code_id,
+            sender.clone(),
+            &msg,
+            &[],
+            "Regen Marketplace",
+            Some(sender.to_string()),
+        )
+        .unwrap();
+
+    app.execute_contract(
+        sender,
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowedDenom {
+            bank_denom: "uusd".to_string(),
+            display_denom: "USD".to_string(),
+            exponent: 6,
+            kind: DenomKind::Native,
+        },
+        &[],
+    )
+    .unwrap();
+
+    contract_addr
+}
+
+fn event<'a>(res: &'a AppResponse, ty: &str) -> &'a Event {
+    res.events
+        .iter()
+        .find(|event| event.ty == ty)
+        .unwrap_or_else(|| panic!("missing event {}", ty))
+}
+
+fn event_attr<'a>(event: &'a Event, key: &str) -> &'a str {
+    event
+        .attributes
+        .iter()
+        .find(|attr| attr.key == key)
+        .map(|attr| attr.value.as_str())
+        .unwrap_or_else(|| panic!("missing attribute {} on {}", key, event.ty))
+}
+
+fn sell_order_msg(batch_denom: &str, quantity: u128, ask_amount: u128) -> SellOrderMsg {
+    SellOrderMsg {
+        batch_denom: batch_denom.to_string(),
+        quantity: Uint128::new(quantity),
+        ask_price: Coin {
+            denom: "uusd".to_string(),
+            amount: Uint128::new(ask_amount),
+        },
+        disable_auto_retire: false,
+        expiration: None,
+        allowed_buyers: None,
+        asset: None,
+    }
+}
+
+fn buy_order_msg(sell_order_id: u64, quantity: u128, bid_amount: u128) -> BuyOrderMsg {
+    BuyOrderMsg {
+        sell_order_id,
+        quantity: Uint128::new(quantity),
+        bid_price: Coin {
+            denom: "uusd".to_string(),
+            amount: Uint128::new(bid_amount),
+        },
+        disable_auto_retire: false,
+        retirement_jurisdiction: None,
+        retirement_reason: None,
+        max_fee_amount: Coin {
+            denom: "uusd".to_string(),
+            amount: Uint128::new(bid_amount),
+        },
+        beneficiary: None,
+        expected_version: None,
+        merkle_proof: None,
+        on_delivery_failure: DeliveryFailure::Revert,
+    }
+}
+
+/// Seller key for off-chain signed orders, with its regen address.
+fn signer(seed: u8) -> (k256::ecdsa::SigningKey, String) {
+    use bech32::ToBase32;
+    use ripemd::Ripemd160;
+    use sha2::{Digest, Sha256};
+
+    let key = k256::ecdsa::SigningKey::from_bytes(&[seed; 32].into()).unwrap();
+    let pubkey = key.verifying_key().to_encoded_point(true);
+    let hash = Ripemd160::digest(Sha256::digest(pubkey.as_bytes()));
+    let address = bech32::encode("regen", hash.to_base32(), bech32::Variant::Bech32).unwrap();
+    (key, address)
+}
+
+fn sign_order(
+    key: &k256::ecdsa::SigningKey,
+    order: &SignedSellOrder,
+) -> (cosmwasm_std::Binary, cosmwasm_std::Binary) {
+    use k256::ecdsa::signature::hazmat::PrehashSigner;
+    use sha2::{Digest, Sha256};
+
+    let hash = Sha256::digest(crate::signing::sign_bytes(order).unwrap());
+    let signature: k256::ecdsa::Signature = key.sign_prehash(&hash).unwrap();
+    let pubkey = key.verifying_key().to_encoded_point(true);
+    (
+        signature.to_bytes().to_vec().into(),
+        pubkey.as_bytes().to_vec().into(),
+    )
+}
+
+fn signed_buy(quantity: u128) -> SignedOrderBuyMsg {
+    SignedOrderBuyMsg {
+        quantity: Uint128::new(quantity),
+        bid_price: Coin {
+            denom: "uusd".to_string(),
+            amount: Uint128::new(10),
+        },
+        disable_auto_retire: false,
+        retirement_jurisdiction: None,
+        retirement_reason: None,
+        max_fee_amount: Coin {
+            denom: "uusd".to_string(),
+            amount: Uint128::new(10),
+        },
+        beneficiary: None,
+        use_balance: false,
+        on_delivery_failure: DeliveryFailure::Revert,
+    }
+}
+
+/// Writes the storage of a marketplace deployed at 0.1.0, before cw2 version
+/// info was recorded.
+fn store_v0_1_fixtures(storage: &mut dyn cosmwasm_std::Storage) {
+    let sell_order: v0_1::SellOrder = from_json(
+        br#"{
+            "id": 7,
+            "seller": "seller",
+            "batch_key": 0,
+            "quantity": "0100",
+            "market_id": 0,
+            "ask_amount": "1000",
+            "disable_auto_retire": false,
+            "expiration": null,
+            "maker": true
+        }"#,
+    )
+    .unwrap();
+    let allowed_denom: v0_1::AllowedDenom =
+        from_json(br#"{"bank_denom": "uregen", "display_denom": "REGEN", "exponent": 6}"#).unwrap();
+    let fee_params: v0_1::FeeParams =
+        from_json(br#"{"buyer_percentage_fee": "100", "seller_percentage_fee": "200"}"#).unwrap();
+
+    v0_1::SELL_ORDERS.save(storage, 7, &sell_order).unwrap();
+    v0_1::ALLOWED_DENOMS
+        .save(storage, "uregen".to_string(), &allowed_denom)
+        .unwrap();
+    v0_1::FEE_PARAMS.save(storage, &fee_params).unwrap();
 }
 
 #[test]
@@ -42,13 +436,15 @@ fn test_sell_order() {
     let sell_msg = ExecuteMsg::Sell {
         orders: vec![SellOrderMsg {
             batch_denom: "eco.uC.001".to_string(),
-            quantity: "100".to_string(),
+            quantity: Uint128::new(100),
             ask_price: Coin {
                 denom: "uusd".to_string(),
                 amount: Uint128::new(1000),
             },
             disable_auto_retire: false,
             expiration: None,
+            allowed_buyers: None,
+            asset: None,
         }],
     };
 
@@ -57,7 +453,7 @@ fn test_sell_order() {
         .unwrap();
 
     // Check if the sell order was created successfully
-    assert!(res.events.len() > 0);
+    assert!(!res.events.is_empty());
 
     // Query the sell order
     let query_msg = QueryMsg::SellOrder { sell_order_id: 1 };
@@ -67,8 +463,8 @@ fn test_sell_order() {
         .unwrap();
 
     assert_eq!(res.sell_order.seller, sender);
-    assert_eq!(res.sell_order.quantity, "100");
-    assert_eq!(res.sell_order.ask_amount, "1000");
+    assert_eq!(res.sell_order.quantity, Uint128::new(100));
+    assert_eq!(res.sell_order.ask_amount, Uint128::new(1000));
 }
 
 #[test]
@@ -82,13 +478,15 @@ fn test_buy_direct() {
     let sell_msg = ExecuteMsg::Sell {
         orders: vec![SellOrderMsg {
             batch_denom: "eco.uC.001".to_string(),
-            quantity: "100".to_string(),
+            quantity: Uint128::new(100),
             ask_price: Coin {
                 denom: "uusd".to_string(),
                 amount: Uint128::new(1000),
             },
             disable_auto_retire: false,
             expiration: None,
+            allowed_buyers: None,
+            asset: None,
         }],
     };
 
@@ -99,7 +497,7 @@ fn test_buy_direct() {
     let buy_msg = ExecuteMsg::BuyDirect {
         orders: vec![crate::msg::BuyOrderMsg {
             sell_order_id: 1,
-            quantity: "50".to_string(),
+            quantity: Uint128::new(50),
             bid_price: Coin {
                 denom: "uusd".to_string(),
                 amount: Uint128::new(1000),
@@ -111,15 +509,29 @@ fn test_buy_direct() {
                 denom: "uusd".to_string(),
                 amount: Uint128::new(10),
             },
+            beneficiary: None,
+            expected_version: None,
+            merkle_proof: None,
+            on_delivery_failure: DeliveryFailure::Revert,
         }],
+        use_balance: false,
     };
 
+    // 50 credits at 1000 plus the 1% buyer fee
+    fund(&mut app, &buyer, 50_500);
     let res = app
-        .execute_contract(buyer.clone(), contract_addr.clone(), &buy_msg, &[])
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &buy_msg,
+            &[Coin::new(50_500, "uusd")],
+        )
         .unwrap();
 
     // Check if the buy order was processed successfully
-    assert!(res.events.len() > 0);
+    assert!(!res.events.is_empty());
+    let balance = app.wrap().query_balance(&seller, "uusd").unwrap();
+    assert_eq!(balance.amount, Uint128::new(49_500));
 
     // Query the updated sell order
     let query_msg = QueryMsg::SellOrder { sell_order_id: 1 };
@@ -128,7 +540,7 @@ fn test_buy_direct() {
         .query_wasm_smart(&contract_addr, &query_msg)
         .unwrap();
 
-    assert_eq!(res.sell_order.quantity, "50");
+    assert_eq!(res.sell_order.quantity, Uint128::new(50));
 }
 
 #[test]
@@ -141,13 +553,15 @@ fn test_cancel_sell_order() {
     let sell_msg = ExecuteMsg::Sell {
         orders: vec![SellOrderMsg {
             batch_denom: "eco.uC.001".to_string(),
-            quantity: "100".to_string(),
+            quantity: Uint128::new(100),
             ask_price: Coin {
                 denom: "uusd".to_string(),
                 amount: Uint128::new(1000),
             },
             disable_auto_retire: false,
             expiration: None,
+            allowed_buyers: None,
+            asset: None,
         }],
     };
 
@@ -161,12 +575,13 @@ fn test_cancel_sell_order() {
         .unwrap();
 
     // Check if the sell order was cancelled successfully
-    assert!(res.events.len() > 0);
+    assert!(!res.events.is_empty());
 
     // Query all sell orders (should be empty now)
     let query_msg = QueryMsg::SellOrders {
         start_after: None,
         limit: None,
+        include_private: None,
     };
     let res: SellOrdersResponse = app
         .wrap()
@@ -175,3 +590,4297 @@ fn test_cancel_sell_order() {
 
     assert_eq!(res.sell_orders.len(), 0);
 }
+
+#[test]
+fn test_migrate_records_version() {
+    let mut app = mock_app();
+    let admin = Addr::unchecked("admin");
+    let contract_addr = instantiate_contract(&mut app, admin.clone());
+
+    let version = cw2::query_contract_info(&app.wrap(), contract_addr.to_string()).unwrap();
+    assert_eq!(version.contract, CONTRACT_NAME);
+    assert_eq!(version.version, CONTRACT_VERSION);
+
+    let code_id = app.contract_data(&contract_addr).unwrap().code_id;
+    app.migrate_contract(
+        admin,
+        contract_addr.clone(),
+        &MigrateMsg {
+            authority: None,
+            ecocredit: None,
+        },
+        code_id,
+    )
+    .unwrap();
+
+    let version = cw2::query_contract_info(&app.wrap(), contract_addr.to_string()).unwrap();
+    assert_eq!(version.version, CONTRACT_VERSION);
+}
+
+#[test]
+fn test_migrate_from_unversioned_v0_1() {
+    let mut deps = mock_dependencies();
+    store_v0_1_fixtures(deps.as_mut().storage);
+
+    let msg = MigrateMsg {
+        authority: Some("authority".to_string()),
+        ecocredit: None,
+    };
+    let res = migrate(deps.as_mut(), mock_env(), msg).unwrap();
+    assert!(res
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "from_version" && attr.value == "0.1.0"));
+
+    let version = cw2::get_contract_version(deps.as_ref().storage).unwrap();
+    assert_eq!(version.version, CONTRACT_VERSION);
+    let config = CONFIG.load(deps.as_ref().storage).unwrap();
+    assert_eq!(config.authority, Addr::unchecked("authority"));
+
+    let sell_order = SELL_ORDERS.load(deps.as_ref().storage, 7).unwrap();
+    assert_eq!(sell_order.seller, Addr::unchecked("seller"));
+    assert_eq!(sell_order.quantity, Uint128::new(100));
+    assert_eq!(sell_order.version, 1);
+    assert_eq!(sell_order.ask_amount, Uint128::new(1000));
+    let allowed_denom = ALLOWED_DENOMS
+        .load(deps.as_ref().storage, "uregen".to_string())
+        .unwrap();
+    assert_eq!(allowed_denom.display_denom, "REGEN");
+    assert_eq!(allowed_denom.kind, DenomKind::Native);
+    // Basis points become fractions of the price.
+    let fee_params = FEE_PARAMS.load(deps.as_ref().storage).unwrap();
+    assert_eq!(fee_params.buyer_percentage_fee, Decimal::percent(1));
+    assert_eq!(fee_params.seller_percentage_fee, Decimal::percent(2));
+}
+
+#[test]
+fn test_migrate_from_v0_3() {
+    let mut deps = mock_dependencies();
+    store_v0_1_fixtures(deps.as_mut().storage);
+    v0_1::SELL_ORDERS.remove(deps.as_mut().storage, 7);
+    let sell_order: v0_3::SellOrder = from_json(
+        br#"{
+            "id": 7,
+            "seller": "seller",
+            "batch_key": 0,
+            "quantity": "100",
+            "market_id": 0,
+            "ask_amount": "1000",
+            "disable_auto_retire": false,
+            "expiration": null,
+            "maker": true,
+            "version": 4
+        }"#,
+    )
+    .unwrap();
+    v0_3::SELL_ORDERS
+        .save(deps.as_mut().storage, 7, &sell_order)
+        .unwrap();
+    v0_3::CONFIG
+        .save(
+            deps.as_mut().storage,
+            &v0_3::Config {
+                authority: Addr::unchecked("authority"),
+            },
+        )
+        .unwrap();
+    cw2::set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.3.0").unwrap();
+
+    let msg = MigrateMsg {
+        authority: None,
+        ecocredit: None,
+    };
+    migrate(deps.as_mut(), mock_env(), msg).unwrap();
+
+    let config = CONFIG.load(deps.as_ref().storage).unwrap();
+    assert_eq!(config.authority, Addr::unchecked("authority"));
+    assert_eq!(config.ecocredit, EcocreditAdapter::Disabled);
+    assert_eq!(config.crank_reward_share, Decimal::zero());
+    // Orders keep their version and stay public orders of native credits.
+    let sell_order = SELL_ORDERS.load(deps.as_ref().storage, 7).unwrap();
+    assert_eq!(sell_order.version, 4);
+    assert_eq!(sell_order.allowed_buyers, None);
+    assert_eq!(sell_order.asset, CreditAsset::Native);
+    let allowed_denom = ALLOWED_DENOMS
+        .load(deps.as_ref().storage, "uregen".to_string())
+        .unwrap();
+    assert_eq!(allowed_denom.kind, DenomKind::Native);
+
+    // History starts at the migration.
+    let height = mock_env().block.height;
+    let storage = deps.as_ref().storage;
+    assert_eq!(
+        SELL_ORDERS.may_load_at_height(storage, 7, height).unwrap(),
+        None
+    );
+    assert!(SELL_ORDERS
+        .may_load_at_height(storage, 7, height + 1)
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        FEE_PARAMS.may_load_at_height(storage, height).unwrap(),
+        None
+    );
+    assert!(FEE_PARAMS.load(storage).is_ok());
+}
+
+#[test]
+fn test_rewrite_map_from_v0_1_layout() {
+    let mut deps = mock_dependencies();
+    store_v0_1_fixtures(deps.as_mut().storage);
+
+    migrations::rewrite_map(
+        deps.as_mut().storage,
+        &v0_1::ALLOWED_DENOMS,
+        &ALLOWED_DENOMS,
+        |old| {
+            Ok(crate::state::AllowedDenom {
+                bank_denom: old.bank_denom,
+                display_denom: old.display_denom.to_lowercase(),
+                exponent: old.exponent,
+                kind: DenomKind::Native,
+            })
+        },
+    )
+    .unwrap();
+
+    let allowed_denom = ALLOWED_DENOMS
+        .load(deps.as_ref().storage, "uregen".to_string())
+        .unwrap();
+    assert_eq!(allowed_denom.display_denom, "regen");
+}
+
+#[test]
+fn test_migrate_rejects_unparsable_v0_1_amounts() {
+    let mut deps = mock_dependencies();
+    store_v0_1_fixtures(deps.as_mut().storage);
+
+    let mut sell_order = v0_1::SELL_ORDERS.load(deps.as_ref().storage, 7).unwrap();
+    sell_order.quantity = "1.5".to_string();
+    v0_1::SELL_ORDERS
+        .save(deps.as_mut().storage, 7, &sell_order)
+        .unwrap();
+
+    let msg = MigrateMsg {
+        authority: Some("authority".to_string()),
+        ecocredit: None,
+    };
+    let err = migrate(deps.as_mut(), mock_env(), msg).unwrap_err();
+    assert!(matches!(err, ContractError::MigrationFailed { .. }));
+    assert!(cw2::CONTRACT
+        .may_load(deps.as_ref().storage)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_migrate_rejects_downgrade_and_foreign_contracts() {
+    let mut deps = mock_dependencies();
+    cw2::set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+    let err = migrate(
+        deps.as_mut(),
+        mock_env(),
+        MigrateMsg {
+            authority: None,
+            ecocredit: None,
+        },
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::CannotDowngrade { .. }));
+
+    let mut deps = mock_dependencies();
+    cw2::set_contract_version(deps.as_mut().storage, "crates.io:cw20-base", "1.0.0").unwrap();
+    let err = migrate(
+        deps.as_mut(),
+        mock_env(),
+        MigrateMsg {
+            authority: None,
+            ecocredit: None,
+        },
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::WrongContract { .. }));
+
+    // Without cw2 info or marketplace state there is nothing to migrate.
+    let mut deps = mock_dependencies();
+    let err = migrate(
+        deps.as_mut(),
+        mock_env(),
+        MigrateMsg {
+            authority: None,
+            ecocredit: None,
+        },
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::WrongContract { .. }));
+}
+
+#[test]
+fn test_pause_scopes() {
+    let mut app = mock_app();
+    let authority = Addr::unchecked("authority");
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, authority.clone());
+
+    let sell = |batch_denom: &str| ExecuteMsg::Sell {
+        orders: vec![sell_order_msg(batch_denom, 100, 10)],
+    };
+    app.execute_contract(seller.clone(), contract_addr.clone(), &sell("C01-001"), &[])
+        .unwrap();
+
+    // Only the authority can pause.
+    let pause = |scope: PauseScope| ExecuteMsg::Pause { scope };
+    let err = app
+        .execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &pause(PauseScope::All),
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::Unauthorized {})
+    ));
+
+    // Halting purchases still allows new listings.
+    app.execute_contract(
+        authority.clone(),
+        contract_addr.clone(),
+        &pause(PauseScope::Purchases),
+        &[],
+    )
+    .unwrap();
+    let buy = ExecuteMsg::BuyDirect {
+        orders: vec![buy_order_msg(1, 10, 10)],
+        use_balance: false,
+    };
+    let err = app
+        .execute_contract(buyer.clone(), contract_addr.clone(), &buy, &[])
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::Paused { .. })
+    ));
+    // Offers are purchases too.
+    fund(&mut app, &buyer, 202);
+    let err = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::MakeOffer {
+                sell_order_id: 1,
+                quantity: Uint128::new(2),
+                price: Coin::new(100, "uusd"),
+                expiration: None,
+                disable_auto_retire: false,
+                merkle_proof: None,
+                on_delivery_failure: DeliveryFailure::Revert,
+            },
+            &[Coin::new(202, "uusd")],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::Paused { .. })
+    ));
+    app.execute_contract(seller.clone(), contract_addr.clone(), &sell("C01-002"), &[])
+        .unwrap();
+
+    app.execute_contract(
+        authority.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Unpause {
+            scope: PauseScope::Purchases,
+        },
+        &[],
+    )
+    .unwrap();
+    fund(&mut app, &buyer, 100);
+    app.execute_contract(
+        buyer,
+        contract_addr.clone(),
+        &buy,
+        &[Coin::new(100, "uusd")],
+    )
+    .unwrap();
+
+    // Halting one batch leaves the others tradable.
+    app.execute_contract(
+        authority.clone(),
+        contract_addr.clone(),
+        &pause(PauseScope::Batch {
+            batch_denom: "C01-001".to_string(),
+        }),
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(seller.clone(), contract_addr.clone(), &sell("C01-001"), &[])
+        .unwrap_err();
+    app.execute_contract(seller.clone(), contract_addr.clone(), &sell("C01-002"), &[])
+        .unwrap();
+
+    let res: PausesResponse = app
+        .wrap()
+        .query_wasm_smart(&contract_addr, &QueryMsg::Pauses {})
+        .unwrap();
+    assert_eq!(
+        res.pauses,
+        vec![PauseScope::Batch {
+            batch_denom: "C01-001".to_string()
+        }]
+    );
+}
+
+#[test]
+fn test_cancel_allowed_while_paused() {
+    let mut app = mock_app();
+    let authority = Addr::unchecked("authority");
+    let seller = Addr::unchecked("seller");
+    let contract_addr = instantiate_contract(&mut app, authority.clone());
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![sell_order_msg("C01-001", 100, 10)],
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        authority,
+        contract_addr.clone(),
+        &ExecuteMsg::Pause {
+            scope: PauseScope::All,
+        },
+        &[],
+    )
+    .unwrap();
+
+    let update = ExecuteMsg::UpdateSellOrders {
+        updates: vec![crate::msg::UpdateSellOrderMsg {
+            sell_order_id: 1,
+            new_quantity: Some(Uint128::new(50)),
+            new_ask_price: None,
+            disable_auto_retire: None,
+            new_expiration: None,
+        }],
+    };
+    app.execute_contract(seller.clone(), contract_addr.clone(), &update, &[])
+        .unwrap_err();
+
+    app.execute_contract(
+        seller,
+        contract_addr.clone(),
+        &ExecuteMsg::CancelSellOrder { sell_order_id: 1 },
+        &[],
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_sell_orders_by_batch() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![
+                sell_order_msg("C01-001", 100, 10),
+                sell_order_msg("C01-002", 100, 10),
+                sell_order_msg("C01-001", 50, 12),
+            ],
+        },
+        &[],
+    )
+    .unwrap();
+
+    let res: SellOrdersResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::SellOrdersByBatch {
+                batch_denom: "C01-001".to_string(),
+                start_after: None,
+                limit: None,
+                include_private: None,
+            },
+        )
+        .unwrap();
+    let ids: Vec<u64> = res.sell_orders.iter().map(|order| order.id).collect();
+    assert_eq!(ids, vec![1, 3]);
+    // Both batches share the C credit type and uusd, so one market.
+    assert!(res.sell_orders.iter().all(|order| order.market_id == 1));
+}
+
+#[test]
+fn test_ask_denom_must_be_allowed() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+
+    let mut order = sell_order_msg("C01-001", 100, 10);
+    order.ask_price.denom = "uatom".to_string();
+    let err = app
+        .execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Sell {
+                orders: vec![order],
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::DenomNotAllowed { .. })
+    ));
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![sell_order_msg("C01-001", 100, 10)],
+        },
+        &[],
+    )
+    .unwrap();
+
+    let reprice = ExecuteMsg::UpdateSellOrders {
+        updates: vec![crate::msg::UpdateSellOrderMsg {
+            sell_order_id: 1,
+            new_quantity: None,
+            new_ask_price: Some(Coin {
+                denom: "uatom".to_string(),
+                amount: Uint128::new(5),
+            }),
+            disable_auto_retire: None,
+            new_expiration: None,
+        }],
+    };
+    let err = app
+        .execute_contract(seller, contract_addr, &reprice, &[])
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::DenomNotAllowed { .. })
+    ));
+}
+
+#[test]
+fn test_bid_denom_must_match_order() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+
+    app.execute_contract(
+        seller,
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![sell_order_msg("C01-001", 100, 10)],
+        },
+        &[],
+    )
+    .unwrap();
+
+    let mut order = buy_order_msg(1, 10, 10);
+    order.bid_price.denom = "uatom".to_string();
+    order.max_fee_amount.denom = "uatom".to_string();
+    let err = app
+        .execute_contract(
+            buyer,
+            contract_addr,
+            &ExecuteMsg::BuyDirect {
+                orders: vec![order],
+                use_balance: false,
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::BidDenomMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_removed_denom_freezes_orders() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![sell_order_msg("C01-001", 100, 10)],
+        },
+        &[],
+    )
+    .unwrap();
+    let remove = ExecuteMsg::RemoveAllowedDenom {
+        denom: "uusd".to_string(),
+    };
+    // Only the authority manages allowed denoms.
+    let err = app
+        .execute_contract(buyer.clone(), contract_addr.clone(), &remove, &[])
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::Unauthorized {})
+    ));
+    app.execute_contract(seller.clone(), contract_addr.clone(), &remove, &[])
+        .unwrap();
+
+    // The order stays listed but cannot be bought.
+    let buy = ExecuteMsg::BuyDirect {
+        orders: vec![buy_order_msg(1, 10, 10)],
+        use_balance: false,
+    };
+    let err = app
+        .execute_contract(buyer.clone(), contract_addr.clone(), &buy, &[])
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::DenomNotAllowed { .. })
+    ));
+    let res: SellOrderResponse = app
+        .wrap()
+        .query_wasm_smart(&contract_addr, &QueryMsg::SellOrder { sell_order_id: 1 })
+        .unwrap();
+    assert_eq!(res.sell_order.quantity, Uint128::new(100));
+
+    // Allowing the denom again thaws it.
+    app.execute_contract(
+        seller,
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowedDenom {
+            bank_denom: "uusd".to_string(),
+            display_denom: "USD".to_string(),
+            exponent: 6,
+            kind: DenomKind::Native,
+        },
+        &[],
+    )
+    .unwrap();
+    fund(&mut app, &buyer, 100);
+    app.execute_contract(buyer, contract_addr, &buy, &[Coin::new(100, "uusd")])
+        .unwrap();
+}
+
+#[test]
+fn test_buy_pinned_to_order_version() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![sell_order_msg("C01-001", 100, 10)],
+        },
+        &[],
+    )
+    .unwrap();
+    let res: SellOrderResponse = app
+        .wrap()
+        .query_wasm_smart(&contract_addr, &QueryMsg::SellOrder { sell_order_id: 1 })
+        .unwrap();
+    assert_eq!(res.sell_order.version, 1);
+
+    // The seller raises the price after the buyer looked at the order.
+    app.execute_contract(
+        seller,
+        contract_addr.clone(),
+        &ExecuteMsg::UpdateSellOrders {
+            updates: vec![crate::msg::UpdateSellOrderMsg {
+                sell_order_id: 1,
+                new_quantity: None,
+                new_ask_price: Some(Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::new(12),
+                }),
+                disable_auto_retire: None,
+                new_expiration: None,
+            }],
+        },
+        &[],
+    )
+    .unwrap();
+
+    let mut order = buy_order_msg(1, 10, 20);
+    order.expected_version = Some(1);
+    let err = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirect {
+                orders: vec![order.clone()],
+                use_balance: false,
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::SellOrderChanged {
+            sell_order_id: 1,
+            expected_version: 1,
+            version: 2,
+        })
+    ));
+
+    order.expected_version = Some(2);
+    fund(&mut app, &buyer, 120);
+    app.execute_contract(
+        buyer,
+        contract_addr,
+        &ExecuteMsg::BuyDirect {
+            orders: vec![order],
+            use_balance: false,
+        },
+        &[Coin::new(120, "uusd")],
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_buy_for_beneficiary() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let treasury = Addr::unchecked("treasury");
+    let customer = Addr::unchecked("customer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![sell_order_msg("C01-001", 100, 10)],
+        },
+        &[],
+    )
+    .unwrap();
+
+    let mut order = buy_order_msg(1, 10, 10);
+    order.beneficiary = Some(customer.to_string());
+    order.retirement_jurisdiction = Some("US-WA".to_string());
+    order.retirement_reason = Some("offsetting customer flights".to_string());
+    fund(&mut app, &treasury, 150);
+    let res = app
+        .execute_contract(
+            treasury.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirect {
+                orders: vec![order],
+                use_balance: false,
+            },
+            &[Coin::new(100, "uusd")],
+        )
+        .unwrap();
+
+    let filled = event(&res, "wasm-sell_order_filled");
+    assert_eq!(event_attr(filled, "trade_id"), "1");
+    assert_eq!(event_attr(filled, "payer"), "treasury");
+    assert_eq!(event_attr(filled, "beneficiary"), "customer");
+    assert_eq!(event_attr(filled, "retired"), "true");
+
+    let res: TradesResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::TradesByBeneficiary {
+                beneficiary: customer.to_string(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(res.trades.len(), 1);
+    let trade = &res.trades[0];
+    assert_eq!(trade.source, TradeSource::SellOrder { sell_order_id: 1 });
+    assert_eq!(trade.seller, seller);
+    assert_eq!(trade.payer, treasury);
+    assert_eq!(trade.beneficiary, customer);
+    assert_eq!(trade.batch_denom, "C01-001");
+    assert_eq!(trade.quantity, Uint128::new(10));
+    assert_eq!(
+        trade.retirement,
+        Some(Retirement {
+            jurisdiction: Some("US-WA".to_string()),
+            reason: Some("offsetting customer flights".to_string()),
+        })
+    );
+
+    // Without a beneficiary the payer keeps the credits.
+    app.execute_contract(
+        treasury.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::BuyDirect {
+            orders: vec![buy_order_msg(1, 5, 10)],
+            use_balance: false,
+        },
+        &[Coin::new(50, "uusd")],
+    )
+    .unwrap();
+    let res: TradesResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::TradesByBeneficiary {
+                beneficiary: treasury.to_string(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(res.trades.len(), 1);
+    assert_eq!(res.trades[0].id, 2);
+}
+
+#[test]
+fn test_buy_direct_from_purchase_allowance() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let company = Addr::unchecked("company");
+    let employee = Addr::unchecked("employee");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+    fund(&mut app, &company, 5000);
+    fund(&mut app, &employee, 100);
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![
+                sell_order_msg("C01-001", 100, 100),
+                sell_order_msg("C02-001", 100, 100),
+            ],
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        company.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit {},
+        &[Coin::new(5000, "uusd")],
+    )
+    .unwrap();
+    app.execute_contract(
+        company.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::GrantPurchaseAllowance {
+            spender: employee.to_string(),
+            denom: "uusd".to_string(),
+            limit: Uint128::new(1100),
+            expiration: None,
+            allowed_batches: Some(vec!["C01-001".to_string()]),
+        },
+        &[],
+    )
+    .unwrap();
+
+    let buy_from = |app: &mut App, order: BuyOrderMsg| {
+        app.execute_contract(
+            employee.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirectFrom {
+                owner: company.to_string(),
+                orders: vec![order],
+            },
+            &[],
+        )
+    };
+
+    // The owner pays, so the spender cannot attach funds.
+    let err = app
+        .execute_contract(
+            employee.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirectFrom {
+                owner: company.to_string(),
+                orders: vec![buy_order_msg(1, 10, 100)],
+            },
+            &[Coin::new(100, "uusd")],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::InvalidFunds { .. })
+    ));
+
+    // 10 credits at 100 plus a 1% buyer fee cost 1010 of the 1100 budget.
+    buy_from(&mut app, buy_order_msg(1, 10, 100)).unwrap();
+    let balance = app.wrap().query_balance(&seller, "uusd").unwrap();
+    assert_eq!(balance.amount, Uint128::new(990));
+    let res: PurchaseAllowanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::PurchaseAllowance {
+                owner: company.to_string(),
+                spender: employee.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.allowance.unwrap().remaining, Uint128::new(90));
+    let res: TradesResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::Trades {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(res.trades[0].payer, company);
+    assert_eq!(res.trades[0].spender, Some(employee.clone()));
+    assert_eq!(res.trades[0].beneficiary, company);
+
+    let err = buy_from(&mut app, buy_order_msg(2, 1, 100)).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::BatchNotAllowed { batch_denom }) if batch_denom == "C02-001"
+    ));
+    let err = buy_from(&mut app, buy_order_msg(1, 1, 100)).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::PurchaseAllowanceExceeded { .. })
+    ));
+
+    app.execute_contract(
+        company.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RevokeAllowance {
+            spender: employee.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    let err = buy_from(&mut app, buy_order_msg(1, 1, 100)).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::NoPurchaseAllowance { .. })
+    ));
+}
+
+#[test]
+fn test_settle_against_deposited_balances() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+    fund(&mut app, &buyer, 2000);
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![sell_order_msg("C01-001", 100, 100)],
+        },
+        &[],
+    )
+    .unwrap();
+
+    // Attached funds must match the cost of 1010 exactly.
+    let buy = |use_balance| ExecuteMsg::BuyDirect {
+        orders: vec![buy_order_msg(1, 10, 100)],
+        use_balance,
+    };
+    let err = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &buy(false),
+            &[Coin::new(1000, "uusd")],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::InvalidFunds { .. })
+    ));
+
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit {},
+        &[Coin::new(2000, "uusd")],
+    )
+    .unwrap();
+    let res = app
+        .execute_contract(buyer.clone(), contract_addr.clone(), &buy(true), &[])
+        .unwrap();
+    assert!(!res.events.iter().any(|event| event.ty == "transfer"));
+
+    let balance = |app: &App, address: &Addr| -> Vec<Coin> {
+        let res: BalanceResponse = app
+            .wrap()
+            .query_wasm_smart(
+                &contract_addr,
+                &QueryMsg::Balance {
+                    address: address.to_string(),
+                },
+            )
+            .unwrap();
+        res.balances
+    };
+    assert_eq!(balance(&app, &buyer), vec![Coin::new(990, "uusd")]);
+    assert_eq!(balance(&app, &seller), vec![Coin::new(990, "uusd")]);
+    let res: BalancesResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::Balances {
+                start_after: Some(buyer.to_string()),
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(res.accounts.len(), 1);
+    assert_eq!(res.accounts[0].address, seller);
+
+    let withdraw = |amount| ExecuteMsg::Withdraw {
+        coins: vec![Coin::new(amount, "uusd")],
+    };
+    let err = app
+        .execute_contract(seller.clone(), contract_addr.clone(), &withdraw(991), &[])
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::InsufficientBalance { .. })
+    ));
+    app.execute_contract(seller.clone(), contract_addr.clone(), &withdraw(990), &[])
+        .unwrap();
+    assert_eq!(
+        app.wrap().query_balance(&seller, "uusd").unwrap().amount,
+        Uint128::new(990)
+    );
+    assert!(balance(&app, &seller).is_empty());
+}
+
+#[test]
+fn test_private_sell_orders() {
+    use sha2::{Digest, Sha256};
+
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+    let mallory = Addr::unchecked("mallory");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+    for buyer in [&alice, &bob, &mallory] {
+        fund(&mut app, buyer, 100);
+    }
+
+    // A two leaf tree of alice and bob, hashed in sorted order.
+    let alice_leaf = Sha256::digest(alice.as_bytes()).to_vec();
+    let bob_leaf = Sha256::digest(bob.as_bytes()).to_vec();
+    let (left, right) = if alice_leaf <= bob_leaf {
+        (&alice_leaf, &bob_leaf)
+    } else {
+        (&bob_leaf, &alice_leaf)
+    };
+    let root = Sha256::new()
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .to_vec();
+
+    let mut listed = sell_order_msg("C01-001", 100, 10);
+    listed.allowed_buyers = Some(AllowedBuyersMsg::Addresses {
+        addresses: vec![alice.to_string()],
+    });
+    let mut merkle = sell_order_msg("C01-001", 100, 10);
+    merkle.allowed_buyers = Some(AllowedBuyersMsg::MerkleRoot { root: root.into() });
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![listed, merkle, sell_order_msg("C01-001", 100, 10)],
+        },
+        &[],
+    )
+    .unwrap();
+
+    let buy = |app: &mut App, buyer: &Addr, order: BuyOrderMsg| {
+        app.execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirect {
+                orders: vec![order],
+                use_balance: false,
+            },
+            &[Coin::new(10, "uusd")],
+        )
+    };
+
+    let err = buy(&mut app, &mallory, buy_order_msg(1, 1, 10)).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::BuyerNotAllowed {
+            sell_order_id: 1,
+            ..
+        })
+    ));
+    buy(&mut app, &alice, buy_order_msg(1, 1, 10)).unwrap();
+
+    let mut order = buy_order_msg(2, 1, 10);
+    order.merkle_proof = Some(vec![alice_leaf.clone().into()]);
+    buy(&mut app, &bob, order.clone()).unwrap();
+    let err = buy(&mut app, &mallory, order).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::BuyerNotAllowed {
+            sell_order_id: 2,
+            ..
+        })
+    ));
+
+    let query = |include_private| -> Vec<u64> {
+        let res: SellOrdersResponse = app
+            .wrap()
+            .query_wasm_smart(
+                &contract_addr,
+                &QueryMsg::SellOrders {
+                    start_after: None,
+                    limit: None,
+                    include_private,
+                },
+            )
+            .unwrap();
+        res.sell_orders.iter().map(|order| order.id).collect()
+    };
+    assert_eq!(query(None), vec![3]);
+    assert_eq!(query(Some(true)), vec![1, 2, 3]);
+}
+
+#[test]
+fn test_offer_negotiation_settles_with_fees() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+    fund(&mut app, &buyer, 1000);
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![sell_order_msg("C01-001", 10, 150)],
+        },
+        &[],
+    )
+    .unwrap();
+
+    // 5 credits at 100 plus a 1% buyer fee.
+    let res = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::MakeOffer {
+                sell_order_id: 1,
+                quantity: Uint128::new(5),
+                price: Coin::new(100, "uusd"),
+                expiration: None,
+                disable_auto_retire: false,
+                merkle_proof: None,
+                on_delivery_failure: DeliveryFailure::Revert,
+            },
+            &[Coin::new(505, "uusd")],
+        )
+        .unwrap();
+    let made = event(&res, "wasm-offer_made");
+    assert_eq!(event_attr(made, "offer_id"), "1");
+    assert_eq!(event_attr(made, "proposer"), "buyer");
+
+    let err = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AcceptOffer { offer_id: 1 },
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::OwnOfferTerms { offer_id: 1 })
+    ));
+    app.execute_contract(
+        Addr::unchecked("mallory"),
+        contract_addr.clone(),
+        &ExecuteMsg::CounterOffer {
+            offer_id: 1,
+            quantity: Uint128::new(5),
+            price: Coin::new(1, "uusd"),
+            expiration: None,
+        },
+        &[],
+    )
+    .unwrap_err();
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CounterOffer {
+            offer_id: 1,
+            quantity: Uint128::new(5),
+            price: Coin::new(120, "uusd"),
+            expiration: None,
+        },
+        &[],
+    )
+    .unwrap();
+
+    // The escrow of 505 no longer covers 5 * (120 + 1).
+    let accept = |app: &mut App, funds: &[Coin]| {
+        app.execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AcceptOffer { offer_id: 1 },
+            funds,
+        )
+    };
+    let err = accept(&mut app, &[]).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::InvalidFunds { .. })
+    ));
+    let res = accept(&mut app, &[Coin::new(100, "uusd")]).unwrap();
+
+    let filled = event(&res, "wasm-sell_order_filled");
+    assert_eq!(event_attr(filled, "ask_amount"), "120");
+    assert_eq!(event_attr(filled, "buyer_fee"), "1uusd");
+    assert_eq!(event_attr(filled, "remaining_quantity"), "5");
+    assert_eq!(
+        event_attr(event(&res, "wasm-offer_accepted"), "trade_id"),
+        event_attr(filled, "trade_id")
+    );
+
+    let balance = |addr: &Addr| app.wrap().query_balance(addr, "uusd").unwrap().amount;
+    assert_eq!(balance(&seller), Uint128::new(595));
+    assert_eq!(balance(&buyer), Uint128::new(395));
+    assert_eq!(balance(&contract_addr), Uint128::new(10));
+    app.wrap()
+        .query_wasm_smart::<OfferResponse>(&contract_addr, &QueryMsg::Offer { offer_id: 1 })
+        .unwrap_err();
+}
+
+#[test]
+fn test_offer_reject_and_expiry() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+    fund(&mut app, &buyer, 1000);
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![
+                sell_order_msg("C01-001", 10, 150),
+                sell_order_msg("C01-001", 10, 150),
+            ],
+        },
+        &[],
+    )
+    .unwrap();
+
+    let expiration = app.block_info().time.plus_seconds(60);
+    for sell_order_id in [1, 2] {
+        app.execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::MakeOffer {
+                sell_order_id,
+                quantity: Uint128::new(2),
+                price: Coin::new(100, "uusd"),
+                expiration: Some(expiration),
+                disable_auto_retire: false,
+                merkle_proof: None,
+                on_delivery_failure: DeliveryFailure::Revert,
+            },
+            &[Coin::new(202, "uusd")],
+        )
+        .unwrap();
+    }
+
+    let offers = |app: &App, msg: &QueryMsg| -> Vec<u64> {
+        let res: OffersResponse = app.wrap().query_wasm_smart(&contract_addr, msg).unwrap();
+        res.offers.iter().map(|offer| offer.id).collect()
+    };
+    let by_buyer = QueryMsg::OffersByBuyer {
+        buyer: buyer.to_string(),
+        start_after: None,
+        limit: None,
+    };
+    assert_eq!(offers(&app, &by_buyer), vec![1, 2]);
+    assert_eq!(
+        offers(
+            &app,
+            &QueryMsg::OffersForOrder {
+                sell_order_id: 2,
+                start_after: None,
+                limit: None,
+            }
+        ),
+        vec![2]
+    );
+
+    // The seller rejects, refunding the escrow to the buyer.
+    let res = app
+        .execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RejectOffer { offer_id: 1 },
+            &[],
+        )
+        .unwrap();
+    assert_eq!(
+        event_attr(event(&res, "wasm-offer_rejected"), "rejected_by"),
+        "seller"
+    );
+    let balance = app.wrap().query_balance(&buyer, "uusd").unwrap().amount;
+    assert_eq!(balance, Uint128::new(798));
+
+    app.update_block(|block| block.time = block.time.plus_seconds(60));
+    let err = app
+        .execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AcceptOffer { offer_id: 2 },
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::OfferExpired { offer_id: 2 })
+    ));
+
+    // Expired offers can still be withdrawn.
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RejectOffer { offer_id: 2 },
+        &[],
+    )
+    .unwrap();
+    let balance = app.wrap().query_balance(&buyer, "uusd").unwrap().amount;
+    assert_eq!(balance, Uint128::new(1000));
+    assert!(offers(&app, &by_buyer).is_empty());
+}
+
+#[test]
+fn test_expired_sell_orders_cannot_be_bought() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+    fund(&mut app, &buyer, 1000);
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![SellOrderMsg {
+                expiration: Some(app.block_info().time.plus_seconds(60)),
+                ..sell_order_msg("C01-001", 10, 100)
+            }],
+        },
+        &[],
+    )
+    .unwrap();
+    let make_offer = ExecuteMsg::MakeOffer {
+        sell_order_id: 1,
+        quantity: Uint128::new(2),
+        price: Coin::new(100, "uusd"),
+        expiration: None,
+        disable_auto_retire: false,
+        merkle_proof: None,
+        on_delivery_failure: DeliveryFailure::Revert,
+    };
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &make_offer,
+        &[Coin::new(202, "uusd")],
+    )
+    .unwrap();
+
+    // Once the order has expired, neither buying it, offering on it nor
+    // accepting an offer that outlives it goes through.
+    app.update_block(|block| block.time = block.time.plus_seconds(60));
+    let err = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirect {
+                orders: vec![buy_order_msg(1, 1, 100)],
+                use_balance: false,
+            },
+            &[Coin::new(101, "uusd")],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::SellOrderExpired { sell_order_id: 1 })
+    ));
+    let err = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &make_offer,
+            &[Coin::new(202, "uusd")],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::SellOrderExpired { sell_order_id: 1 })
+    ));
+    let err = app
+        .execute_contract(
+            seller,
+            contract_addr.clone(),
+            &ExecuteMsg::AcceptOffer { offer_id: 1 },
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::SellOrderExpired { sell_order_id: 1 })
+    ));
+    assert_eq!(
+        app.wrap().query_balance(&buyer, "uusd").unwrap().amount,
+        Uint128::new(798)
+    );
+}
+
+#[test]
+fn test_collection_bid_fills_matching_orders() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr =
+        instantiate_contract_with(&mut app, seller.clone(), EcocreditAdapter::Regen);
+    fund(&mut app, &buyer, 1010);
+
+    // Class, project and vintage come from the batch denom, the jurisdiction
+    // from the batch's project.
+    let vintage_2021 = "C01-001-20210101-20211231-001";
+    let vintage_2019 = "C01-002-20190101-20191231-001";
+    let congo = "C01-003-20220101-20221231-001";
+    issue_credits(&mut app, &seller, vintage_2021, 14);
+    issue_credits(&mut app, &seller, vintage_2019, 10);
+    issue_credits(&mut app, &seller, congo, 10);
+    app.init_modules(|_, _, storage| {
+        for (project_id, jurisdiction) in [
+            ("C01-001", "US-WA 98225"),
+            ("C01-002", "US-OR"),
+            ("C01-003", "CD-MN"),
+        ] {
+            mock_ecocredit::PROJECTS
+                .save(storage, project_id, &jurisdiction.to_string())
+                .unwrap();
+        }
+        mock_ecocredit::GRANTS
+            .save(storage, (seller.as_str(), contract_addr.as_str()), &())
+            .unwrap();
+    });
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![
+                sell_order_msg(vintage_2021, 4, 150),
+                sell_order_msg(vintage_2021, 10, 150),
+                sell_order_msg(vintage_2019, 10, 150),
+                sell_order_msg(congo, 10, 150),
+            ],
+        },
+        &[],
+    )
+    .unwrap();
+    let res: SellOrderResponse = app
+        .wrap()
+        .query_wasm_smart(&contract_addr, &QueryMsg::SellOrder { sell_order_id: 1 })
+        .unwrap();
+    assert_eq!(
+        res.sell_order.batch_metadata,
+        Some(BatchMetadata {
+            class_id: "C01".to_string(),
+            project_id: "C01-001".to_string(),
+            project_jurisdiction: "US-WA 98225".to_string(),
+            vintage_year: 2021,
+        })
+    );
+
+    // 10 credits at 100 plus a 1% buyer fee.
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::PlaceCollectionBid {
+            criteria: CollectionBidCriteria {
+                class_ids: Some(vec!["C01".to_string()]),
+                project_ids: None,
+                min_vintage_year: Some(2021),
+                max_vintage_year: None,
+                jurisdictions: Some(vec!["US".to_string()]),
+            },
+            quantity: Uint128::new(10),
+            price: Coin::new(100, "uusd"),
+            expiration: None,
+            disable_auto_retire: false,
+            retirement_jurisdiction: Some("US-WA".to_string()),
+            retirement_reason: None,
+            on_delivery_failure: DeliveryFailure::Revert,
+        },
+        &[Coin::new(1010, "uusd")],
+    )
+    .unwrap();
+
+    let fill =
+        |app: &mut App, sender: &Addr, sell_order_id, batch_denom: Option<&str>, quantity| {
+            app.execute_contract(
+                sender.clone(),
+                contract_addr.clone(),
+                &ExecuteMsg::FillCollectionBid {
+                    bid_id: 1,
+                    sell_order_id,
+                    batch_denom: batch_denom.map(str::to_string),
+                    quantity: Uint128::new(quantity),
+                },
+                &[],
+            )
+        };
+    for sell_order_id in [3, 4] {
+        let err = fill(&mut app, &seller, Some(sell_order_id), None, 1).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ContractError>(),
+            Some(ContractError::CollectionBidMismatch { bid_id: 1, .. })
+        ));
+    }
+    let err = fill(&mut app, &buyer, Some(1), None, 1).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::Unauthorized {})
+    ));
+    let err = fill(&mut app, &seller, Some(1), None, 11).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::CollectionBidQuantityExceeded { bid_id: 1, .. })
+    ));
+
+    // Fills the first order of the batch and part of the second.
+    let res = fill(&mut app, &seller, None, Some(vintage_2021), 6).unwrap();
+    let fills: Vec<&str> = res
+        .events
+        .iter()
+        .filter(|event| event.ty == "wasm-sell_order_filled")
+        .map(|event| event_attr(event, "sell_order_id"))
+        .collect();
+    assert_eq!(fills, vec!["1", "2"]);
+    let filled = event(&res, "wasm-sell_order_filled");
+    assert_eq!(event_attr(filled, "ask_amount"), "100");
+    assert_eq!(event_attr(filled, "payer"), "buyer");
+
+    let res: CollectionBidResponse = app
+        .wrap()
+        .query_wasm_smart(&contract_addr, &QueryMsg::CollectionBid { bid_id: 1 })
+        .unwrap();
+    assert_eq!(res.bid.quantity, Uint128::new(4));
+    assert_eq!(res.bid.escrow, Coin::new(404, "uusd"));
+    let balance = |app: &App, addr: &Addr| app.wrap().query_balance(addr, "uusd").unwrap().amount;
+    assert_eq!(balance(&app, &seller), Uint128::new(594));
+    assert_eq!(regen_balance(&app, &buyer, vintage_2021), (0, 6));
+
+    let res = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CancelCollectionBid { bid_id: 1 },
+            &[],
+        )
+        .unwrap();
+    assert_eq!(
+        event_attr(event(&res, "wasm-collection_bid_cancelled"), "refund"),
+        "404uusd"
+    );
+    assert_eq!(balance(&app, &buyer), Uint128::new(404));
+    let res: CollectionBidsResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::CollectionBids {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert!(res.bids.is_empty());
+}
+
+#[test]
+fn test_jurisdiction_criteria_need_regen_adapter() {
+    let mut app = mock_app();
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, Addr::unchecked("owner"));
+    fund(&mut app, &buyer, 1010);
+
+    let err = app
+        .execute_contract(
+            buyer,
+            contract_addr,
+            &ExecuteMsg::PlaceCollectionBid {
+                criteria: CollectionBidCriteria {
+                    class_ids: None,
+                    project_ids: None,
+                    min_vintage_year: None,
+                    max_vintage_year: None,
+                    jurisdictions: Some(vec!["US".to_string()]),
+                },
+                quantity: Uint128::new(10),
+                price: Coin::new(100, "uusd"),
+                expiration: None,
+                disable_auto_retire: false,
+                retirement_jurisdiction: None,
+                retirement_reason: None,
+                on_delivery_failure: DeliveryFailure::Revert,
+            },
+            &[Coin::new(1010, "uusd")],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::JurisdictionsUnavailable {})
+    ));
+}
+
+#[test]
+fn test_buy_bundle_atomically() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+    fund(&mut app, &buyer, 2000);
+
+    let item = |batch_denom: &str, quantity, disable_auto_retire| BundleItem {
+        batch_denom: batch_denom.to_string(),
+        quantity: Uint128::new(quantity),
+        disable_auto_retire,
+    };
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::SellBundle {
+            items: vec![item("C01-001", 100, true), item("C02-001", 50, true)],
+            price: Coin::new(1000, "uusd"),
+            expiration: None,
+        },
+        &[],
+    )
+    .unwrap();
+
+    let res: BundlesResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::BundlesBySeller {
+                seller: seller.to_string(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(res.bundles.len(), 1);
+    assert_eq!(res.bundles[0].items.len(), 2);
+
+    let buy = |app: &mut App, retirement_options: Vec<BundleRetirementMsg>, funds: &[Coin]| {
+        app.execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyBundle {
+                bundle_id: 1,
+                max_fee_amount: Coin::new(10, "uusd"),
+                retirement_options,
+                beneficiary: None,
+                use_balance: false,
+            },
+            funds,
+        )
+    };
+    let option = |batch_denom: &str, disable_auto_retire| BundleRetirementMsg {
+        batch_denom: batch_denom.to_string(),
+        disable_auto_retire,
+        retirement_jurisdiction: Some("US-OR".to_string()),
+        retirement_reason: None,
+    };
+
+    let err = app
+        .execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyBundle {
+                bundle_id: 1,
+                max_fee_amount: Coin::new(10, "uusd"),
+                retirement_options: vec![],
+                beneficiary: None,
+                use_balance: true,
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::SelfTrade {})
+    ));
+
+    let err = buy(
+        &mut app,
+        vec![option("C03-001", true)],
+        &[Coin::new(1010, "uusd")],
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::BatchNotInBundle { bundle_id: 1, .. })
+    ));
+    // The price and a 1% buyer fee on the whole bundle.
+    let err = buy(&mut app, vec![], &[Coin::new(1000, "uusd")]).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::InvalidFunds { .. })
+    ));
+
+    let res = buy(
+        &mut app,
+        vec![option("C01-001", true), option("C02-001", false)],
+        &[Coin::new(1010, "uusd")],
+    )
+    .unwrap();
+    let sold = event(&res, "wasm-bundle_sold");
+    assert_eq!(event_attr(sold, "items"), "100C01-001,50C02-001");
+    assert_eq!(event_attr(sold, "retired_batches"), "C02-001");
+
+    let trades: TradesResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::Trades {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(trades.trades.len(), 2);
+    assert!(trades
+        .trades
+        .iter()
+        .all(|trade| trade.source == TradeSource::Bundle { bundle_id: 1 }));
+    assert_eq!(trades.trades[0].quantity, Uint128::new(100));
+    assert_eq!(trades.trades[0].retirement, None);
+    assert_eq!(
+        trades.trades[1].retirement,
+        Some(Retirement {
+            jurisdiction: Some("US-OR".to_string()),
+            reason: None,
+        })
+    );
+
+    let balance = |addr: &Addr| app.wrap().query_balance(addr, "uusd").unwrap().amount;
+    assert_eq!(balance(&seller), Uint128::new(990));
+    assert_eq!(balance(&buyer), Uint128::new(990));
+    app.wrap()
+        .query_wasm_smart::<BundleResponse>(&contract_addr, &QueryMsg::Bundle { bundle_id: 1 })
+        .unwrap_err();
+}
+
+fn instantiate_cw20(app: &mut App, holder: &Addr, amount: u128) -> Addr {
+    let code = ContractWrapper::new(
+        cw20_base::contract::execute,
+        cw20_base::contract::instantiate,
+        cw20_base::contract::query,
+    );
+    let code_id = app.store_code(Box::new(code));
+    app.instantiate_contract(
+        code_id,
+        holder.clone(),
+        &cw20_base::msg::InstantiateMsg {
+            name: "Stable".to_string(),
+            symbol: "STBL".to_string(),
+            decimals: 6,
+            initial_balances: vec![Cw20Coin {
+                address: holder.to_string(),
+                amount: Uint128::new(amount),
+            }],
+            mint: None,
+            marketing: None,
+        },
+        &[],
+        "Stable",
+        None,
+    )
+    .unwrap()
+}
+
+fn cw20_balance(app: &App, token: &Addr, address: &Addr) -> Uint128 {
+    let res: cw20::BalanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            token,
+            &cw20::Cw20QueryMsg::Balance {
+                address: address.to_string(),
+            },
+        )
+        .unwrap();
+    res.balance
+}
+
+#[test]
+fn test_pay_with_cw20() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+    let token = instantiate_cw20(&mut app, &buyer, 2000);
+    let other_token = instantiate_cw20(&mut app, &buyer, 2000);
+    let denom = format!("cw20:{}", token);
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddAllowedDenom {
+            bank_denom: token.to_string(),
+            display_denom: "STBL".to_string(),
+            exponent: 6,
+            kind: DenomKind::Cw20,
+        },
+        &[],
+    )
+    .unwrap();
+    let mut order = sell_order_msg("C01-001", 10, 100);
+    order.ask_price.denom = denom.clone();
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![order],
+        },
+        &[],
+    )
+    .unwrap();
+
+    let send = |app: &mut App, token: &Addr, amount: u128, msg: &ExecuteMsg| {
+        app.execute_contract(
+            buyer.clone(),
+            token.clone(),
+            &cw20::Cw20ExecuteMsg::Send {
+                contract: contract_addr.to_string(),
+                amount: Uint128::new(amount),
+                msg: to_json_binary(msg).unwrap(),
+            },
+            &[],
+        )
+    };
+
+    let err = send(
+        &mut app,
+        &token,
+        10,
+        &ExecuteMsg::CancelSellOrder { sell_order_id: 1 },
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::NotPayable {})
+    ));
+    let err = send(&mut app, &other_token, 10, &ExecuteMsg::Deposit {}).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::DenomNotAllowed { .. })
+    ));
+
+    // An offer escrowed in cw20 is refunded with a cw20 transfer.
+    send(
+        &mut app,
+        &token,
+        404,
+        &ExecuteMsg::MakeOffer {
+            sell_order_id: 1,
+            quantity: Uint128::new(4),
+            price: Coin::new(100, &denom),
+            expiration: None,
+            disable_auto_retire: false,
+            merkle_proof: None,
+            on_delivery_failure: DeliveryFailure::Revert,
+        },
+    )
+    .unwrap();
+    assert_eq!(cw20_balance(&app, &token, &buyer), Uint128::new(1596));
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RejectOffer { offer_id: 1 },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(cw20_balance(&app, &token, &buyer), Uint128::new(2000));
+
+    // 10 credits at 100 plus a 1% buyer fee.
+    let mut buy = buy_order_msg(1, 10, 100);
+    buy.bid_price.denom = denom.clone();
+    buy.max_fee_amount.denom = denom.clone();
+    let res = send(
+        &mut app,
+        &token,
+        1010,
+        &ExecuteMsg::BuyDirect {
+            orders: vec![buy],
+            use_balance: false,
+        },
+    )
+    .unwrap();
+    let filled = event(&res, "wasm-sell_order_filled");
+    assert_eq!(event_attr(filled, "payer"), "buyer");
+    assert_eq!(event_attr(filled, "ask_denom"), denom);
+
+    assert_eq!(cw20_balance(&app, &token, &seller), Uint128::new(990));
+    assert_eq!(cw20_balance(&app, &token, &buyer), Uint128::new(990));
+    // Both fees stay with the marketplace.
+    assert_eq!(cw20_balance(&app, &token, &contract_addr), Uint128::new(20));
+}
+
+#[test]
+fn test_cw20_credits_escrowed_and_delivered() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+    fund(&mut app, &buyer, 10_000);
+    let credits = instantiate_cw20(&mut app, &seller, 100);
+
+    let mut order = sell_order_msg("C01-001", 10, 100);
+    order.disable_auto_retire = true;
+    order.asset = Some(CreditAssetMsg::Cw20 {
+        contract: credits.to_string(),
+    });
+    let sell = |app: &mut App| {
+        app.execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Sell {
+                orders: vec![order.clone()],
+            },
+            &[],
+        )
+        .unwrap_err()
+    };
+    // Only tokens the authority registered for the batch can be listed.
+    let err = sell(&mut app);
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::UnregisteredCreditToken { .. })
+    ));
+    let res = app
+        .execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddCreditToken {
+                batch_denom: "C01-001".to_string(),
+                asset: CreditAssetMsg::Cw20 {
+                    contract: credits.to_string(),
+                },
+                custodial: false,
+            },
+            &[],
+        )
+        .unwrap();
+    assert_eq!(
+        event_attr(event(&res, "wasm-credit_token_added"), "token"),
+        format!("cw20:{}", credits)
+    );
+    let res: CreditTokensResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::CreditTokens {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(res.tokens.len(), 1);
+    assert_eq!(res.tokens[0].batch_denom, "C01-001");
+    let mut other_batch = order.clone();
+    other_batch.batch_denom = "C01-002".to_string();
+    let err = app
+        .execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Sell {
+                orders: vec![other_batch],
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::UnregisteredCreditToken { .. })
+    ));
+    let err = sell(&mut app);
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::InvalidFunds { .. })
+    ));
+    let res = app
+        .execute_contract(
+            seller.clone(),
+            credits.clone(),
+            &cw20::Cw20ExecuteMsg::Send {
+                contract: contract_addr.to_string(),
+                amount: Uint128::new(10),
+                msg: to_json_binary(&ExecuteMsg::Sell {
+                    orders: vec![order],
+                })
+                .unwrap(),
+            },
+            &[],
+        )
+        .unwrap();
+    let created = event(&res, "wasm-sell_order_created");
+    assert_eq!(event_attr(created, "seller"), "seller");
+    assert_eq!(event_attr(created, "asset"), "cw20");
+    assert_eq!(
+        cw20_balance(&app, &credits, &contract_addr),
+        Uint128::new(10)
+    );
+
+    let buy = |app: &mut App, quantity: u128, disable_auto_retire: bool| {
+        let mut buy = buy_order_msg(1, quantity, 100);
+        buy.disable_auto_retire = disable_auto_retire;
+        app.execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirect {
+                orders: vec![buy],
+                use_balance: false,
+            },
+            &[Coin::new(quantity * 101, "uusd")],
+        )
+        .unwrap()
+    };
+    let supply = |app: &App| {
+        let res: cw20::TokenInfoResponse = app
+            .wrap()
+            .query_wasm_smart(&credits, &cw20::Cw20QueryMsg::TokenInfo {})
+            .unwrap();
+        res.total_supply
+    };
+
+    // Tradable credits are transferred, retired ones are burned.
+    buy(&mut app, 4, true);
+    assert_eq!(cw20_balance(&app, &credits, &buyer), Uint128::new(4));
+    buy(&mut app, 3, false);
+    assert_eq!(cw20_balance(&app, &credits, &buyer), Uint128::new(4));
+    assert_eq!(supply(&app), Uint128::new(97));
+    assert_eq!(
+        cw20_balance(&app, &credits, &contract_addr),
+        Uint128::new(3)
+    );
+
+    let update = |app: &mut App, new_quantity: u128| {
+        app.execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::UpdateSellOrders {
+                updates: vec![crate::msg::UpdateSellOrderMsg {
+                    sell_order_id: 1,
+                    new_quantity: Some(Uint128::new(new_quantity)),
+                    new_ask_price: None,
+                    disable_auto_retire: None,
+                    new_expiration: None,
+                }],
+            },
+            &[],
+        )
+    };
+    let err = update(&mut app, 5).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::EscrowedQuantityIncrease { sell_order_id: 1 })
+    ));
+    update(&mut app, 2).unwrap();
+    assert_eq!(cw20_balance(&app, &credits, &seller), Uint128::new(91));
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CancelSellOrder { sell_order_id: 1 },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(cw20_balance(&app, &credits, &seller), Uint128::new(93));
+    assert_eq!(
+        cw20_balance(&app, &credits, &contract_addr),
+        Uint128::zero()
+    );
+}
+
+#[test]
+fn test_custodial_credit_tokens_retire_backing_credits() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    fund(&mut app, &buyer, 10_000);
+    let credits = instantiate_cw20(&mut app, &seller, 100);
+    let add_token = |app: &mut App, contract_addr: &Addr| {
+        app.execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddCreditToken {
+                batch_denom: "C01-001".to_string(),
+                asset: CreditAssetMsg::Cw20 {
+                    contract: credits.to_string(),
+                },
+                custodial: true,
+            },
+            &[],
+        )
+    };
+
+    // Only the Regen adapter can retire the credits backing a token.
+    let disabled_addr = instantiate_contract(&mut app, seller.clone());
+    let err = add_token(&mut app, &disabled_addr).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::InvalidField { field, .. }) if field == "custodial"
+    ));
+
+    let contract_addr =
+        instantiate_contract_with(&mut app, seller.clone(), EcocreditAdapter::Regen);
+    let res = add_token(&mut app, &contract_addr).unwrap();
+    assert_eq!(
+        event_attr(event(&res, "wasm-credit_token_added"), "custodial"),
+        "true"
+    );
+    issue_credits(&mut app, &contract_addr, "C01-001", 100);
+
+    let mut order = sell_order_msg("C01-001", 10, 100);
+    order.disable_auto_retire = true;
+    order.asset = Some(CreditAssetMsg::Cw20 {
+        contract: credits.to_string(),
+    });
+    app.execute_contract(
+        seller.clone(),
+        credits.clone(),
+        &cw20::Cw20ExecuteMsg::Send {
+            contract: contract_addr.to_string(),
+            amount: Uint128::new(10),
+            msg: to_json_binary(&ExecuteMsg::Sell {
+                orders: vec![order],
+            })
+            .unwrap(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    let buy = |app: &mut App, quantity: u128, disable_auto_retire: bool| {
+        let mut buy = buy_order_msg(1, quantity, 100);
+        buy.disable_auto_retire = disable_auto_retire;
+        app.execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirect {
+                orders: vec![buy],
+                use_balance: false,
+            },
+            &[Coin::new(quantity * 101, "uusd")],
+        )
+        .unwrap()
+    };
+    // Burning retired tokens retires as many of the credits behind them;
+    // transferred tokens leave the credits in custody.
+    buy(&mut app, 3, false);
+    assert_eq!(regen_balance(&app, &contract_addr, "C01-001"), (97, 3));
+    buy(&mut app, 4, true);
+    assert_eq!(cw20_balance(&app, &credits, &buyer), Uint128::new(4));
+    assert_eq!(regen_balance(&app, &contract_addr, "C01-001"), (97, 3));
+}
+
+/// Minimal cw1155 contract holding balances per owner and token id.
+mod mock_cw1155 {
+    use cosmwasm_schema::cw_serde;
+    use cosmwasm_std::{
+        to_json_binary, wasm_execute, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdError,
+        StdResult, Uint128,
+    };
+    use cw_storage_plus::Map;
+
+    use crate::cw1155::{Cw1155ExecuteMsg, Cw1155ReceiveMsg};
+
+    const BALANCES: Map<(&str, &str), Uint128> = Map::new("balances");
+
+    #[cw_serde]
+    pub struct InstantiateMsg {
+        pub balances: Vec<(String, String, Uint128)>,
+    }
+
+    #[cw_serde]
+    pub enum QueryMsg {
+        Balance { owner: String, token_id: String },
+    }
+
+    #[cw_serde]
+    enum ReceiverMsg {
+        Receive(Cw1155ReceiveMsg),
+    }
+
+    pub fn instantiate(
+        deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        msg: InstantiateMsg,
+    ) -> StdResult<Response> {
+        for (owner, token_id, amount) in msg.balances {
+            BALANCES.save(deps.storage, (&owner, &token_id), &amount)?;
+        }
+        Ok(Response::new())
+    }
+
+    fn debit(deps: &mut DepsMut, from: &str, token_id: &str, value: Uint128) -> StdResult<()> {
+        BALANCES.update(deps.storage, (from, token_id), |balance| {
+            StdResult::Ok(balance.unwrap_or_default().checked_sub(value)?)
+        })?;
+        Ok(())
+    }
+
+    pub fn execute(
+        mut deps: DepsMut,
+        _env: Env,
+        info: MessageInfo,
+        msg: Cw1155ExecuteMsg,
+    ) -> StdResult<Response> {
+        match msg {
+            Cw1155ExecuteMsg::SendFrom {
+                from,
+                to,
+                token_id,
+                value,
+                msg,
+            } => {
+                if info.sender != from {
+                    return Err(StdError::generic_err("unauthorized"));
+                }
+                debit(&mut deps, &from, &token_id, value)?;
+                BALANCES.update(deps.storage, (&to, &token_id), |balance| {
+                    StdResult::Ok(balance.unwrap_or_default() + value)
+                })?;
+                let mut response = Response::new();
+                if let Some(msg) = msg {
+                    let receive = ReceiverMsg::Receive(Cw1155ReceiveMsg {
+                        operator: info.sender.to_string(),
+                        from: Some(from),
+                        token_id,
+                        amount: value,
+                        msg,
+                    });
+                    response = response.add_message(wasm_execute(to, &receive, vec![])?);
+                }
+                Ok(response)
+            }
+            Cw1155ExecuteMsg::BurnFrom {
+                from,
+                token_id,
+                value,
+            } => {
+                if info.sender != from {
+                    return Err(StdError::generic_err("unauthorized"));
+                }
+                debit(&mut deps, &from, &token_id, value)?;
+                Ok(Response::new())
+            }
+        }
+    }
+
+    pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+        match msg {
+            QueryMsg::Balance { owner, token_id } => to_json_binary(
+                &BALANCES
+                    .may_load(deps.storage, (&owner, &token_id))?
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+}
+
+#[test]
+fn test_cw1155_credits_escrowed_and_delivered() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+    fund(&mut app, &buyer, 10_000);
+    let code = ContractWrapper::new(
+        mock_cw1155::execute,
+        mock_cw1155::instantiate,
+        mock_cw1155::query,
+    );
+    let code_id = app.store_code(Box::new(code));
+    let credits = app
+        .instantiate_contract(
+            code_id,
+            seller.clone(),
+            &mock_cw1155::InstantiateMsg {
+                balances: vec![(seller.to_string(), "C01-001".to_string(), Uint128::new(10))],
+            },
+            &[],
+            "Credits",
+            None,
+        )
+        .unwrap();
+    let balance = |app: &App, owner: &Addr| -> Uint128 {
+        app.wrap()
+            .query_wasm_smart(
+                &credits,
+                &mock_cw1155::QueryMsg::Balance {
+                    owner: owner.to_string(),
+                    token_id: "C01-001".to_string(),
+                },
+            )
+            .unwrap()
+    };
+
+    let mut order = sell_order_msg("C01-001", 10, 100);
+    order.disable_auto_retire = true;
+    order.asset = Some(CreditAssetMsg::Cw1155 {
+        contract: credits.to_string(),
+        token_id: "C01-001".to_string(),
+    });
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::AddCreditToken {
+            batch_denom: "C01-001".to_string(),
+            asset: order.asset.clone().unwrap(),
+            custodial: false,
+        },
+        &[],
+    )
+    .unwrap();
+
+    // Only the token contract can vouch for the seller of escrowed credits.
+    let attacker = Addr::unchecked("attacker");
+    let spoof = |app: &mut App, batch: Vec<(String, Uint128)>, order: SellOrderMsg| {
+        app.execute_contract(
+            attacker.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BatchReceive(Cw1155BatchReceiveMsg {
+                operator: attacker.to_string(),
+                from: Some(seller.to_string()),
+                batch,
+                msg: to_json_binary(&ExecuteMsg::Sell {
+                    orders: vec![order],
+                })
+                .unwrap(),
+            }),
+            &[],
+        )
+        .unwrap_err()
+    };
+    let err = spoof(&mut app, vec![], sell_order_msg("C01-001", 10, 1));
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::InvalidFunds { .. })
+    ));
+    let err = spoof(
+        &mut app,
+        vec![("C01-001".to_string(), Uint128::new(10))],
+        order.clone(),
+    );
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::Unauthorized {})
+    ));
+
+    let res = app
+        .execute_contract(
+            seller.clone(),
+            credits.clone(),
+            &Cw1155ExecuteMsg::SendFrom {
+                from: seller.to_string(),
+                to: contract_addr.to_string(),
+                token_id: "C01-001".to_string(),
+                value: Uint128::new(10),
+                msg: Some(
+                    to_json_binary(&ExecuteMsg::Sell {
+                        orders: vec![order],
+                    })
+                    .unwrap(),
+                ),
+            },
+            &[],
+        )
+        .unwrap();
+    assert_eq!(
+        event_attr(event(&res, "wasm-sell_order_created"), "asset"),
+        "cw1155"
+    );
+    assert_eq!(balance(&app, &contract_addr), Uint128::new(10));
+
+    let buy = |app: &mut App, quantity: u128, disable_auto_retire: bool| {
+        let mut buy = buy_order_msg(1, quantity, 100);
+        buy.disable_auto_retire = disable_auto_retire;
+        app.execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirect {
+                orders: vec![buy],
+                use_balance: false,
+            },
+            &[Coin::new(quantity * 101, "uusd")],
+        )
+        .unwrap()
+    };
+    buy(&mut app, 4, true);
+    buy(&mut app, 2, false);
+    assert_eq!(balance(&app, &buyer), Uint128::new(4));
+    assert_eq!(balance(&app, &contract_addr), Uint128::new(4));
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CancelSellOrder { sell_order_id: 1 },
+        &[],
+    )
+    .unwrap();
+    assert_eq!(balance(&app, &seller), Uint128::new(4));
+    assert_eq!(balance(&app, &contract_addr), Uint128::zero());
+}
+
+fn issue_credits(app: &mut App, owner: &Addr, batch_denom: &str, amount: u128) {
+    app.init_modules(|_, _, storage| {
+        mock_ecocredit::BATCHES
+            .save(storage, batch_denom, &())
+            .unwrap();
+        mock_ecocredit::BALANCES
+            .save(
+                storage,
+                (owner.as_str(), batch_denom),
+                &(Uint128::new(amount), Uint128::zero()),
+            )
+            .unwrap();
+    });
+}
+
+/// Tradable and retired credits of `owner`.
+fn regen_balance(app: &App, owner: &Addr, batch_denom: &str) -> (u128, u128) {
+    app.read_module(|_, _, storage| {
+        let (tradable, retired) = mock_ecocredit::BALANCES
+            .may_load(storage, (owner.as_str(), batch_denom))
+            .unwrap()
+            .unwrap_or_default();
+        (tradable.u128(), retired.u128())
+    })
+}
+
+#[test]
+fn test_regen_adapter_moves_native_credits() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr =
+        instantiate_contract_with(&mut app, seller.clone(), EcocreditAdapter::Regen);
+    fund(&mut app, &buyer, 10_000);
+
+    let sell = |app: &mut App, batch_denom: &str, quantity: u128| {
+        app.execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Sell {
+                orders: vec![sell_order_msg(batch_denom, quantity, 100)],
+            },
+            &[],
+        )
+    };
+    let err = sell(&mut app, "C01-001-20200101-20201231-001", 10).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::UnknownBatch { .. })
+    ));
+    issue_credits(&mut app, &seller, "C01-001-20200101-20201231-001", 10);
+    let err = sell(&mut app, "C01-001-20200101-20201231-001", 11).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::InsufficientCredits { .. })
+    ));
+    sell(&mut app, "C01-001-20200101-20201231-001", 10).unwrap();
+
+    let buy = |app: &mut App, buy: BuyOrderMsg| {
+        let funds = Coin::new(buy.quantity.u128() * 101, "uusd");
+        app.execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirect {
+                orders: vec![buy],
+                use_balance: false,
+            },
+            &[funds],
+        )
+    };
+    // The seller has not authorized the marketplace to send their credits.
+    let err = buy(&mut app, buy_order_msg(1, 4, 100)).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::SettlementFailed { trade_id: 1, .. })
+    ));
+
+    app.init_modules(|_, _, storage| {
+        mock_ecocredit::GRANTS
+            .save(storage, (seller.as_str(), contract_addr.as_str()), &())
+            .unwrap();
+    });
+    let mut order = buy_order_msg(1, 4, 100);
+    order.retirement_jurisdiction = Some("US-WA".to_string());
+    buy(&mut app, order).unwrap();
+    assert_eq!(
+        regen_balance(&app, &buyer, "C01-001-20200101-20201231-001"),
+        (0, 4)
+    );
+    assert_eq!(
+        regen_balance(&app, &seller, "C01-001-20200101-20201231-001"),
+        (6, 0)
+    );
+    // The buyer still pays the seller.
+    assert_eq!(
+        app.wrap().query_balance(&seller, "uusd").unwrap().amount,
+        Uint128::new(396)
+    );
+}
+
+#[test]
+fn test_retry_failed_settlement() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr =
+        instantiate_contract_with(&mut app, seller.clone(), EcocreditAdapter::Regen);
+    fund(&mut app, &buyer, 10_000);
+    issue_credits(&mut app, &seller, "C01-001-20200101-20201231-001", 10);
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![sell_order_msg("C01-001-20200101-20201231-001", 10, 100)],
+        },
+        &[],
+    )
+    .unwrap();
+
+    // Without a grant the deliveries fail, but the trades stand and their
+    // payments are held until the credits are delivered.
+    let buy = |app: &mut App, quantity: u128| {
+        let mut order = buy_order_msg(1, quantity, 100);
+        order.retirement_jurisdiction = Some("US-WA".to_string());
+        order.on_delivery_failure = DeliveryFailure::Retry;
+        app.execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirect {
+                orders: vec![order],
+                use_balance: false,
+            },
+            &[Coin::new(101 * quantity, "uusd")],
+        )
+        .unwrap()
+    };
+    let res = buy(&mut app, 4);
+    assert!(res.has_event(&Event::new("wasm-settlement_failed").add_attribute("trade_id", "1")));
+    buy(&mut app, 3);
+    assert_eq!(
+        app.wrap().query_balance(&seller, "uusd").unwrap().amount,
+        Uint128::zero()
+    );
+    assert_eq!(
+        regen_balance(&app, &buyer, "C01-001-20200101-20201231-001"),
+        (0, 0)
+    );
+    let failed = |app: &App| -> SettlementsResponse {
+        app.wrap()
+            .query_wasm_smart(
+                &contract_addr,
+                &QueryMsg::FailedSettlements {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap()
+    };
+    let settlements = failed(&app).settlements;
+    assert_eq!(settlements.len(), 2);
+    assert_eq!(settlements[0].trade_id, 1);
+    assert!(settlements[0].error.is_some());
+    assert_eq!(
+        settlements[1].held,
+        Some(HeldPayment {
+            cost: Coin::new(303, "uusd"),
+            proceeds: Coin::new(297, "uusd"),
+            from_balance: false,
+        })
+    );
+
+    // Only the payer can take the payment of a failed trade back.
+    let refund = |app: &mut App, sender: &Addr, id| {
+        app.execute_contract(
+            sender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RefundSettlement { id },
+            &[],
+        )
+    };
+    let err = refund(&mut app, &seller, 2).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::Unauthorized {})
+    ));
+    let res = refund(&mut app, &buyer, 2).unwrap();
+    assert!(res.has_event(
+        &Event::new("wasm-settlement_refunded")
+            .add_attribute("trade_id", "2")
+            .add_attribute("refund", "303uusd")
+    ));
+    assert_eq!(
+        app.wrap().query_balance(&buyer, "uusd").unwrap().amount,
+        Uint128::new(10_000 - 404)
+    );
+    // The trade stays on record, marked as refunded.
+    let res: TradeResponse = app
+        .wrap()
+        .query_wasm_smart(&contract_addr, &QueryMsg::Trade { trade_id: 2 })
+        .unwrap();
+    assert!(res.trade.refunded);
+
+    // A payment from the deposited balance is refunded to the balance.
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit {},
+        &[Coin::new(303, "uusd")],
+    )
+    .unwrap();
+    let mut order = buy_order_msg(1, 3, 100);
+    order.on_delivery_failure = DeliveryFailure::Retry;
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::BuyDirect {
+            orders: vec![order],
+            use_balance: true,
+        },
+        &[],
+    )
+    .unwrap();
+    assert!(failed(&app).settlements[1]
+        .held
+        .as_ref()
+        .is_some_and(|held| held.from_balance));
+    refund(&mut app, &buyer, 3).unwrap();
+    let res: BalanceResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::Balance {
+                address: buyer.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.balances, vec![Coin::new(303, "uusd")]);
+    assert_eq!(
+        app.wrap().query_balance(&buyer, "uusd").unwrap().amount,
+        Uint128::new(10_000 - 404 - 303)
+    );
+
+    let retry = |app: &mut App| {
+        app.execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::RetrySettlement { id: 1 },
+            &[],
+        )
+    };
+    // Still no grant: the settlement stays failed.
+    retry(&mut app).unwrap();
+    assert_eq!(failed(&app).settlements.len(), 1);
+    assert_eq!(
+        app.wrap().query_balance(&seller, "uusd").unwrap().amount,
+        Uint128::zero()
+    );
+
+    app.init_modules(|_, _, storage| {
+        mock_ecocredit::GRANTS
+            .save(storage, (seller.as_str(), contract_addr.as_str()), &())
+            .unwrap();
+    });
+    let res = retry(&mut app).unwrap();
+    assert!(res.has_event(&Event::new("wasm-settlement_completed").add_attribute("trade_id", "1")));
+    assert!(failed(&app).settlements.is_empty());
+    assert_eq!(
+        regen_balance(&app, &buyer, "C01-001-20200101-20201231-001"),
+        (0, 4)
+    );
+    assert_eq!(
+        app.wrap().query_balance(&seller, "uusd").unwrap().amount,
+        Uint128::new(396)
+    );
+
+    let err = retry(&mut app).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::NoFailedSettlement { trade_id: 1 })
+    ));
+    let err = refund(&mut app, &buyer, 1).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::NoFailedSettlement { trade_id: 1 })
+    ));
+}
+
+#[test]
+fn test_native_marketplace_proto_json() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+    fund(&mut app, &buyer, 1_000);
+
+    let sell = r#"{"@type":"/regen.ecocredit.marketplace.v1.MsgSell","seller":"seller","orders":[{"batch_denom":"C01-001","quantity":"10","ask_price":{"denom":"uusd","amount":"100"},"disable_auto_retire":true,"expiration":"2030-01-02T03:04:05.500Z"}]}"#;
+    let msg: MarketplaceMsg = from_json(sell).unwrap();
+    assert_eq!(to_json_string(&msg).unwrap(), sell);
+
+    let err = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Marketplace(msg.clone()),
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::Unauthorized {})
+    ));
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Marketplace(msg),
+        &[],
+    )
+    .unwrap();
+
+    let res: QuerySellOrderResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::SellOrderInfo { sell_order_id: 1 },
+        )
+        .unwrap();
+    assert_eq!(
+        to_json_string(&res).unwrap(),
+        r#"{"sell_order":{"id":"1","seller":"seller","batch_denom":"C01-001","quantity":"10","ask_denom":"uusd","ask_amount":"100","disable_auto_retire":true,"expiration":"2030-01-02T03:04:05.500Z"}}"#
+    );
+    let res: SellOrderResponse = app
+        .wrap()
+        .query_wasm_smart(&contract_addr, &QueryMsg::SellOrder { sell_order_id: 1 })
+        .unwrap();
+    assert_eq!(
+        res.sell_order.expiration,
+        Some(Timestamp::from_seconds(1893553445).plus_nanos(500_000_000))
+    );
+    assert_eq!(
+        res.sell_order.expiration.map(format_rfc3339).unwrap(),
+        "2030-01-02T03:04:05.500Z"
+    );
+
+    // The contract's own messages take proto JSON ids too.
+    let buy: ExecuteMsg = from_json(
+        r#"{"buy_direct":{"orders":[{"sell_order_id":"1","quantity":"4","bid_price":{"denom":"uusd","amount":"100"},"disable_auto_retire":true,"max_fee_amount":{"denom":"uusd","amount":"10"}}],"use_balance":false}}"#,
+    )
+    .unwrap();
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &buy,
+        &[Coin::new(404, "uusd")],
+    )
+    .unwrap();
+    let query: QueryMsg = from_json(r#"{"sell_order":{"sell_order_id":"1"}}"#).unwrap();
+    assert_eq!(query, QueryMsg::SellOrder { sell_order_id: 1 });
+    assert_eq!(
+        to_json_string(&QueryMsg::Trades {
+            start_after: Some(2),
+            limit: None,
+        })
+        .unwrap(),
+        r#"{"trades":{"start_after":"2","limit":null}}"#
+    );
+    let offer: ExecuteMsg = from_json(
+        r#"{"make_offer":{"sell_order_id":"1","quantity":"1","price":{"denom":"uusd","amount":"90"},"expiration":null,"merkle_proof":null}}"#,
+    )
+    .unwrap();
+    assert!(matches!(
+        offer,
+        ExecuteMsg::MakeOffer {
+            sell_order_id: 1,
+            ..
+        }
+    ));
+
+    // Canonical proto JSON names fields in lowerCamelCase, which reads the
+    // same as the snake_case names.
+    let snake: MarketplaceMsg = from_json(
+        r#"{"@type":"/regen.ecocredit.marketplace.v1.MsgBuyDirect","buyer":"buyer","orders":[{"sell_order_id":"1","quantity":"4","bid_price":{"denom":"uusd","amount":"100"},"disable_auto_retire":true,"retirement_jurisdiction":"US-WA","max_fee_amount":{"denom":"uusd","amount":"10"}}]}"#,
+    )
+    .unwrap();
+    let camel: MarketplaceMsg = from_json(
+        r#"{"@type":"/regen.ecocredit.marketplace.v1.MsgBuyDirect","buyer":"buyer","orders":[{"sellOrderId":"1","quantity":"4","bidPrice":{"denom":"uusd","amount":"100"},"disableAutoRetire":true,"retirementJurisdiction":"US-WA","maxFeeAmount":{"denom":"uusd","amount":"10"}}]}"#,
+    )
+    .unwrap();
+    assert_eq!(camel, snake);
+    let res: QuerySellOrdersResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::SellOrderInfosByBatch {
+                batch_denom: "C01-001".to_string(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(res.sell_orders.len(), 1);
+    assert_eq!(res.sell_orders[0].quantity, Uint128::new(6));
+    assert_eq!(
+        res.sell_orders[0].to_sell_order_msg().ask_price,
+        Coin::new(100, "uusd")
+    );
+
+    // Ids and timestamps are written back in the form they are read in.
+    let update = r#"{"update_sell_orders":{"updates":[{"sell_order_id":"1","new_quantity":"5","new_ask_price":null,"disable_auto_retire":null,"new_expiration":"2031-02-03T04:05:06Z"}]}}"#;
+    let msg: ExecuteMsg = from_json(update).unwrap();
+    assert_eq!(to_json_string(&msg).unwrap(), update);
+    let msg: ExecuteMsg = from_json(r#"{"cancel_sell_order":{"sell_order_id":1}}"#).unwrap();
+    assert_eq!(
+        to_json_string(&msg).unwrap(),
+        r#"{"cancel_sell_order":{"sell_order_id":"1"}}"#
+    );
+
+    // Proto quantities are decimals, which must be whole credits.
+    let order = |quantity: &str| {
+        from_json::<MsgSellOrder>(format!(
+            r#"{{"batch_denom":"C01-001","quantity":"{}","ask_price":{{"denom":"uusd","amount":"100"}}}}"#,
+            quantity
+        ))
+    };
+    let whole = order("10.000").unwrap();
+    assert_eq!(whole.quantity, Uint128::new(10));
+    assert!(to_json_string(&whole)
+        .unwrap()
+        .contains(r#""quantity":"10""#));
+    let err = order("10.5").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("fractional quantities are unsupported: 10.5"));
+    let err = order("10.x").unwrap_err();
+    assert!(err.to_string().contains("10.x is not a decimal number"));
+}
+
+#[test]
+fn test_rfc3339() {
+    let at = |seconds: u64, nanos: u64| Timestamp::from_seconds(seconds).plus_nanos(nanos);
+    for (timestamp, formatted) in [
+        (at(0, 0), "1970-01-01T00:00:00Z"),
+        (at(951_782_400, 0), "2000-02-29T00:00:00Z"),
+        (at(1_709_208_000, 120_000_000), "2024-02-29T12:00:00.120Z"),
+        (at(1_893_553_445, 500_000), "2030-01-02T03:04:05.000500Z"),
+        (at(4_102_444_799, 1), "2099-12-31T23:59:59.000000001Z"),
+        (at(16_756_761_599, 0), "2500-12-31T23:59:59Z"),
+    ] {
+        assert_eq!(format_rfc3339(timestamp), formatted);
+        assert_eq!(parse_rfc3339(formatted), Some(timestamp));
+    }
+    // Every day formats and parses back over four centuries.
+    for day in (0..146_097u64).step_by(7) {
+        let timestamp = at(day * 86_400 + 3_723, 0);
+        assert_eq!(parse_rfc3339(&format_rfc3339(timestamp)), Some(timestamp));
+    }
+
+    assert_eq!(
+        parse_rfc3339("2024-02-29T13:30:00.12+01:30"),
+        Some(at(1_709_208_000, 120_000_000))
+    );
+    assert_eq!(parse_rfc3339("1969-12-31t19:00:00-05:00"), Some(at(0, 0)));
+    for invalid in [
+        "1969-12-31T23:59:59Z",
+        // Past the last nanosecond a timestamp can hold.
+        "9999-12-31T23:59:59Z",
+        "2023-02-29T00:00:00Z",
+        "2024-04-31T00:00:00Z",
+        "2024-13-01T00:00:00Z",
+        "2024-00-10T00:00:00Z",
+        "2024-01-01T24:00:00Z",
+        "2024-01-01T23:59:60Z",
+        "2024-01-01T00:00:00.Z",
+        "2024-01-01T00:00:00.0000000001Z",
+        "2024-01-01T00:00:00",
+        "2024-01-01T00:00:00+0100",
+        "24-01-01T00:00:00Z",
+        "2024-1-01T00:00:00Z",
+        "2024-01-01 00:00:00Z",
+    ] {
+        assert_eq!(parse_rfc3339(invalid), None, "{}", invalid);
+    }
+}
+
+#[test]
+fn test_import_native_genesis_in_batches() {
+    let mut app = mock_app();
+    let authority = Addr::unchecked("authority");
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    fund(&mut app, &buyer, 10_000);
+    let code = ContractWrapper::new(execute, instantiate, query).with_reply(reply);
+    let code_id = app.store_code(Box::new(code));
+    let contract_addr = app
+        .instantiate_contract(
+            code_id,
+            authority.clone(),
+            &InstantiateMsg {
+                fee_params: FeeParams {
+                    buyer_percentage_fee: Decimal::zero(),
+                    seller_percentage_fee: Decimal::zero(),
+                },
+                authority: None,
+                ecocredit: EcocreditAdapter::Disabled,
+                import_genesis: true,
+                crank_reward_share: Decimal::zero(),
+                state: None,
+            },
+            &[],
+            "Regen Marketplace",
+            None,
+        )
+        .unwrap();
+
+    let import = |app: &mut App, sender: &Addr, genesis: &str| {
+        app.execute_contract(
+            sender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::ImportBatch {
+                genesis: from_json(genesis).unwrap(),
+            },
+            &[],
+        )
+    };
+    let finalize = |app: &mut App| {
+        app.execute_contract(
+            authority.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Finalize {},
+            &[],
+        )
+    };
+
+    let err = app
+        .execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Sell {
+                orders: vec![sell_order_msg("C01-001", 10, 100)],
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::ImportInProgress {})
+    ));
+
+    let seller_bytes = app.api().addr_canonicalize(seller.as_str()).unwrap();
+    let orders = format!(
+        r#"{{"regen.ecocredit.marketplace.v1.SellOrder":[{{"id":"7","seller":"{}","batch_key":"3","quantity":"25.000000","market_id":"2","ask_amount":"100","disable_auto_retire":false,"expiration":null,"maker":true}}]}}"#,
+        Binary::from(seller_bytes.as_slice()).to_base64()
+    );
+    let err = import(&mut app, &seller, &orders).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::Unauthorized {})
+    ));
+    import(&mut app, &authority, &orders).unwrap();
+    import(
+        &mut app,
+        &authority,
+        r#"{
+            "regen.ecocredit.marketplace.v1.AllowedDenom": [{"bank_denom": "uusd", "display_denom": "USD", "exponent": 6}],
+            "regen.ecocredit.marketplace.v1.Market": [{"id": "2", "credit_type_abbrev": "C", "bank_denom": "uusd", "precision_modifier": 0}],
+            "regen.ecocredit.marketplace.v1.FeeParams": {"buyer_percentage_fee": "0.010000000000000000", "seller_percentage_fee": "0.010000000000000000"},
+            "regen.ecocredit.marketplace.v1.BuyOrder": []
+        }"#,
+    )
+    .unwrap();
+
+    // The order's batch is still missing.
+    let err = finalize(&mut app).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::InvalidGenesis { .. })
+    ));
+    import(
+        &mut app,
+        &authority,
+        r#"{"regen.ecocredit.v1.Batch": [{"key": "3", "issuer": "aXNzdWVy", "denom": "C01-001-20200101-20201231-001"}]}"#,
+    )
+    .unwrap();
+    let res = finalize(&mut app).unwrap();
+    assert_eq!(
+        event_attr(event(&res, "wasm-genesis_import_finalized"), "sell_orders"),
+        "1"
+    );
+    let err = import(&mut app, &authority, "{}").unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::ImportFinished {})
+    ));
+
+    let res: QuerySellOrderResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::SellOrderInfo { sell_order_id: 7 },
+        )
+        .unwrap();
+    assert_eq!(res.sell_order.seller, "seller");
+    assert_eq!(res.sell_order.batch_denom, "C01-001-20200101-20201231-001");
+    assert_eq!(res.sell_order.quantity, Uint128::new(25));
+
+    // Trading opens on the imported state: the funds cover the imported 1%
+    // buyer fee, and new orders continue the imported ids.
+    let res = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirect {
+                orders: vec![buy_order_msg(7, 10, 100)],
+                use_balance: false,
+            },
+            &[Coin::new(1010, "uusd")],
+        )
+        .unwrap();
+    assert_eq!(
+        event_attr(event(&res, "wasm-sell_order_filled"), "sell_order_id"),
+        "7"
+    );
+    let res = app
+        .execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Sell {
+                orders: vec![sell_order_msg("C01-001-20200101-20201231-001", 5, 100)],
+            },
+            &[],
+        )
+        .unwrap();
+    let created = event(&res, "wasm-sell_order_created");
+    assert_eq!(event_attr(created, "sell_order_id"), "8");
+    assert_eq!(event_attr(created, "market_id"), "2");
+}
+
+#[test]
+fn test_validation_reports_order_index_and_field() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+
+    let execute = |app: &mut App, msg: &ExecuteMsg| {
+        app.execute_contract(seller.clone(), contract_addr.clone(), msg, &[])
+            .unwrap_err()
+            .downcast::<ContractError>()
+            .unwrap()
+    };
+
+    let err = execute(&mut app, &ExecuteMsg::Sell { orders: vec![] });
+    assert!(matches!(err, ContractError::EmptyField { field } if field == "orders"));
+
+    let err = execute(
+        &mut app,
+        &ExecuteMsg::Sell {
+            orders: vec![
+                sell_order_msg("C01-001", 100, 10),
+                sell_order_msg("C01-001", 0, 10),
+            ],
+        },
+    );
+    assert!(matches!(
+        err,
+        ContractError::InvalidOrderField { index: 1, field, .. } if field == "quantity"
+    ));
+
+    let mut expired = sell_order_msg("C01-001", 100, 10);
+    expired.expiration = Some(app.block_info().time);
+    let err = execute(
+        &mut app,
+        &ExecuteMsg::Sell {
+            orders: vec![expired],
+        },
+    );
+    assert!(matches!(
+        err,
+        ContractError::InvalidOrderField { index: 0, field, .. } if field == "expiration"
+    ));
+
+    let err = execute(
+        &mut app,
+        &ExecuteMsg::BuyDirect {
+            orders: vec![buy_order_msg(1, 10, 10), buy_order_msg(1, 5, 10)],
+            use_balance: false,
+        },
+    );
+    assert!(matches!(
+        err,
+        ContractError::DuplicateSellOrder {
+            index: 1,
+            sell_order_id: 1
+        }
+    ));
+
+    let err = execute(
+        &mut app,
+        &ExecuteMsg::GovSendFromFeePool {
+            recipient: "x".to_string(),
+            coins: vec![Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::new(1),
+            }],
+        },
+    );
+    assert!(matches!(err, ContractError::InvalidRecipient { .. }));
+
+    let err = execute(
+        &mut app,
+        &ExecuteMsg::GovSetFeeParams {
+            fees: FeeParams {
+                buyer_percentage_fee: Decimal::percent(101),
+                seller_percentage_fee: Decimal::zero(),
+            },
+        },
+    );
+    assert!(matches!(err, ContractError::InvalidFee { .. }));
+
+    // Nothing was written by the rejected messages.
+    let res: SellOrdersResponse = app
+        .wrap()
+        .query_wasm_smart(
+            &contract_addr,
+            &QueryMsg::SellOrders {
+                start_after: None,
+                limit: None,
+                include_private: None,
+            },
+        )
+        .unwrap();
+    assert!(res.sell_orders.is_empty());
+}
+
+#[test]
+fn test_order_lifecycle_events() {
+    let mut app = mock_app();
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, seller.clone());
+
+    let res = app
+        .execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Sell {
+                orders: vec![sell_order_msg("C01-001", 100, 10)],
+            },
+            &[],
+        )
+        .unwrap();
+    let created = event(&res, "wasm-sell_order_created");
+    assert_eq!(event_attr(created, "schema_version"), "1");
+    assert_eq!(event_attr(created, "sell_order_id"), "1");
+    assert_eq!(event_attr(created, "seller"), "seller");
+    assert_eq!(event_attr(created, "batch_denom"), "C01-001");
+    assert_eq!(event_attr(created, "quantity"), "100");
+    assert_eq!(event_attr(created, "ask_amount"), "10");
+    assert_eq!(event_attr(created, "ask_denom"), "uusd");
+
+    let res = app
+        .execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::UpdateSellOrders {
+                updates: vec![crate::msg::UpdateSellOrderMsg {
+                    sell_order_id: 1,
+                    new_quantity: Some(Uint128::new(80)),
+                    new_ask_price: Some(Coin {
+                        denom: "uusd".to_string(),
+                        amount: Uint128::new(12),
+                    }),
+                    disable_auto_retire: Some(true),
+                    new_expiration: None,
+                }],
+            },
+            &[],
+        )
+        .unwrap();
+    let updated = event(&res, "wasm-sell_order_updated");
+    assert_eq!(event_attr(updated, "quantity_before"), "100");
+    assert_eq!(event_attr(updated, "quantity_after"), "80");
+    assert_eq!(event_attr(updated, "ask_amount_before"), "10");
+    assert_eq!(event_attr(updated, "ask_amount_after"), "12");
+    assert_eq!(event_attr(updated, "disable_auto_retire_before"), "false");
+    assert_eq!(event_attr(updated, "disable_auto_retire_after"), "true");
+
+    fund(&mut app, &buyer, 360);
+    let res = app
+        .execute_contract(
+            buyer,
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirect {
+                orders: vec![buy_order_msg(1, 30, 12)],
+                use_balance: false,
+            },
+            &[Coin::new(360, "uusd")],
+        )
+        .unwrap();
+    let filled = event(&res, "wasm-sell_order_filled");
+    assert_eq!(event_attr(filled, "sell_order_id"), "1");
+    assert_eq!(event_attr(filled, "buyer"), "buyer");
+    assert_eq!(event_attr(filled, "quantity"), "30");
+    assert_eq!(event_attr(filled, "remaining_quantity"), "50");
+    assert_eq!(event_attr(filled, "ask_amount"), "12");
+    assert_eq!(event_attr(filled, "ask_denom"), "uusd");
+    assert!(filled.attributes.iter().any(|attr| attr.key == "buyer_fee"));
+    assert!(filled
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "seller_fee"));
+
+    let res = app
+        .execute_contract(
+            seller,
+            contract_addr,
+            &ExecuteMsg::CancelSellOrder { sell_order_id: 1 },
+            &[],
+        )
+        .unwrap();
+    let cancelled = event(&res, "wasm-sell_order_cancelled");
+    assert_eq!(event_attr(cancelled, "sell_order_id"), "1");
+    assert_eq!(event_attr(cancelled, "quantity"), "50");
+}
+
+#[test]
+fn test_governance_events() {
+    let mut app = mock_app();
+    let authority = Addr::unchecked("authority");
+    let contract_addr = instantiate_contract(&mut app, authority.clone());
+
+    let res = app
+        .execute_contract(
+            authority.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddAllowedDenom {
+                bank_denom: "uregen".to_string(),
+                display_denom: "REGEN".to_string(),
+                exponent: 6,
+                kind: DenomKind::Native,
+            },
+            &[],
+        )
+        .unwrap();
+    let allowed = event(&res, "wasm-denom_allowed");
+    assert_eq!(event_attr(allowed, "bank_denom"), "uregen");
+    assert_eq!(event_attr(allowed, "exponent"), "6");
+
+    // A purchase of 10 credits at 100 puts 20uusd of fees in the pool.
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    fund(&mut app, &buyer, 1_010);
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![sell_order_msg("C01-001", 10, 100)],
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::BuyDirect {
+            orders: vec![buy_order_msg(1, 10, 100)],
+            use_balance: false,
+        },
+        &[Coin::new(1_010, "uusd")],
+    )
+    .unwrap();
+
+    let send = |amount: u128| ExecuteMsg::GovSendFromFeePool {
+        recipient: "community".to_string(),
+        coins: vec![Coin::new(amount, "uusd")],
+    };
+    let fee_params = ExecuteMsg::GovSetFeeParams {
+        fees: FeeParams {
+            buyer_percentage_fee: Decimal::one(),
+            seller_percentage_fee: Decimal::one(),
+        },
+    };
+    for msg in [&fee_params, &send(5)] {
+        let err = app
+            .execute_contract(buyer.clone(), contract_addr.clone(), msg, &[])
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ContractError>(),
+            Some(ContractError::Unauthorized {})
+        ));
+    }
+    let err = app
+        .execute_contract(authority.clone(), contract_addr.clone(), &send(21), &[])
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::InsufficientBalance { .. })
+    ));
+
+    let res = app
+        .execute_contract(
+            authority.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::GovSetFeeParams {
+                fees: FeeParams {
+                    buyer_percentage_fee: Decimal::percent(2),
+                    seller_percentage_fee: Decimal::percent(3),
+                },
+            },
+            &[],
+        )
+        .unwrap();
+    let updated = event(&res, "wasm-fee_params_updated");
+    assert_eq!(event_attr(updated, "buyer_percentage_fee_before"), "0.01");
+    assert_eq!(event_attr(updated, "buyer_percentage_fee_after"), "0.02");
+    assert_eq!(event_attr(updated, "seller_percentage_fee_after"), "0.03");
+
+    let res = app
+        .execute_contract(authority, contract_addr, &send(5), &[])
+        .unwrap();
+    let sent = event(&res, "wasm-fee_pool_sent");
+    assert_eq!(event_attr(sent, "recipient"), "community");
+    assert_eq!(event_attr(sent, "coins"), "5uusd");
+    assert_eq!(
+        app.wrap()
+            .query_balance("community", "uusd")
+            .unwrap()
+            .amount,
+        Uint128::new(5)
+    );
+}
+
+fn signed_sell_order(app: &App, contract_addr: &Addr, seller: &str, nonce: u64) -> SignedSellOrder {
+    SignedSellOrder {
+        marketplace: contract_addr.to_string(),
+        chain_id: app.block_info().chain_id,
+        seller: seller.to_string(),
+        batch_denom: "C01-001".to_string(),
+        quantity: Uint128::new(100),
+        ask_price: Coin {
+            denom: "uusd".to_string(),
+            amount: Uint128::new(10),
+        },
+        disable_auto_retire: false,
+        expiration: app.block_info().time.plus_seconds(3600),
+        nonce,
+    }
+}
+
+#[test]
+fn test_buy_with_signed_order() {
+    let mut app = mock_app();
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, Addr::unchecked("authority"));
+    let (key, seller) = signer(7);
+
+    let order = signed_sell_order(&app, &contract_addr, &seller, 1);
+    let (signature, pubkey) = sign_order(&key, &order);
+    let buy = |quantity| ExecuteMsg::BuyWithSignedOrder {
+        order: Box::new(order.clone()),
+        signature: signature.clone(),
+        pubkey: pubkey.clone(),
+        buy: signed_buy(quantity),
+    };
+
+    fund(&mut app, &buyer, 1000);
+    let res = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &buy(60),
+            &[Coin::new(600, "uusd")],
+        )
+        .unwrap();
+    let filled = event(&res, "wasm-signed_order_filled");
+    assert_eq!(event_attr(filled, "seller"), seller);
+    assert_eq!(event_attr(filled, "nonce"), "1");
+    assert_eq!(event_attr(filled, "quantity"), "60");
+    assert_eq!(event_attr(filled, "remaining_quantity"), "40");
+
+    // Replaying the signature cannot sell more than was signed for.
+    let err = app
+        .execute_contract(buyer.clone(), contract_addr.clone(), &buy(50), &[])
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::InsufficientSellOrderQuantity)
+    ));
+    app.execute_contract(
+        buyer,
+        contract_addr.clone(),
+        &buy(40),
+        &[Coin::new(400, "uusd")],
+    )
+    .unwrap();
+    let balance = app.wrap().query_balance(&seller, "uusd").unwrap();
+    assert_eq!(balance.amount, Uint128::new(1000));
+
+    let res: SignedOrderResponse = app
+        .wrap()
+        .query_wasm_smart(&contract_addr, &QueryMsg::SignedOrder { seller, nonce: 1 })
+        .unwrap();
+    assert_eq!(res.status.filled, Uint128::new(100));
+}
+
+#[test]
+fn test_signed_order_rejections() {
+    let mut app = mock_app();
+    let buyer = Addr::unchecked("buyer");
+    let contract_addr = instantiate_contract(&mut app, Addr::unchecked("authority"));
+    let (key, seller) = signer(7);
+
+    let assert_rejected = |app: &mut App, msg: &ExecuteMsg| {
+        let err = app
+            .execute_contract(buyer.clone(), contract_addr.clone(), msg, &[])
+            .unwrap_err();
+        err.downcast::<ContractError>().unwrap()
+    };
+
+    // Terms changed after signing.
+    let order = signed_sell_order(&app, &contract_addr, &seller, 1);
+    let (signature, pubkey) = sign_order(&key, &order);
+    let mut tampered = order.clone();
+    tampered.ask_price.amount = Uint128::new(1);
+    let err = assert_rejected(
+        &mut app,
+        &ExecuteMsg::BuyWithSignedOrder {
+            order: Box::new(tampered),
+            signature: signature.clone(),
+            pubkey: pubkey.clone(),
+            buy: signed_buy(10),
+        },
+    );
+    assert!(matches!(err, ContractError::InvalidSignedOrder { .. }));
+
+    // Signed by a key that does not belong to the named seller.
+    let (_, other_seller) = signer(8);
+    let other = signed_sell_order(&app, &contract_addr, &other_seller, 2);
+    let (other_signature, _) = sign_order(&key, &other);
+    let err = assert_rejected(
+        &mut app,
+        &ExecuteMsg::BuyWithSignedOrder {
+            order: Box::new(other),
+            signature: other_signature,
+            pubkey: pubkey.clone(),
+            buy: signed_buy(10),
+        },
+    );
+    assert!(matches!(err, ContractError::InvalidSignedOrder { .. }));
+
+    // Cancelled by the seller before anyone bought.
+    app.execute_contract(
+        Addr::unchecked(&seller),
+        contract_addr.clone(),
+        &ExecuteMsg::CancelSignedOrders { nonces: vec![1] },
+        &[],
+    )
+    .unwrap();
+    let err = assert_rejected(
+        &mut app,
+        &ExecuteMsg::BuyWithSignedOrder {
+            order: Box::new(order),
+            signature,
+            pubkey,
+            buy: signed_buy(10),
+        },
+    );
+    assert!(matches!(
+        err,
+        ContractError::SignedOrderCancelled { nonce: 1 }
+    ));
+}
+
+/// Every page of every section, in an order `ImportState` accepts.
+fn export_state(app: &App, contract_addr: &Addr) -> Vec<StateRecords> {
+    let sections = [
+        StateSection::FeeParams,
+        StateSection::FeePool,
+        StateSection::Sequences,
+        StateSection::AllowedDenoms,
+        StateSection::Batches,
+        StateSection::Markets,
+        StateSection::SellOrders,
+        StateSection::Balances,
+        StateSection::Offers,
+        StateSection::CollectionBids,
+        StateSection::Bundles,
+        StateSection::Trades,
+        StateSection::PurchaseAllowances,
+        StateSection::SignedOrders,
+        StateSection::Pauses,
+        StateSection::Hooks,
+        StateSection::CreditTokens,
+        StateSection::Settlements,
+        StateSection::CrankCursor,
+        StateSection::ContractVersion,
+        StateSection::GenesisImport,
+        StateSection::Config,
+    ];
+    let mut pages = vec![];
+    for section in sections {
+        let mut start_after = None;
+        loop {
+            let res: ExportStateResponse = app
+                .wrap()
+                .query_wasm_smart(
+                    contract_addr,
+                    &QueryMsg::ExportState {
+                        section: section.clone(),
+                        start_after,
+                        limit: Some(2),
+                    },
+                )
+                .unwrap();
+            assert_eq!(res.version, STATE_EXPORT_VERSION);
+            pages.push(res.records);
+            match res.next {
+                Some(next) => start_after = Some(next),
+                None => break,
+            }
+        }
+    }
+    pages
+}
+
+#[test]
+fn test_export_state_round_trips_through_import() {
+    let mut app = mock_app();
+    let owner = Addr::unchecked("owner");
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let fork_owner = Addr::unchecked("fork_owner");
+    fund(&mut app, &buyer, 10_000);
+    let contract_addr = instantiate_contract(&mut app, owner.clone());
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![
+                sell_order_msg("C01-001", 10, 100),
+                sell_order_msg("C01-001", 20, 100),
+                sell_order_msg("C02-001", 30, 100),
+            ],
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::BuyDirect {
+            orders: vec![buy_order_msg(1, 10, 100)],
+            use_balance: false,
+        },
+        &[Coin::new(1010, "uusd")],
+    )
+    .unwrap();
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Deposit {},
+        &[Coin::new(300, "uusd")],
+    )
+    .unwrap();
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::MakeOffer {
+            sell_order_id: 2,
+            quantity: Uint128::new(5),
+            price: Coin::new(100, "uusd"),
+            expiration: None,
+            disable_auto_retire: false,
+            merkle_proof: None,
+            on_delivery_failure: DeliveryFailure::Revert,
+        },
+        &[Coin::new(505, "uusd")],
+    )
+    .unwrap();
+
+    let pages = export_state(&app, &contract_addr);
+    assert!(pages.contains(&StateRecords::Balances(vec![BalanceRecord {
+        account: buyer.clone(),
+        balance: Coin::new(300, "uusd"),
+    }])));
+    assert!(pages.iter().any(|page| matches!(
+        page,
+        StateRecords::Offers(offers) if offers.len() == 1 && offers[0].buyer == buyer
+    )));
+    assert!(pages.iter().any(|page| matches!(
+        page,
+        StateRecords::Trades(trades) if trades.len() == 1 && trades[0].seller == seller
+    )));
+    // Both 1% fees of the 10 credits bought stay in the pool.
+    assert!(pages.contains(&StateRecords::FeePool(vec![Coin::new(20, "uusd")])));
+    assert!(pages.iter().any(|page| matches!(
+        page,
+        StateRecords::Sequences(sequences) if sequences.sell_order == 3 && sequences.trade == 1
+    )));
+    // The filled order is gone.
+    let sell_orders: Vec<u64> = pages
+        .iter()
+        .filter_map(|page| match page {
+            StateRecords::SellOrders(sell_orders) => Some(sell_orders),
+            _ => None,
+        })
+        .flatten()
+        .map(|sell_order| sell_order.id)
+        .collect();
+    assert_eq!(sell_orders, vec![2, 3]);
+
+    let code = ContractWrapper::new(execute, instantiate, query).with_reply(reply);
+    let code_id = app.store_code(Box::new(code));
+    let fork_addr = app
+        .instantiate_contract(
+            code_id,
+            owner.clone(),
+            &InstantiateMsg {
+                fee_params: FeeParams {
+                    buyer_percentage_fee: Decimal::zero(),
+                    seller_percentage_fee: Decimal::zero(),
+                },
+                authority: Some(fork_owner.to_string()),
+                ecocredit: EcocreditAdapter::Disabled,
+                import_genesis: true,
+                crank_reward_share: Decimal::zero(),
+                state: None,
+            },
+            &[],
+            "Regen Marketplace",
+            None,
+        )
+        .unwrap();
+
+    let err = app
+        .execute_contract(
+            fork_owner.clone(),
+            fork_addr.clone(),
+            &ExecuteMsg::ImportState {
+                version: STATE_EXPORT_VERSION + 1,
+                records: pages[0].clone(),
+                import_authority: false,
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::UnsupportedExportVersion { .. })
+    ));
+    // The config goes last and hands the fork to the exported authority.
+    for records in pages.clone() {
+        let import_authority = matches!(records, StateRecords::Config(_));
+        app.execute_contract(
+            fork_owner.clone(),
+            fork_addr.clone(),
+            &ExecuteMsg::ImportState {
+                version: STATE_EXPORT_VERSION,
+                records,
+                import_authority,
+            },
+            &[],
+        )
+        .unwrap();
+    }
+    app.execute_contract(
+        owner.clone(),
+        fork_addr.clone(),
+        &ExecuteMsg::Finalize {},
+        &[],
+    )
+    .unwrap();
+
+    assert_eq!(export_state(&app, &fork_addr), pages);
+}
+
+#[test]
+fn test_export_state_instantiates_a_fork() {
+    let mut app = mock_app();
+    let owner = Addr::unchecked("owner");
+    let fork_owner = Addr::unchecked("fork_owner");
+    let seller = Addr::unchecked("seller");
+    let contract_addr = instantiate_contract(&mut app, owner.clone());
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![
+                sell_order_msg("C01-001", 10, 100),
+                sell_order_msg("C02-001", 20, 100),
+            ],
+        },
+        &[],
+    )
+    .unwrap();
+    let pages = export_state(&app, &contract_addr);
+    assert!(pages.contains(&StateRecords::GenesisImport(false)));
+    assert!(pages.iter().any(|page| matches!(
+        page,
+        StateRecords::ContractVersion(version) if version.version == CONTRACT_VERSION
+    )));
+
+    let code = ContractWrapper::new(execute, instantiate, query).with_reply(reply);
+    let code_id = app.store_code(Box::new(code));
+    let mut fork = |records: Vec<StateRecords>| {
+        app.instantiate_contract(
+            code_id,
+            owner.clone(),
+            &InstantiateMsg {
+                fee_params: FeeParams {
+                    buyer_percentage_fee: Decimal::zero(),
+                    seller_percentage_fee: Decimal::zero(),
+                },
+                authority: Some(fork_owner.to_string()),
+                ecocredit: EcocreditAdapter::Disabled,
+                import_genesis: false,
+                crank_reward_share: Decimal::zero(),
+                state: Some(StateDump {
+                    version: STATE_EXPORT_VERSION,
+                    records,
+                }),
+            },
+            &[],
+            "Regen Marketplace",
+            None,
+        )
+    };
+
+    let foreign = |page: &StateRecords| match page {
+        StateRecords::ContractVersion(version) => StateRecords::ContractVersion(ContractVersion {
+            contract: "crates.io:other".to_string(),
+            version: version.version.clone(),
+        }),
+        page => page.clone(),
+    };
+    let err = fork(pages.iter().map(foreign).collect()).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::WrongContract { .. })
+    ));
+
+    let mid_import = |page: &StateRecords| match page {
+        StateRecords::GenesisImport(_) => StateRecords::GenesisImport(true),
+        page => page.clone(),
+    };
+    let closed_addr = fork(pages.iter().map(mid_import).collect()).unwrap();
+
+    let fork_addr = fork(pages.clone()).unwrap();
+    // The fork keeps its own authority and opens to trading at once.
+    let expected: Vec<StateRecords> = pages
+        .iter()
+        .map(|page| match page {
+            StateRecords::Config(config) => StateRecords::Config(Config {
+                authority: fork_owner.clone(),
+                ..config.clone()
+            }),
+            page => page.clone(),
+        })
+        .collect();
+    assert_eq!(export_state(&app, &fork_addr), expected);
+    app.execute_contract(
+        seller.clone(),
+        fork_addr,
+        &ExecuteMsg::CancelSellOrder { sell_order_id: 1 },
+        &[],
+    )
+    .unwrap();
+
+    let err = app
+        .execute_contract(
+            seller,
+            closed_addr,
+            &ExecuteMsg::CancelSellOrder { sell_order_id: 1 },
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::ImportInProgress {})
+    ));
+}
+
+#[test]
+fn test_queries_at_past_heights() {
+    let mut app = mock_app();
+    let owner = Addr::unchecked("owner");
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    fund(&mut app, &buyer, 10_000);
+    let contract_addr = instantiate_contract(&mut app, owner.clone());
+    let next_block = |app: &mut App| {
+        app.update_block(cw_multi_test::next_block);
+        app.block_info().height
+    };
+
+    let listed = next_block(&mut app);
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![
+                sell_order_msg("C01-001", 10, 100),
+                sell_order_msg("C02-001", 10, 100),
+            ],
+        },
+        &[],
+    )
+    .unwrap();
+    let bought = next_block(&mut app);
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::BuyDirect {
+            orders: vec![buy_order_msg(1, 4, 100)],
+            use_balance: false,
+        },
+        &[Coin::new(404, "uusd")],
+    )
+    .unwrap();
+    let repriced = next_block(&mut app);
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::GovSetFeeParams {
+            fees: FeeParams {
+                buyer_percentage_fee: Decimal::percent(2),
+                seller_percentage_fee: Decimal::percent(2),
+            },
+        },
+        &[],
+    )
+    .unwrap();
+    let cancelled = next_block(&mut app);
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::CancelSellOrder { sell_order_id: 1 },
+        &[],
+    )
+    .unwrap();
+    let now = next_block(&mut app);
+
+    let quantity_at = |app: &App, height: u64| {
+        let res: SellOrderAtHeightResponse = app
+            .wrap()
+            .query_wasm_smart(
+                &contract_addr,
+                &QueryMsg::SellOrderAtHeight {
+                    sell_order_id: 1,
+                    height,
+                },
+            )
+            .unwrap();
+        res.sell_order.map(|sell_order| sell_order.quantity.u128())
+    };
+    // Heights are read at the start of the block, before its changes.
+    assert_eq!(quantity_at(&app, listed), None);
+    assert_eq!(quantity_at(&app, bought), Some(10));
+    assert_eq!(quantity_at(&app, repriced), Some(6));
+    assert_eq!(quantity_at(&app, now), None);
+
+    let by_batch = |app: &App, height: u64| {
+        let res: SellOrdersAtHeightResponse = app
+            .wrap()
+            .query_wasm_smart(
+                &contract_addr,
+                &QueryMsg::SellOrdersByBatchAtHeight {
+                    batch_denom: "C01-001".to_string(),
+                    height,
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap();
+        res.sell_orders
+            .iter()
+            .map(|sell_order| (sell_order.id, sell_order.quantity.u128()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(by_batch(&app, cancelled), vec![(1, 6)]);
+    assert_eq!(by_batch(&app, now), vec![]);
+
+    let fees_at = |app: &App, height: u64| {
+        let res: FeeParamsAtHeightResponse = app
+            .wrap()
+            .query_wasm_smart(&contract_addr, &QueryMsg::FeeParamsAtHeight { height })
+            .unwrap();
+        res.fee_params.unwrap().buyer_percentage_fee
+    };
+    assert_eq!(fees_at(&app, repriced), Decimal::percent(1));
+    assert_eq!(fees_at(&app, cancelled), Decimal::percent(2));
+
+    // A query looks up a bounded number of ids and says where to resume.
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: (0..MAX_HISTORY_SCAN)
+                .map(|_| sell_order_msg("C02-001", 1, 100))
+                .chain([sell_order_msg("C01-001", 5, 100)])
+                .collect(),
+        },
+        &[],
+    )
+    .unwrap();
+    let after = next_block(&mut app);
+    let page = |app: &App, start_after: Option<u64>| {
+        let res: SellOrdersAtHeightResponse = app
+            .wrap()
+            .query_wasm_smart(
+                &contract_addr,
+                &QueryMsg::SellOrdersByBatchAtHeight {
+                    batch_denom: "C01-001".to_string(),
+                    height: after,
+                    start_after,
+                    limit: None,
+                },
+            )
+            .unwrap();
+        let ids = res
+            .sell_orders
+            .iter()
+            .map(|order| order.id)
+            .collect::<Vec<_>>();
+        (ids, res.next_start_after)
+    };
+    assert_eq!(page(&app, None), (vec![], Some(MAX_HISTORY_SCAN)));
+    assert_eq!(
+        page(&app, Some(MAX_HISTORY_SCAN)),
+        (vec![MAX_HISTORY_SCAN + 3], None)
+    );
+}
+
+#[test]
+fn test_crank_prunes_expired_records_and_pays_keeper() {
+    let mut app = mock_app();
+    let owner = Addr::unchecked("owner");
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    let keeper = Addr::unchecked("keeper");
+    fund(&mut app, &buyer, 200_000);
+    let contract_addr = instantiate_contract(&mut app, owner.clone());
+    let expiration = app.block_info().time.plus_seconds(3600);
+
+    let err = app
+        .execute_contract(
+            keeper.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::GovSetCrankRewardShare {
+                share: Decimal::percent(10),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::Unauthorized {})
+    ));
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::GovSetCrankRewardShare {
+            share: Decimal::percent(10),
+        },
+        &[],
+    )
+    .unwrap();
+
+    let expiring = SellOrderMsg {
+        expiration: Some(expiration),
+        ..sell_order_msg("C01-001", 10, 100)
+    };
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![
+                expiring.clone(),
+                expiring,
+                sell_order_msg("C01-001", 20, 10_000),
+            ],
+        },
+        &[],
+    )
+    .unwrap();
+    // 10 credits at 10000 put 2000uusd of fees in the pool.
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::BuyDirect {
+            orders: vec![buy_order_msg(3, 10, 10_000)],
+            use_balance: false,
+        },
+        &[Coin::new(101_000, "uusd")],
+    )
+    .unwrap();
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::MakeOffer {
+            sell_order_id: 3,
+            quantity: Uint128::new(2),
+            price: Coin::new(100, "uusd"),
+            expiration: Some(expiration),
+            disable_auto_retire: false,
+            merkle_proof: None,
+            on_delivery_failure: DeliveryFailure::Revert,
+        },
+        &[Coin::new(202, "uusd")],
+    )
+    .unwrap();
+    let crank = |app: &mut App, limit: u32| {
+        app.execute_contract(
+            keeper.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Crank { limit },
+            &[],
+        )
+        .unwrap()
+    };
+
+    // Nothing has expired yet, so the keeper earns nothing.
+    let res = crank(&mut app, 10);
+    let summary = event(&res, "wasm-crank");
+    assert_eq!(event_attr(summary, "scanned"), "4");
+    assert_eq!(event_attr(summary, "expired"), "0");
+    assert_eq!(event_attr(summary, "reward"), "none");
+
+    app.update_block(|block| block.time = block.time.plus_seconds(7200));
+    // The last crank covered every record, so this one starts over and
+    // stops after order 2.
+    let res = crank(&mut app, 2);
+    let summary = event(&res, "wasm-crank");
+    assert_eq!(event_attr(summary, "expired"), "2");
+    // Expired sell orders free no funds, so they earn nothing.
+    assert_eq!(event_attr(summary, "reward"), "none");
+    assert!(app
+        .wrap()
+        .query_wasm_smart::<SellOrderResponse>(
+            &contract_addr,
+            &QueryMsg::SellOrder { sell_order_id: 1 },
+        )
+        .is_err());
+
+    let res = crank(&mut app, 10);
+    let expired = event(&res, "wasm-record_expired");
+    assert_eq!(event_attr(expired, "record"), "offer");
+    assert_eq!(event_attr(expired, "owner"), "buyer");
+    // One of the 100 parts of 10% of the 2000uusd pool.
+    assert_eq!(event_attr(event(&res, "wasm-crank"), "reward"), "2uusd");
+    assert_eq!(
+        app.wrap().query_balance(&keeper, "uusd").unwrap().amount,
+        Uint128::new(2)
+    );
+    // The offer's escrow went back to the buyer.
+    assert_eq!(
+        app.wrap().query_balance(&buyer, "uusd").unwrap().amount,
+        Uint128::new(200_000 - 101_000)
+    );
+    app.wrap()
+        .query_wasm_smart::<SellOrderResponse>(
+            &contract_addr,
+            &QueryMsg::SellOrder { sell_order_id: 3 },
+        )
+        .unwrap();
+}
+
+#[test]
+fn test_crank_reward_is_capped_by_fee_pool() {
+    let mut app = mock_app();
+    let owner = Addr::unchecked("owner");
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    fund(&mut app, &buyer, 2_000_000);
+    let contract_addr = instantiate_contract(&mut app, owner.clone());
+    app.execute_contract(
+        owner,
+        contract_addr.clone(),
+        &ExecuteMsg::GovSetCrankRewardShare {
+            share: Decimal::one(),
+        },
+        &[],
+    )
+    .unwrap();
+    app.execute_contract(
+        seller,
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![sell_order_msg("C01-001", 20, 10_000)],
+        },
+        &[],
+    )
+    .unwrap();
+    // 10 credits at 10000 put 2000uusd of fees in the pool.
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::BuyDirect {
+            orders: vec![buy_order_msg(1, 10, 10_000)],
+            use_balance: false,
+        },
+        &[Coin::new(101_000, "uusd")],
+    )
+    .unwrap();
+
+    // The buyer escrows far more than the pool holds, then cranks their own
+    // offer once it has expired.
+    app.execute_contract(
+        buyer.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::MakeOffer {
+            sell_order_id: 1,
+            quantity: Uint128::new(2),
+            price: Coin::new(500_000, "uusd"),
+            expiration: Some(app.block_info().time.plus_seconds(60)),
+            disable_auto_retire: false,
+            merkle_proof: None,
+            on_delivery_failure: DeliveryFailure::Revert,
+        },
+        &[Coin::new(1_010_000, "uusd")],
+    )
+    .unwrap();
+    app.update_block(|block| block.time = block.time.plus_seconds(120));
+    let res = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::Crank { limit: 10 },
+            &[],
+        )
+        .unwrap();
+
+    // Even with the whole pool offered, one record earns one hundredth of
+    // it, whatever its escrow.
+    let summary = event(&res, "wasm-crank");
+    assert_eq!(event_attr(summary, "expired"), "1");
+    assert_eq!(event_attr(summary, "reward"), "20uusd");
+    assert_eq!(
+        app.wrap()
+            .query_balance(&contract_addr, "uusd")
+            .unwrap()
+            .amount,
+        Uint128::new(1980)
+    );
+    assert_eq!(
+        app.wrap().query_balance(&buyer, "uusd").unwrap().amount,
+        Uint128::new(2_000_000 - 101_000 + 20)
+    );
+}
+
+/// Contract hooked to marketplace events. It records the events it is called
+/// with, or fails every call.
+mod mock_hook {
+    use cosmwasm_schema::cw_serde;
+    use cosmwasm_std::{
+        to_json_binary, Binary, Deps, DepsMut, Empty, Env, MessageInfo, Response, StdError,
+        StdResult,
+    };
+    use cw_storage_plus::Item;
+
+    use crate::msg::HookExecuteMsg;
+
+    const FAIL: Item<bool> = Item::new("fail");
+    const CALLS: Item<Vec<String>> = Item::new("calls");
+
+    #[cw_serde]
+    pub struct InstantiateMsg {
+        pub fail: bool,
+    }
+
+    pub fn instantiate(
+        deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        msg: InstantiateMsg,
+    ) -> StdResult<Response> {
+        FAIL.save(deps.storage, &msg.fail)?;
+        CALLS.save(deps.storage, &vec![])?;
+        Ok(Response::new())
+    }
+
+    pub fn execute(
+        deps: DepsMut,
+        _env: Env,
+        _info: MessageInfo,
+        msg: HookExecuteMsg,
+    ) -> StdResult<Response> {
+        if FAIL.load(deps.storage)? {
+            return Err(StdError::generic_err("hook refused"));
+        }
+        let HookExecuteMsg::MarketplaceHook(msg) = msg;
+        let mut calls = CALLS.load(deps.storage)?;
+        calls.push(msg.event().as_str().to_string());
+        CALLS.save(deps.storage, &calls)?;
+        Ok(Response::new())
+    }
+
+    pub fn query(deps: Deps, _env: Env, _msg: Empty) -> StdResult<Binary> {
+        to_json_binary(&CALLS.load(deps.storage)?)
+    }
+}
+
+#[test]
+fn test_hooks_called_on_marketplace_events() {
+    let mut app = mock_app();
+    let owner = Addr::unchecked("owner");
+    let seller = Addr::unchecked("seller");
+    let buyer = Addr::unchecked("buyer");
+    fund(&mut app, &buyer, 10_000);
+    let contract_addr = instantiate_contract(&mut app, owner.clone());
+
+    let code = ContractWrapper::new(mock_hook::execute, mock_hook::instantiate, mock_hook::query);
+    let code_id = app.store_code(Box::new(code));
+    let mut hook_contract = |fail: bool| {
+        app.instantiate_contract(
+            code_id,
+            owner.clone(),
+            &mock_hook::InstantiateMsg { fail },
+            &[],
+            "hook",
+            None,
+        )
+        .unwrap()
+    };
+    let recorder = hook_contract(false);
+    let failing = hook_contract(true);
+    let add_hook = |app: &mut App, sender: &Addr, event: HookEvent, contract: &Addr, required| {
+        app.execute_contract(
+            sender.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::AddHook {
+                event,
+                contract: contract.to_string(),
+                required,
+            },
+            &[],
+        )
+    };
+
+    let err = add_hook(&mut app, &seller, HookEvent::OrderFilled, &recorder, false).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::Unauthorized {})
+    ));
+    for event in [
+        HookEvent::OrderCreated,
+        HookEvent::OrderFilled,
+        HookEvent::CreditsRetired,
+    ] {
+        add_hook(&mut app, &owner, event, &recorder, false).unwrap();
+    }
+    add_hook(&mut app, &owner, HookEvent::OrderFilled, &failing, false).unwrap();
+    let err = add_hook(&mut app, &owner, HookEvent::OrderFilled, &failing, true).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::HookExists { .. })
+    ));
+
+    app.execute_contract(
+        seller.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::Sell {
+            orders: vec![sell_order_msg("C01-001", 10, 100)],
+        },
+        &[],
+    )
+    .unwrap();
+    // The failing optional hook is logged and the purchase goes through.
+    let res = app
+        .execute_contract(
+            buyer.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::BuyDirect {
+                orders: vec![buy_order_msg(1, 4, 100)],
+                use_balance: false,
+            },
+            &[Coin::new(404, "uusd")],
+        )
+        .unwrap();
+    let failed = event(&res, "wasm-hook_failed");
+    assert_eq!(event_attr(failed, "hook_id"), "4");
+    assert_eq!(event_attr(failed, "contract"), failing.as_str());
+    let calls: Vec<String> = app.wrap().query_wasm_smart(&recorder, &Empty {}).unwrap();
+    assert_eq!(
+        calls,
+        vec!["order_created", "order_filled", "credits_retired"]
+    );
+
+    // A required hook that fails blocks the action until it is removed.
+    add_hook(&mut app, &owner, HookEvent::OrderCancelled, &failing, true).unwrap();
+    let cancel = |app: &mut App| {
+        app.execute_contract(
+            seller.clone(),
+            contract_addr.clone(),
+            &ExecuteMsg::CancelSellOrder { sell_order_id: 1 },
+            &[],
+        )
+    };
+    cancel(&mut app).unwrap_err();
+    app.execute_contract(
+        owner.clone(),
+        contract_addr.clone(),
+        &ExecuteMsg::RemoveHook {
+            event: HookEvent::OrderCancelled,
+            contract: failing.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+    cancel(&mut app).unwrap();
+
+    let res: HooksResponse = app
+        .wrap()
+        .query_wasm_smart(&contract_addr, &QueryMsg::Hooks {})
+        .unwrap();
+    assert_eq!(res.hooks.len(), 4);
+}
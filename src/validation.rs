@@ -0,0 +1,535 @@
+use std::collections::HashSet;
+
+use cosmwasm_std::{Coin, Decimal, Deps, Env, HexBinary, Timestamp, Uint128};
+
+use crate::error::ContractError;
+use crate::msg::{
+    AllowedBuyersMsg, BuyOrderMsg, CreditAssetMsg, ExecuteMsg, SellOrderMsg, SignedOrderBuyMsg,
+    SignedSellOrder, UpdateSellOrderMsg, MIN_STATE_EXPORT_VERSION, STATE_EXPORT_VERSION,
+};
+use crate::state::{BundleItem, CollectionBidCriteria, FeeParams, PauseScope};
+
+/// Most records a single `Crank` may scan.
+pub const MAX_CRANK_LIMIT: u32 = 100;
+
+/// Checks the shape of every execute message before any state is touched.
+/// Checks that need stored state, such as ownership or allowed denoms, stay
+/// in the handlers.
+pub fn validate_execute_msg(deps: Deps, env: &Env, msg: &ExecuteMsg) -> Result<(), ContractError> {
+    match msg {
+        ExecuteMsg::Sell { orders } => validate_sell_orders(deps, env, orders),
+        ExecuteMsg::UpdateSellOrders { updates } => validate_updates(env, updates),
+        ExecuteMsg::CancelSellOrder { .. } => Ok(()),
+        ExecuteMsg::BuyDirect { orders, .. } => validate_buy_orders(deps, orders),
+        ExecuteMsg::AddAllowedDenom {
+            bank_denom,
+            display_denom,
+            ..
+        } => {
+            ensure_not_empty("bank_denom", bank_denom)?;
+            ensure_not_empty("display_denom", display_denom)
+        }
+        ExecuteMsg::RemoveAllowedDenom { denom } => ensure_not_empty("denom", denom),
+        ExecuteMsg::GovSetFeeParams { fees } => validate_fee_params(fees),
+        ExecuteMsg::GovSendFromFeePool { recipient, coins } => {
+            deps.api
+                .addr_validate(recipient)
+                .map_err(|_| ContractError::InvalidRecipient {
+                    recipient: recipient.clone(),
+                })?;
+            validate_coins(coins)
+        }
+        ExecuteMsg::BuyWithSignedOrder { order, buy, .. } => {
+            validate_signed_order(deps, env, order, buy)
+        }
+        ExecuteMsg::CancelSignedOrders { nonces } => {
+            if nonces.is_empty() {
+                return Err(ContractError::EmptyField {
+                    field: "nonces".to_string(),
+                });
+            }
+            Ok(())
+        }
+        ExecuteMsg::Pause { scope } | ExecuteMsg::Unpause { scope } => validate_pause_scope(scope),
+        ExecuteMsg::Deposit {} => Ok(()),
+        ExecuteMsg::Withdraw { coins } => validate_coins(coins),
+        ExecuteMsg::GrantPurchaseAllowance {
+            spender,
+            denom,
+            limit,
+            expiration,
+            allowed_batches,
+        } => {
+            validate_address(deps, "spender", spender)?;
+            ensure_not_empty("denom", denom)?;
+            if limit.is_zero() {
+                return Err(ContractError::InvalidField {
+                    field: "limit".to_string(),
+                    reason: "must be positive".to_string(),
+                });
+            }
+            if matches!(expiration, Some(expiration) if *expiration <= env.block.time) {
+                return Err(ContractError::InvalidField {
+                    field: "expiration".to_string(),
+                    reason: "must be in the future".to_string(),
+                });
+            }
+            if matches!(allowed_batches, Some(batches) if batches.is_empty()) {
+                return Err(ContractError::EmptyField {
+                    field: "allowed_batches".to_string(),
+                });
+            }
+            Ok(())
+        }
+        ExecuteMsg::RevokeAllowance { spender } => validate_address(deps, "spender", spender),
+        ExecuteMsg::MakeOffer {
+            quantity,
+            price,
+            expiration,
+            merkle_proof,
+            ..
+        } => {
+            for node in merkle_proof.iter().flatten() {
+                ensure_hash(0, "merkle_proof", node)?;
+            }
+            validate_offer_terms(env, *quantity, price, *expiration)
+        }
+        ExecuteMsg::CounterOffer {
+            quantity,
+            price,
+            expiration,
+            ..
+        } => validate_offer_terms(env, *quantity, price, *expiration),
+        ExecuteMsg::AcceptOffer { .. } | ExecuteMsg::RejectOffer { .. } => Ok(()),
+        ExecuteMsg::PlaceCollectionBid {
+            criteria,
+            quantity,
+            price,
+            expiration,
+            ..
+        } => {
+            validate_collection_bid_criteria(criteria)?;
+            validate_offer_terms(env, *quantity, price, *expiration)
+        }
+        ExecuteMsg::CancelCollectionBid { .. } => Ok(()),
+        ExecuteMsg::FillCollectionBid {
+            sell_order_id,
+            batch_denom,
+            quantity,
+            ..
+        } => {
+            match (sell_order_id, batch_denom) {
+                (Some(_), None) => {}
+                (None, Some(batch_denom)) => ensure_not_empty("batch_denom", batch_denom)?,
+                _ => {
+                    return Err(ContractError::InvalidField {
+                        field: "sell_order_id".to_string(),
+                        reason: "or batch_denom must be set, but not both".to_string(),
+                    })
+                }
+            }
+            ensure_positive(0, "quantity", *quantity)
+        }
+        ExecuteMsg::SellBundle {
+            items,
+            price,
+            expiration,
+        } => {
+            validate_bundle_items(items)?;
+            validate_price(0, "price", price)?;
+            ensure_future(env, 0, "expiration", *expiration)
+        }
+        ExecuteMsg::CancelBundle { .. } => Ok(()),
+        ExecuteMsg::BuyBundle {
+            max_fee_amount,
+            retirement_options,
+            beneficiary,
+            ..
+        } => {
+            ensure_not_empty("max_fee_amount.denom", &max_fee_amount.denom)?;
+            let mut seen = HashSet::new();
+            for (index, option) in retirement_options.iter().enumerate() {
+                ensure_order_field_not_empty(index, "batch_denom", &option.batch_denom)?;
+                if !seen.insert(&option.batch_denom) {
+                    return Err(ContractError::InvalidOrderField {
+                        index,
+                        field: "batch_denom".to_string(),
+                        reason: "is repeated".to_string(),
+                    });
+                }
+            }
+            if let Some(beneficiary) = beneficiary {
+                validate_address(deps, "beneficiary", beneficiary)?;
+            }
+            Ok(())
+        }
+        // The wrapped action is validated when it is dispatched.
+        ExecuteMsg::Receive(_) | ExecuteMsg::BatchReceive(_) | ExecuteMsg::Marketplace(_) => Ok(()),
+        // Records are checked as they are imported.
+        ExecuteMsg::ImportBatch { .. } | ExecuteMsg::Finalize {} => Ok(()),
+        ExecuteMsg::Crank { limit } => {
+            if *limit == 0 || *limit > MAX_CRANK_LIMIT {
+                return Err(ContractError::InvalidField {
+                    field: "limit".to_string(),
+                    reason: format!("must be between 1 and {}", MAX_CRANK_LIMIT),
+                });
+            }
+            Ok(())
+        }
+        ExecuteMsg::GovSetCrankRewardShare { share } => validate_crank_reward_share(*share),
+        ExecuteMsg::AddHook { contract, .. } | ExecuteMsg::RemoveHook { contract, .. } => {
+            validate_address(deps, "contract", contract)
+        }
+        ExecuteMsg::AddCreditToken {
+            batch_denom, asset, ..
+        } => {
+            ensure_not_empty("batch_denom", batch_denom)?;
+            validate_credit_token(deps, asset)
+        }
+        ExecuteMsg::RemoveCreditToken { asset } => validate_credit_token(deps, asset),
+        ExecuteMsg::RetrySettlement { .. } | ExecuteMsg::RefundSettlement { .. } => Ok(()),
+        ExecuteMsg::ImportState { version, .. } => validate_state_version(*version),
+        ExecuteMsg::BuyDirectFrom { owner, orders } => {
+            validate_address(deps, "owner", owner)?;
+            validate_buy_orders(deps, orders)
+        }
+    }
+}
+
+pub fn validate_state_version(version: u32) -> Result<(), ContractError> {
+    if !(MIN_STATE_EXPORT_VERSION..=STATE_EXPORT_VERSION).contains(&version) {
+        return Err(ContractError::UnsupportedExportVersion { version });
+    }
+    Ok(())
+}
+
+pub fn validate_crank_reward_share(share: Decimal) -> Result<(), ContractError> {
+    if share > Decimal::one() {
+        return Err(ContractError::InvalidFee {
+            field: "crank_reward_share".to_string(),
+        });
+    }
+    Ok(())
+}
+
+pub fn validate_fee_params(fees: &FeeParams) -> Result<(), ContractError> {
+    if fees.buyer_percentage_fee > Decimal::one() {
+        return Err(ContractError::InvalidFee {
+            field: "buyer_percentage_fee".to_string(),
+        });
+    }
+    if fees.seller_percentage_fee > Decimal::one() {
+        return Err(ContractError::InvalidFee {
+            field: "seller_percentage_fee".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn validate_sell_orders(
+    deps: Deps,
+    env: &Env,
+    orders: &[SellOrderMsg],
+) -> Result<(), ContractError> {
+    if orders.is_empty() {
+        return Err(ContractError::EmptyField {
+            field: "orders".to_string(),
+        });
+    }
+
+    for (index, order) in orders.iter().enumerate() {
+        ensure_order_field_not_empty(index, "batch_denom", &order.batch_denom)?;
+        ensure_positive(index, "quantity", order.quantity)?;
+        validate_price(index, "ask_price", &order.ask_price)?;
+        ensure_future(env, index, "expiration", order.expiration)?;
+        match &order.allowed_buyers {
+            Some(AllowedBuyersMsg::Addresses { addresses }) => {
+                if addresses.is_empty() {
+                    return Err(ContractError::InvalidOrderField {
+                        index,
+                        field: "allowed_buyers.addresses".to_string(),
+                        reason: "must not be empty".to_string(),
+                    });
+                }
+                for address in addresses {
+                    validate_order_address(deps, index, "allowed_buyers.addresses", address)?;
+                }
+            }
+            Some(AllowedBuyersMsg::MerkleRoot { root }) => {
+                ensure_hash(index, "allowed_buyers.root", root)?
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+fn ensure_hash(index: usize, field: &str, hash: &HexBinary) -> Result<(), ContractError> {
+    if hash.len() != 32 {
+        return Err(ContractError::InvalidOrderField {
+            index,
+            field: field.to_string(),
+            reason: "must be a 32 byte sha256 hash".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn validate_updates(env: &Env, updates: &[UpdateSellOrderMsg]) -> Result<(), ContractError> {
+    if updates.is_empty() {
+        return Err(ContractError::EmptyField {
+            field: "updates".to_string(),
+        });
+    }
+
+    let mut seen = HashSet::new();
+    for (index, update) in updates.iter().enumerate() {
+        if !seen.insert(update.sell_order_id) {
+            return Err(ContractError::DuplicateSellOrder {
+                index,
+                sell_order_id: update.sell_order_id,
+            });
+        }
+        if let Some(new_quantity) = update.new_quantity {
+            ensure_positive(index, "new_quantity", new_quantity)?;
+        }
+        if let Some(new_ask_price) = &update.new_ask_price {
+            validate_price(index, "new_ask_price", new_ask_price)?;
+        }
+        ensure_future(env, index, "new_expiration", update.new_expiration)?;
+    }
+    Ok(())
+}
+
+fn validate_buy_orders(deps: Deps, orders: &[BuyOrderMsg]) -> Result<(), ContractError> {
+    if orders.is_empty() {
+        return Err(ContractError::EmptyField {
+            field: "orders".to_string(),
+        });
+    }
+
+    let mut seen = HashSet::new();
+    for (index, order) in orders.iter().enumerate() {
+        if !seen.insert(order.sell_order_id) {
+            return Err(ContractError::DuplicateSellOrder {
+                index,
+                sell_order_id: order.sell_order_id,
+            });
+        }
+        ensure_positive(index, "quantity", order.quantity)?;
+        validate_price(index, "bid_price", &order.bid_price)?;
+        if order.max_fee_amount.denom != order.bid_price.denom {
+            return Err(ContractError::InvalidOrderField {
+                index,
+                field: "max_fee_amount.denom".to_string(),
+                reason: "must match bid_price.denom".to_string(),
+            });
+        }
+        if let Some(beneficiary) = &order.beneficiary {
+            validate_order_address(deps, index, "beneficiary", beneficiary)?;
+        }
+        for node in order.merkle_proof.iter().flatten() {
+            ensure_hash(index, "merkle_proof", node)?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_signed_order(
+    deps: Deps,
+    env: &Env,
+    order: &SignedSellOrder,
+    buy: &SignedOrderBuyMsg,
+) -> Result<(), ContractError> {
+    ensure_order_field_not_empty(0, "order.batch_denom", &order.batch_denom)?;
+    ensure_positive(0, "order.quantity", order.quantity)?;
+    validate_price(0, "order.ask_price", &order.ask_price)?;
+    ensure_future(env, 0, "order.expiration", Some(order.expiration))?;
+    ensure_positive(0, "buy.quantity", buy.quantity)?;
+    validate_price(0, "buy.bid_price", &buy.bid_price)?;
+    if buy.max_fee_amount.denom != buy.bid_price.denom {
+        return Err(ContractError::InvalidOrderField {
+            index: 0,
+            field: "buy.max_fee_amount.denom".to_string(),
+            reason: "must match bid_price.denom".to_string(),
+        });
+    }
+    if let Some(beneficiary) = &buy.beneficiary {
+        validate_order_address(deps, 0, "buy.beneficiary", beneficiary)?;
+    }
+    Ok(())
+}
+
+fn validate_address(deps: Deps, field: &str, address: &str) -> Result<(), ContractError> {
+    deps.api
+        .addr_validate(address)
+        .map_err(|_| ContractError::InvalidField {
+            field: field.to_string(),
+            reason: "must be a valid address".to_string(),
+        })?;
+    Ok(())
+}
+
+fn validate_order_address(
+    deps: Deps,
+    index: usize,
+    field: &str,
+    address: &str,
+) -> Result<(), ContractError> {
+    deps.api
+        .addr_validate(address)
+        .map_err(|_| ContractError::InvalidOrderField {
+            index,
+            field: field.to_string(),
+            reason: "must be a valid address".to_string(),
+        })?;
+    Ok(())
+}
+
+fn validate_offer_terms(
+    env: &Env,
+    quantity: Uint128,
+    price: &Coin,
+    expiration: Option<Timestamp>,
+) -> Result<(), ContractError> {
+    ensure_positive(0, "quantity", quantity)?;
+    validate_price(0, "price", price)?;
+    ensure_future(env, 0, "expiration", expiration)
+}
+
+fn validate_collection_bid_criteria(criteria: &CollectionBidCriteria) -> Result<(), ContractError> {
+    let lists = [
+        ("criteria.class_ids", &criteria.class_ids),
+        ("criteria.project_ids", &criteria.project_ids),
+        ("criteria.jurisdictions", &criteria.jurisdictions),
+    ];
+    for (field, list) in lists {
+        if matches!(list, Some(list) if list.is_empty()) {
+            return Err(ContractError::EmptyField {
+                field: field.to_string(),
+            });
+        }
+    }
+    if let (Some(min), Some(max)) = (criteria.min_vintage_year, criteria.max_vintage_year) {
+        if min > max {
+            return Err(ContractError::InvalidField {
+                field: "criteria.min_vintage_year".to_string(),
+                reason: "must not exceed max_vintage_year".to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn validate_bundle_items(items: &[BundleItem]) -> Result<(), ContractError> {
+    if items.is_empty() {
+        return Err(ContractError::EmptyField {
+            field: "items".to_string(),
+        });
+    }
+
+    let mut seen = HashSet::new();
+    for (index, item) in items.iter().enumerate() {
+        ensure_order_field_not_empty(index, "batch_denom", &item.batch_denom)?;
+        ensure_positive(index, "quantity", item.quantity)?;
+        if !seen.insert(&item.batch_denom) {
+            return Err(ContractError::InvalidOrderField {
+                index,
+                field: "batch_denom".to_string(),
+                reason: "is repeated".to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn validate_coins(coins: &[Coin]) -> Result<(), ContractError> {
+    if coins.is_empty() {
+        return Err(ContractError::EmptyField {
+            field: "coins".to_string(),
+        });
+    }
+
+    for (index, coin) in coins.iter().enumerate() {
+        ensure_order_field_not_empty(index, "denom", &coin.denom)?;
+        ensure_positive(index, "amount", coin.amount)?;
+    }
+    Ok(())
+}
+
+fn validate_pause_scope(scope: &PauseScope) -> Result<(), ContractError> {
+    match scope {
+        PauseScope::Batch { batch_denom } => ensure_not_empty("batch_denom", batch_denom),
+        _ => Ok(()),
+    }
+}
+
+fn validate_price(index: usize, field: &str, price: &Coin) -> Result<(), ContractError> {
+    ensure_order_field_not_empty(index, &format!("{}.denom", field), &price.denom)?;
+    ensure_positive(index, &format!("{}.amount", field), price.amount)
+}
+
+fn validate_credit_token(deps: Deps, asset: &CreditAssetMsg) -> Result<(), ContractError> {
+    match asset {
+        CreditAssetMsg::Native => Err(ContractError::InvalidField {
+            field: "asset".to_string(),
+            reason: "must be a cw20 or cw1155 token".to_string(),
+        }),
+        CreditAssetMsg::Cw20 { contract } => validate_address(deps, "asset.contract", contract),
+        CreditAssetMsg::Cw1155 { contract, token_id } => {
+            validate_address(deps, "asset.contract", contract)?;
+            ensure_not_empty("asset.token_id", token_id)
+        }
+    }
+}
+
+fn ensure_not_empty(field: &str, value: &str) -> Result<(), ContractError> {
+    if value.trim().is_empty() {
+        return Err(ContractError::EmptyField {
+            field: field.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn ensure_order_field_not_empty(
+    index: usize,
+    field: &str,
+    value: &str,
+) -> Result<(), ContractError> {
+    if value.trim().is_empty() {
+        return Err(ContractError::InvalidOrderField {
+            index,
+            field: field.to_string(),
+            reason: "must not be empty".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn ensure_positive(index: usize, field: &str, amount: Uint128) -> Result<(), ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::InvalidOrderField {
+            index,
+            field: field.to_string(),
+            reason: "must be positive".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn ensure_future(
+    env: &Env,
+    index: usize,
+    field: &str,
+    expiration: Option<Timestamp>,
+) -> Result<(), ContractError> {
+    match expiration {
+        Some(expiration) if expiration <= env.block.time => Err(ContractError::InvalidOrderField {
+            index,
+            field: field.to_string(),
+            reason: "must be in the future".to_string(),
+        }),
+        _ => Ok(()),
+    }
+}